@@ -0,0 +1,126 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kraken_rs::resp::{Resp, RespRef};
+
+const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"h":["5783.00000","5783.00000"],"l":["5505.00000","5505.00000"],"o":["5760.70000","5763.40000"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"v":["2634.11501494","3591.17907851"]},"ticker","XBT/USD"]"#;
+const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70001","3586.70000","3586.60001","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+const HEARTBEAT: &str = r#"{"event":"heartbeat"}"#;
+const SUBSCRIPTION_STATUS: &str = r#"{"channelID":10001,"channelName":"ohlc-5","event":"subscriptionStatus","pair":"XBT/EUR","status":"subscribed","subscription":{"interval":5,"name":"ohlc"}}"#;
+
+/// The baseline, responses parsed the way they were before
+/// dispatching on the channel name: each shape tried in turn by
+/// an untagged enum, down to every element of the arrays. The old
+/// parser then copied these parts into its public types, which is
+/// left out here, so if anything the baseline is flattered. The
+/// types are laid out as they were, unboxed. It only knew
+/// tickers and OHLC, events failed to parse.
+#[allow(dead_code, clippy::large_enum_variant)]
+mod untagged {
+    use serde_derive::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum Resp {
+        Ticker([TickerPart; 4]),
+        Ohlc([OhlcPart; 4]),
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum IntOrDecimal {
+        Int(u64),
+        Dec(String),
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum TickerPart {
+        UInt(u32),
+        Data(TickerData),
+        Str(String),
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct TickerData {
+        pub a: [IntOrDecimal; 3],
+        pub b: [IntOrDecimal; 3],
+        pub c: [String; 2],
+        pub v: [String; 2],
+        pub p: [String; 2],
+        pub t: [u32; 2],
+        pub l: [String; 2],
+        pub h: [String; 2],
+        pub o: [String; 2],
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum OhlcPart {
+        UInt(u32),
+        Data([IntOrDecimal; 9]),
+        Str(String),
+    }
+}
+
+fn parse_single(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(1));
+    for (name, msg) in &[("ticker", TICKER), ("ohlc", OHLC)] {
+        group.bench_with_input(BenchmarkId::new("untagged", name), msg, |b, msg| {
+            b.iter(|| serde_json::from_str::<untagged::Resp>(black_box(msg)).unwrap())
+        });
+    }
+    for (name, msg) in &[
+        ("ticker", TICKER),
+        ("ohlc", OHLC),
+        ("heartbeat", HEARTBEAT),
+        ("subscriptionStatus", SUBSCRIPTION_STATUS),
+    ] {
+        group.bench_with_input(BenchmarkId::new("owned", name), msg, |b, msg| {
+            b.iter(|| serde_json::from_str::<Resp>(black_box(msg)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", name), msg, |b, msg| {
+            b.iter(|| serde_json::from_str::<RespRef>(black_box(msg)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn parse_stream(c: &mut Criterion) {
+    // Roughly what a busy connection looks like, mostly market
+    // data with the odd heartbeat in between.
+    let stream: Vec<&str> = (0..1000)
+        .map(|i| match i % 10 {
+            0 => HEARTBEAT,
+            1..=4 => TICKER,
+            _ => OHLC,
+        })
+        .collect();
+    let mut group = c.benchmark_group("parse_stream");
+    group.throughput(Throughput::Elements(stream.len() as u64));
+    group.bench_function("untagged", |b| {
+        b.iter(|| {
+            // Heartbeats fail, as they did, and are skipped.
+            for msg in &stream {
+                black_box(serde_json::from_str::<untagged::Resp>(msg).ok());
+            }
+        })
+    });
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            for msg in &stream {
+                black_box(serde_json::from_str::<Resp>(msg).unwrap());
+            }
+        })
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for msg in &stream {
+                black_box(serde_json::from_str::<RespRef>(msg).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse_single, parse_stream);
+criterion_main!(benches);
@@ -0,0 +1,332 @@
+use crate::req::BookDepth;
+use crate::resp::book::{Book, PriceLevel};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Levels on each side covered by Kraken's book checksum.
+const CHECKSUM_LEVELS: usize = 10;
+
+/// The best bid and ask in a book, either side may be
+/// missing while the book for that side is empty.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct BookTop {
+    pub bid: Option<PriceLevel>,
+    pub ask: Option<PriceLevel>,
+}
+
+/// Returned by [`OrderBook::apply`] when a book no longer
+/// matches the checksum Kraken sent with an update. The book is
+/// cleared and ignores updates until the next snapshot, which
+/// resubscribing to the book channel gets.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct OutOfSync {
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for OutOfSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Book checksum {} doesn't match Kraken's {}, resubscribe to resync",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for OutOfSync {}
+
+/// A local copy of the order book for one pair built from
+/// the snapshot and updates on the book channel. Levels are
+/// keyed by their parsed price so that the differently
+/// formatted prices Kraken sends compare correctly.
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    depth: BookDepth,
+    asks: BTreeMap<Decimal, PriceLevel>,
+    bids: BTreeMap<Reverse<Decimal>, PriceLevel>,
+    // Cleared when a checksum fails until the next snapshot.
+    synced: bool,
+}
+
+impl OrderBook {
+    pub fn new(depth: BookDepth) -> OrderBook {
+        OrderBook {
+            depth,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            synced: true,
+        }
+    }
+
+    pub fn depth(&self) -> BookDepth {
+        self.depth
+    }
+
+    /// Whether the book has matched every checksum since the
+    /// last snapshot.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Apply a snapshot, replacing the whole book, or an update.
+    /// Levels with zero volume are removed and any levels past
+    /// the subscribed depth are dropped, as Kraken expects. The
+    /// book is then checked against the update's checksum, a
+    /// mismatch is an [`OutOfSync`] error.
+    pub fn apply<S: AsRef<str>>(&mut self, book: &Book<S>) -> Result<()> {
+        if book.snapshot {
+            self.asks.clear();
+            self.bids.clear();
+            self.synced = true;
+        } else if !self.synced {
+            return Ok(());
+        }
+        for level in &book.asks {
+            let (price, level) = parse_level(level)?;
+            update(&mut self.asks, price, level);
+        }
+        for level in &book.bids {
+            let (price, level) = parse_level(level)?;
+            update(&mut self.bids, Reverse(price), level);
+        }
+        let depth = self.depth.levels() as usize;
+        truncate(&mut self.asks, depth);
+        truncate(&mut self.bids, depth);
+        match book.checksum {
+            Some(expected) if expected != self.checksum() => {
+                let actual = self.checksum();
+                self.asks.clear();
+                self.bids.clear();
+                self.synced = false;
+                Err(OutOfSync { expected, actual }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Kraken's CRC32 of the top ten levels each side, asks then
+    /// bids, their prices and volumes as sent without the
+    /// decimal point and leading zeros.
+    pub fn checksum(&self) -> u32 {
+        let mut text = String::new();
+        for level in self
+            .asks()
+            .take(CHECKSUM_LEVELS)
+            .chain(self.bids().take(CHECKSUM_LEVELS))
+        {
+            for value in [&level.price, &level.volume] {
+                let digits = value.replace('.', "");
+                text.push_str(digits.trim_start_matches('0'));
+            }
+        }
+        crc32(text.as_bytes())
+    }
+
+    /// Asks from the lowest price up.
+    pub fn asks(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.asks.values()
+    }
+
+    /// Bids from the highest price down.
+    pub fn bids(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.bids.values()
+    }
+
+    pub fn top(&self) -> BookTop {
+        BookTop {
+            bid: self.bids().next().cloned(),
+            ask: self.asks().next().cloned(),
+        }
+    }
+}
+
+// Parse the price, returning the level with no volume as
+// None since it should be removed from the book.
+fn parse_level<S: AsRef<str>>(level: &PriceLevel<S>) -> Result<(Decimal, Option<PriceLevel>)> {
+    let dec = |s: &str, field: &str| {
+        Decimal::from_str(s).map_err(|e| anyhow!("Invalid {} {}: {}", field, s, e))
+    };
+    let price = dec(level.price.as_ref(), "price")?;
+    if dec(level.volume.as_ref(), "volume")?.is_zero() {
+        return Ok((price, None));
+    }
+    let level = PriceLevel {
+        price: level.price.as_ref().to_owned(),
+        volume: level.volume.as_ref().to_owned(),
+        time: level.time,
+        republish: level.republish,
+    };
+    Ok((price, Some(level)))
+}
+
+fn update<K: Ord>(side: &mut BTreeMap<K, PriceLevel>, price: K, level: Option<PriceLevel>) {
+    match level {
+        Some(level) => side.insert(price, level),
+        None => side.remove(&price),
+    };
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn truncate<K: Ord + Clone>(side: &mut BTreeMap<K, PriceLevel>, depth: usize) {
+    if let Some(worst) = side.keys().nth(depth).cloned() {
+        side.split_off(&worst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time::Timestamp;
+
+    fn level(price: &'static str, volume: &'static str) -> PriceLevel<&'static str> {
+        PriceLevel {
+            price,
+            volume,
+            time: Timestamp::from_secs(1534614248),
+            republish: false,
+        }
+    }
+
+    fn book(
+        snapshot: bool,
+        asks: Vec<PriceLevel<&'static str>>,
+        bids: Vec<PriceLevel<&'static str>>,
+    ) -> Book<&'static str> {
+        Book {
+            channel_id: 0,
+            pair: "XBT/USD",
+            depth: BookDepth::N10,
+            snapshot,
+            asks,
+            bids,
+            checksum: None,
+        }
+    }
+
+    fn prices<'a>(levels: impl Iterator<Item = &'a PriceLevel>) -> Vec<&'a str> {
+        levels.map(|l| l.price.as_str()).collect()
+    }
+
+    #[test]
+    fn snapshot_orders_levels() -> Result<()> {
+        let mut order_book = OrderBook::new(BookDepth::N10);
+        order_book.apply(&book(
+            true,
+            vec![level("5541.8", "0.33"), level("5541.3", "2.5")],
+            vec![level("5540.1", "1"), level("5541.2", "1.5")],
+        ))?;
+        assert_eq!(vec!["5541.3", "5541.8"], prices(order_book.asks()));
+        assert_eq!(vec!["5541.2", "5540.1"], prices(order_book.bids()));
+        let top = order_book.top();
+        assert_eq!("5541.2", top.bid.unwrap().price);
+        assert_eq!("5541.3", top.ask.unwrap().price);
+        Ok(())
+    }
+
+    #[test]
+    fn updates_replace_and_remove_levels() -> Result<()> {
+        let mut order_book = OrderBook::new(BookDepth::N10);
+        order_book.apply(&book(
+            true,
+            vec![level("5541.3", "2.5"), level("5541.8", "0.33")],
+            vec![level("5541.2", "1.5")],
+        ))?;
+        order_book.apply(&book(
+            false,
+            vec![level("5541.30000", "0.00000000"), level("5541.8", "1")],
+            vec![level("5541.25", "3")],
+        ))?;
+        assert_eq!(vec!["5541.8"], prices(order_book.asks()));
+        assert_eq!("1", order_book.asks().next().unwrap().volume);
+        assert_eq!(vec!["5541.25", "5541.2"], prices(order_book.bids()));
+
+        order_book.apply(&book(true, vec![level("6000", "1")], vec![]))?;
+        assert_eq!(vec!["6000"], prices(order_book.asks()));
+        assert_eq!(None, order_book.top().bid);
+        Ok(())
+    }
+
+    #[test]
+    fn truncates_to_depth() -> Result<()> {
+        let mut order_book = OrderBook::new(BookDepth::N10);
+        let asks = (0..12)
+            .map(|i| PriceLevel {
+                price: (100 + i).to_string(),
+                volume: "1".to_string(),
+                time: Timestamp::from_secs(1534614248),
+                republish: false,
+            })
+            .collect();
+        order_book.apply(&Book {
+            channel_id: 0,
+            pair: "XBT/USD".to_string(),
+            depth: BookDepth::N10,
+            snapshot: true,
+            asks,
+            bids: vec![],
+            checksum: None,
+        })?;
+        assert_eq!(10, order_book.asks().count());
+        assert_eq!("109", order_book.asks().last().unwrap().price);
+        Ok(())
+    }
+
+    #[test]
+    fn checks_checksums() -> Result<()> {
+        let mut order_book = OrderBook::new(BookDepth::N10);
+        order_book.apply(&book(
+            true,
+            vec![level("5541.30000", "2.50700000")],
+            vec![level("5541.20000", "1.52900000")],
+        ))?;
+        assert_eq!(4055273613, order_book.checksum());
+        let update = |bids, checksum| Book {
+            checksum: Some(checksum),
+            ..book(false, vec![], bids)
+        };
+        order_book.apply(&update(vec![level("5541.20000", "0.00000000")], 425429334))?;
+
+        // Out of sync until the next snapshot.
+        let err = order_book
+            .apply(&update(vec![level("5541.10000", "1.00000000")], 425429334))
+            .unwrap_err();
+        assert_eq!(425429334, err.downcast_ref::<OutOfSync>().unwrap().expected);
+        assert!(!order_book.is_synced());
+        assert_eq!(None, order_book.top().ask);
+        order_book.apply(&book(
+            false,
+            vec![level("5541.30000", "1.00000000")],
+            vec![],
+        ))?;
+        assert_eq!(0, order_book.asks().count());
+        order_book.apply(&book(true, vec![level("5541.30000", "1.00000000")], vec![]))?;
+        assert!(order_book.is_synced());
+        assert_eq!(1, order_book.asks().count());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_price() {
+        let mut order_book = OrderBook::new(BookDepth::N10);
+        let err = order_book
+            .apply(&book(false, vec![level("abc", "1")], vec![]))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid price abc"));
+    }
+}
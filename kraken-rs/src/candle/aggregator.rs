@@ -0,0 +1,186 @@
+use crate::req::OhlcInterval;
+use crate::resp::ohlc::Ohlc;
+use crate::time::Timestamp;
+use std::collections::HashMap;
+
+/// Emitted exactly once for each candle when it can no
+/// longer change, carrying the last update Kraken sent.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CandleClosed {
+    pub candle: Ohlc,
+}
+
+/// Tracks the in progress candle for each pair and interval
+/// from the repeated updates on the ohlc channel. A candle is
+/// closed either when an update for a later candle arrives or
+/// when its end time passes, whichever is first. Kraken only
+/// sends updates when there are trades so a candle with no
+/// trades is never seen and so never closed.
+#[derive(Debug, Clone, Default)]
+pub struct CandleAggregator {
+    slots: HashMap<(String, OhlcInterval), Slot>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Slot {
+    open: Option<Ohlc>,
+    // End time of the last candle closed, updates for
+    // this candle or any before it are stale.
+    closed: Option<Timestamp>,
+}
+
+impl CandleAggregator {
+    pub fn new() -> CandleAggregator {
+        Default::default()
+    }
+
+    /// Record an update, returning the previous candle for the
+    /// same pair and interval if this update starts a new one.
+    pub fn update(&mut self, ohlc: Ohlc) -> Option<CandleClosed> {
+        let slot = self
+            .slots
+            .entry((ohlc.pair.clone(), ohlc.interval))
+            .or_default();
+        if slot.closed.is_some_and(|etime| ohlc.etime <= etime) {
+            return None;
+        }
+        match slot.open.take() {
+            Some(open) if open.etime < ohlc.etime => {
+                slot.closed = Some(open.etime);
+                slot.open = Some(ohlc);
+                Some(CandleClosed { candle: open })
+            }
+            Some(open) if ohlc.etime < open.etime => {
+                slot.open = Some(open);
+                None
+            }
+            _ => {
+                slot.open = Some(ohlc);
+                None
+            }
+        }
+    }
+
+    /// Close every candle whose end time is at or before the
+    /// given time. Kraken's clock and ours will differ a little
+    /// so callers may want to pass a time slightly in the past.
+    pub fn close_expired(&mut self, now: Timestamp) -> Vec<CandleClosed> {
+        let mut closed = vec![];
+        for slot in self.slots.values_mut() {
+            if slot.open.as_ref().is_some_and(|open| open.etime <= now) {
+                let candle = slot.open.take().unwrap();
+                slot.closed = Some(candle.etime);
+                closed.push(CandleClosed { candle });
+            }
+        }
+        closed.sort_by_key(|c| c.candle.etime);
+        closed
+    }
+
+    /// The in progress candle for the given pair and interval.
+    pub fn current(&self, pair: &str, interval: OhlcInterval) -> Option<&Ohlc> {
+        self.slots
+            .get(&(pair.to_owned(), interval))
+            .and_then(|slot| slot.open.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ohlc(pair: &str, etime: u64, close: &str) -> Ohlc {
+        Ohlc {
+            channel_id: 42,
+            interval: OhlcInterval::Mins1,
+            pair: pair.to_string(),
+            time: Timestamp::from_secs(etime - 30),
+            etime: Timestamp::from_secs(etime),
+            open: "100.0".to_string(),
+            high: "110.0".to_string(),
+            low: "90.0".to_string(),
+            close: close.to_string(),
+            vwap: "100.0".to_string(),
+            volume: "1.0".to_string(),
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn in_progress_updates_do_not_close() {
+        let mut aggregator = CandleAggregator::new();
+        assert_eq!(None, aggregator.update(ohlc("XBT/USD", 60, "100.0")));
+        assert_eq!(None, aggregator.update(ohlc("XBT/USD", 60, "101.0")));
+        assert_eq!(
+            Some(&ohlc("XBT/USD", 60, "101.0")),
+            aggregator.current("XBT/USD", OhlcInterval::Mins1)
+        );
+    }
+
+    #[test]
+    fn new_candle_closes_previous_with_final_values() {
+        let mut aggregator = CandleAggregator::new();
+        aggregator.update(ohlc("XBT/USD", 60, "100.0"));
+        aggregator.update(ohlc("XBT/USD", 60, "101.0"));
+        assert_eq!(
+            Some(CandleClosed {
+                candle: ohlc("XBT/USD", 60, "101.0")
+            }),
+            aggregator.update(ohlc("XBT/USD", 120, "102.0"))
+        );
+    }
+
+    #[test]
+    fn expiry_closes_candle_once() {
+        let mut aggregator = CandleAggregator::new();
+        aggregator.update(ohlc("XBT/USD", 60, "100.0"));
+        assert!(aggregator
+            .close_expired(Timestamp::from_secs(59))
+            .is_empty());
+        assert_eq!(
+            vec![CandleClosed {
+                candle: ohlc("XBT/USD", 60, "100.0")
+            }],
+            aggregator.close_expired(Timestamp::from_secs(60))
+        );
+        assert!(aggregator
+            .close_expired(Timestamp::from_secs(61))
+            .is_empty());
+        // A late update for the closed candle must not reopen it
+        // and the next candle must not close it a second time.
+        assert_eq!(None, aggregator.update(ohlc("XBT/USD", 60, "99.0")));
+        assert_eq!(None, aggregator.update(ohlc("XBT/USD", 120, "102.0")));
+        assert!(aggregator
+            .close_expired(Timestamp::from_secs(61))
+            .is_empty());
+    }
+
+    #[test]
+    fn stale_update_is_ignored() {
+        let mut aggregator = CandleAggregator::new();
+        aggregator.update(ohlc("XBT/USD", 120, "100.0"));
+        assert_eq!(None, aggregator.update(ohlc("XBT/USD", 60, "99.0")));
+        assert_eq!(
+            Some(&ohlc("XBT/USD", 120, "100.0")),
+            aggregator.current("XBT/USD", OhlcInterval::Mins1)
+        );
+    }
+
+    #[test]
+    fn pairs_are_independent() {
+        let mut aggregator = CandleAggregator::new();
+        aggregator.update(ohlc("XBT/USD", 60, "100.0"));
+        assert_eq!(None, aggregator.update(ohlc("ETH/USD", 120, "10.0")));
+        assert_eq!(
+            vec![
+                CandleClosed {
+                    candle: ohlc("XBT/USD", 60, "100.0")
+                },
+                CandleClosed {
+                    candle: ohlc("ETH/USD", 120, "10.0")
+                }
+            ],
+            aggregator.close_expired(Timestamp::from_secs(120))
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use crate::candle::Candle;
+use crate::resp::trade::{Trade, Trades};
+use crate::time::Timestamp;
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The rule deciding when a bar built from trades is complete.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BarKind {
+    /// Bars covering a fixed period aligned to the epoch, the
+    /// same as the candles on Kraken's ohlc channel.
+    Time(Duration),
+    /// Bars of a fixed number of trades.
+    Tick(u32),
+    /// Bars closing once the traded volume reaches the threshold.
+    Volume(Decimal),
+    /// Bars closing once the traded value, the sum of price
+    /// times volume, reaches the threshold.
+    Dollar(Decimal),
+}
+
+/// Builds bars for each pair from the trade channel. Time bars
+/// close when a trade for a later period arrives or when closed
+/// explicitly once their end has passed, the other kinds close
+/// on the trade which takes them over their threshold and span
+/// the time from their first trade to their last.
+#[derive(Debug, Clone)]
+pub struct BarBuilder {
+    kind: BarKind,
+    building: HashMap<String, Bar>,
+    // End of the last time bar closed for each pair,
+    // trades before this are too late to be counted.
+    closed: HashMap<String, Timestamp>,
+}
+
+#[derive(Debug, Clone)]
+struct Bar {
+    candle: Candle,
+    value: Decimal,
+}
+
+impl BarBuilder {
+    pub fn new(kind: BarKind) -> Result<BarBuilder> {
+        let empty = match kind {
+            BarKind::Time(period) => period.as_micros() == 0,
+            BarKind::Tick(n) => n == 0,
+            BarKind::Volume(threshold) | BarKind::Dollar(threshold) => threshold <= Decimal::ZERO,
+        };
+        if empty {
+            return Err(anyhow!("Bars of {:?} would never contain a trade", kind));
+        }
+        Ok(BarBuilder {
+            kind,
+            building: HashMap::new(),
+            closed: HashMap::new(),
+        })
+    }
+
+    /// Add a batch of trades, returning the bars they complete.
+    /// Every trade is parsed before any is added, so a batch
+    /// with an invalid trade is rejected as a whole.
+    pub fn push<S>(&mut self, trades: &Trades<S>) -> Result<Vec<Candle>>
+    where
+        S: AsRef<str>,
+    {
+        let pair = trades.pair.as_ref();
+        let singles = trades
+            .trades
+            .iter()
+            .map(|trade| single(pair, trade))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(singles
+            .into_iter()
+            .filter_map(|single| self.push_single(single))
+            .collect())
+    }
+
+    fn push_single(&mut self, single: Candle) -> Option<Candle> {
+        if let BarKind::Time(period) = self.kind {
+            return self.push_timed(single, period);
+        }
+        let value = single.close * single.volume;
+        let bar = match self.building.remove(&single.pair) {
+            Some(mut bar) => {
+                bar.candle.merge(&single);
+                bar.candle.period = single.start.saturating_duration_since(bar.candle.start);
+                bar.value += value;
+                bar
+            }
+            None => Bar {
+                candle: single,
+                value,
+            },
+        };
+        let complete = match self.kind {
+            BarKind::Tick(n) => bar.candle.count >= n,
+            BarKind::Volume(threshold) => bar.candle.volume >= threshold,
+            BarKind::Dollar(threshold) => bar.value >= threshold,
+            BarKind::Time(_) => false,
+        };
+        if complete {
+            Some(bar.candle)
+        } else {
+            self.building.insert(bar.candle.pair.clone(), bar);
+            None
+        }
+    }
+
+    fn push_timed(&mut self, single: Candle, period: Duration) -> Option<Candle> {
+        let start = single.start.truncate(period);
+        if self
+            .closed
+            .get(&single.pair)
+            .is_some_and(|&end| start < end)
+        {
+            return None;
+        }
+        match self.building.get_mut(&single.pair) {
+            Some(bar) if bar.candle.start == start => {
+                bar.candle.merge(&single);
+                None
+            }
+            // A late trade for a period before the one being
+            // built is dropped, as if that period had closed.
+            Some(bar) if start < bar.candle.start => None,
+            _ => {
+                let pair = single.pair.clone();
+                let bar = Bar {
+                    candle: Candle {
+                        start,
+                        period,
+                        ..single
+                    },
+                    value: Decimal::ZERO,
+                };
+                let previous = self.building.insert(pair.clone(), bar).map(|b| b.candle);
+                if let Some(previous) = &previous {
+                    self.closed.insert(pair, previous.end());
+                }
+                previous
+            }
+        }
+    }
+
+    /// Close every time bar whose end is at or before the given
+    /// time, this does nothing for the other kinds of bar.
+    pub fn close_expired(&mut self, now: Timestamp) -> Vec<Candle> {
+        let expired: Vec<String> = self
+            .building
+            .iter()
+            .filter(|(_, bar)| matches!(self.kind, BarKind::Time(_)) && bar.candle.end() <= now)
+            .map(|(pair, _)| pair.clone())
+            .collect();
+        let mut closed: Vec<Candle> = expired
+            .into_iter()
+            .filter_map(|pair| {
+                let bar = self.building.remove(&pair)?;
+                self.closed.insert(pair, bar.candle.end());
+                Some(bar.candle)
+            })
+            .collect();
+        closed.sort_by_key(|c| c.start);
+        closed
+    }
+}
+
+/// A trade as a candle lasting no time at all.
+fn single<S>(pair: &str, trade: &Trade<S>) -> Result<Candle>
+where
+    S: AsRef<str>,
+{
+    let price = Decimal::from_str(trade.price.as_ref())
+        .map_err(|e| anyhow!("Invalid trade price {}: {}", trade.price.as_ref(), e))?;
+    let volume = Decimal::from_str(trade.volume.as_ref())
+        .map_err(|e| anyhow!("Invalid trade volume {}: {}", trade.volume.as_ref(), e))?;
+    Ok(Candle {
+        pair: pair.to_owned(),
+        start: trade.time,
+        period: Duration::from_secs(0),
+        open: price,
+        high: price,
+        low: price,
+        close: price,
+        vwap: price,
+        volume,
+        count: 1,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::trade::{OrderType, Side};
+
+    fn trades(trades: &[(&'static str, &'static str, u64)]) -> Trades<&'static str> {
+        Trades {
+            channel_id: 0,
+            pair: "XBT/USD",
+            trades: trades
+                .iter()
+                .map(|&(price, volume, secs)| Trade {
+                    price,
+                    volume,
+                    time: Timestamp::from_secs(secs),
+                    side: Side::Buy,
+                    order_type: OrderType::Market,
+                    misc: "",
+                })
+                .collect(),
+        }
+    }
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn time_bars() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Time(Duration::from_secs(60)))?;
+        assert!(builder
+            .push(&trades(&[("10", "1", 5), ("12", "3", 30)]))?
+            .is_empty());
+        let completed = builder.push(&trades(&[("11", "1", 65)]))?;
+        assert_eq!(
+            vec![Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(0),
+                period: Duration::from_secs(60),
+                open: dec("10"),
+                high: dec("12"),
+                low: dec("10"),
+                close: dec("12"),
+                vwap: dec("11.5"),
+                volume: dec("4"),
+                count: 2
+            }],
+            completed
+        );
+        assert!(builder.close_expired(Timestamp::from_secs(119)).is_empty());
+        let expired = builder.close_expired(Timestamp::from_secs(120));
+        assert_eq!(1, expired.len());
+        assert_eq!(Timestamp::from_secs(60), expired[0].start);
+        // Late trades for a closed bar are dropped
+        assert!(builder.push(&trades(&[("9", "1", 10)]))?.is_empty());
+        assert!(builder.close_expired(Timestamp::from_secs(1000)).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn late_trades_are_dropped() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Time(Duration::from_secs(60)))?;
+        assert!(builder
+            .push(&trades(&[("10", "1", 65), ("9", "1", 5), ("12", "1", 70)]))?
+            .is_empty());
+        let completed = builder.push(&trades(&[("11", "1", 125)]))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(Timestamp::from_secs(60), completed[0].start);
+        assert_eq!(
+            (dec("10"), dec("12")),
+            (completed[0].open, completed[0].close)
+        );
+        assert_eq!(dec("2"), completed[0].volume);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_batch_is_rejected_whole() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Tick(2))?;
+        assert!(builder
+            .push(&trades(&[("10", "1", 5), ("twelve", "1", 30)]))
+            .is_err());
+        let completed = builder.push(&trades(&[("12", "1", 30), ("8", "1", 40)]))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(Timestamp::from_secs(30), completed[0].start);
+        Ok(())
+    }
+
+    #[test]
+    fn tick_bars() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Tick(2))?;
+        let completed =
+            builder.push(&trades(&[("10", "1", 5), ("12", "1", 30), ("8", "1", 40)]))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(Timestamp::from_secs(5), completed[0].start);
+        assert_eq!(Duration::from_secs(25), completed[0].period);
+        assert_eq!(dec("11"), completed[0].vwap);
+        Ok(())
+    }
+
+    #[test]
+    fn volume_bars() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Volume(dec("2.5")))?;
+        let completed = builder.push(&trades(&[
+            ("10", "1", 5),
+            ("12", "1", 30),
+            ("8", "1", 40),
+            ("8", "0.1", 50),
+        ]))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(dec("3"), completed[0].volume);
+        assert_eq!(dec("8"), completed[0].close);
+        Ok(())
+    }
+
+    #[test]
+    fn dollar_bars() -> Result<()> {
+        let mut builder = BarBuilder::new(BarKind::Dollar(dec("100")))?;
+        let completed = builder.push(&trades(&[
+            ("10", "5", 5),
+            ("20", "2", 30),
+            ("20", "1", 40),
+            ("10", "5", 50),
+        ]))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(3, completed[0].count);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_bars_rejected() {
+        assert!(BarBuilder::new(BarKind::Tick(0)).is_err());
+        assert!(BarBuilder::new(BarKind::Volume(Decimal::ZERO)).is_err());
+        assert!(BarBuilder::new(BarKind::Time(Duration::from_secs(0))).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::candle::Candle;
+use crate::time::Timestamp;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A value which differs between a candle we built ourselves
+/// and the candle Kraken sent for the same pair and period.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Discrepancy {
+    pub field: &'static str,
+    pub ours: Decimal,
+    pub theirs: Decimal,
+}
+
+/// Compare two candles for the same pair and period. Kraken
+/// rounds its vwap so that is compared within a tolerance, every
+/// other value should match exactly if no trades were missed.
+pub fn compare(ours: &Candle, theirs: &Candle, vwap_tolerance: Decimal) -> Vec<Discrepancy> {
+    let mut discrepancies = vec![];
+    let mut check = |field: &'static str, ours: Decimal, theirs: Decimal, tolerance: Decimal| {
+        if (ours - theirs).abs() > tolerance {
+            discrepancies.push(Discrepancy {
+                field,
+                ours,
+                theirs,
+            })
+        }
+    };
+    check("open", ours.open, theirs.open, Decimal::ZERO);
+    check("high", ours.high, theirs.high, Decimal::ZERO);
+    check("low", ours.low, theirs.low, Decimal::ZERO);
+    check("close", ours.close, theirs.close, Decimal::ZERO);
+    check("vwap", ours.vwap, theirs.vwap, vwap_tolerance);
+    check("volume", ours.volume, theirs.volume, Decimal::ZERO);
+    check(
+        "count",
+        Decimal::from(ours.count),
+        Decimal::from(theirs.count),
+        Decimal::ZERO,
+    );
+    discrepancies
+}
+
+/// Matches up time bars built from the trade channel with the
+/// closed candles from Kraken's ohlc channel and compares each
+/// pair of candles as soon as both have arrived.
+#[derive(Debug, Clone)]
+pub struct ConsistencyCheck {
+    vwap_tolerance: Decimal,
+    ours: HashMap<Key, Candle>,
+    theirs: HashMap<Key, Candle>,
+}
+
+type Key = (String, Timestamp, Duration);
+
+fn key(candle: &Candle) -> Key {
+    (candle.pair.clone(), candle.start, candle.period)
+}
+
+impl ConsistencyCheck {
+    pub fn new(vwap_tolerance: Decimal) -> ConsistencyCheck {
+        ConsistencyCheck {
+            vwap_tolerance,
+            ours: HashMap::new(),
+            theirs: HashMap::new(),
+        }
+    }
+
+    /// Add a bar we built, returning the comparison if
+    /// Kraken's candle for the same period has arrived.
+    pub fn add_ours(&mut self, candle: Candle) -> Option<Vec<Discrepancy>> {
+        match self.theirs.remove(&key(&candle)) {
+            Some(theirs) => Some(compare(&candle, &theirs, self.vwap_tolerance)),
+            None => {
+                self.ours.insert(key(&candle), candle);
+                None
+            }
+        }
+    }
+
+    /// Add a candle from Kraken, returning the comparison if
+    /// our bar for the same period has been built.
+    pub fn add_theirs(&mut self, candle: Candle) -> Option<Vec<Discrepancy>> {
+        match self.ours.remove(&key(&candle)) {
+            Some(ours) => Some(compare(&ours, &candle, self.vwap_tolerance)),
+            None => {
+                self.theirs.insert(key(&candle), candle);
+                None
+            }
+        }
+    }
+
+    /// Drop unmatched candles starting before the given time,
+    /// returning how many were dropped. Kraken sends no candle
+    /// for a period without trades and we build no bar for a
+    /// period whose trades we missed so these do accumulate.
+    pub fn prune_before(&mut self, time: Timestamp) -> usize {
+        let before = self.ours.len() + self.theirs.len();
+        self.ours.retain(|k, _| k.1 >= time);
+        self.theirs.retain(|k, _| k.1 >= time);
+        before - self.ours.len() - self.theirs.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn candle(close: &str, vwap: &str, count: u32) -> Candle {
+        Candle {
+            pair: "XBT/USD".to_string(),
+            start: Timestamp::from_secs(60),
+            period: Duration::from_secs(60),
+            open: Decimal::from_str("100.0").unwrap(),
+            high: Decimal::from_str("110.0").unwrap(),
+            low: Decimal::from_str("90.0").unwrap(),
+            close: Decimal::from_str(close).unwrap(),
+            vwap: Decimal::from_str(vwap).unwrap(),
+            volume: Decimal::from_str("1.5").unwrap(),
+            count,
+        }
+    }
+
+    #[test]
+    fn matching_candles() {
+        let mut check = ConsistencyCheck::new(Decimal::from_str("0.0001").unwrap());
+        assert_eq!(None, check.add_ours(candle("101.0", "100.333333", 3)));
+        assert_eq!(
+            Some(vec![]),
+            check.add_theirs(candle("101.00000", "100.33330", 3))
+        );
+    }
+
+    #[test]
+    fn missed_trade() {
+        let mut check = ConsistencyCheck::new(Decimal::from_str("0.0001").unwrap());
+        assert_eq!(None, check.add_theirs(candle("101.0", "100.5", 3)));
+        assert_eq!(
+            Some(vec![
+                Discrepancy {
+                    field: "close",
+                    ours: Decimal::from_str("102.0").unwrap(),
+                    theirs: Decimal::from_str("101.0").unwrap()
+                },
+                Discrepancy {
+                    field: "count",
+                    ours: Decimal::from(2),
+                    theirs: Decimal::from(3)
+                }
+            ]),
+            check.add_ours(candle("102.0", "100.5", 2))
+        );
+    }
+
+    #[test]
+    fn prune() {
+        let mut check = ConsistencyCheck::new(Decimal::ZERO);
+        check.add_ours(candle("101.0", "100.5", 3));
+        assert_eq!(0, check.prune_before(Timestamp::from_secs(60)));
+        assert_eq!(1, check.prune_before(Timestamp::from_secs(61)));
+        assert_eq!(None, check.add_theirs(candle("101.0", "100.5", 3)));
+    }
+}
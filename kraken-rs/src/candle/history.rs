@@ -0,0 +1,283 @@
+use crate::candle::{Candle, CandleClosed};
+use crate::req::OhlcInterval;
+use crate::rest::KrakenRest;
+use crate::time::Timestamp;
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tracing::warn;
+
+/// The most flat candles made up for one gap, a day of one
+/// minute candles. Longer gaps only get the candles fetched.
+const MAX_FLAT: u64 = 1440;
+
+/// Somewhere to fetch closed candles from when filling gaps.
+pub trait CandleSource {
+    /// Candles for the pair and interval starting after the
+    /// given time, in any order and possibly including the
+    /// candle which is still in progress.
+    fn candles_since(
+        &self,
+        pair: &str,
+        interval: OhlcInterval,
+        since: Timestamp,
+    ) -> Result<Vec<Candle>>;
+}
+
+impl CandleSource for KrakenRest {
+    fn candles_since(
+        &self,
+        pair: &str,
+        interval: OhlcInterval,
+        since: Timestamp,
+    ) -> Result<Vec<Candle>> {
+        self.ohlc(pair, interval, Some(since))
+    }
+}
+
+/// Turns the closed candles from the websocket, which has holes
+/// after a reconnect or restart, into a continuous series for each
+/// pair and interval. When a candle arrives which does not directly
+/// follow the previous one the missing candles are fetched from the
+/// source and emitted first. Periods the source has no candle for,
+/// because nothing traded or because they are older than the source
+/// keeps, are filled with a flat candle at the previous close,
+/// unless the gap is too long for that to be worth doing.
+#[derive(Debug, Clone)]
+pub struct CandleHistory<S> {
+    source: S,
+    last: HashMap<(String, OhlcInterval), Candle>,
+}
+
+impl<S> CandleHistory<S>
+where
+    S: CandleSource,
+{
+    pub fn new(source: S) -> CandleHistory<S> {
+        CandleHistory {
+            source,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Continue the series for a pair and interval from the given
+    /// candle, e.g. the last one stored before a restart, so that
+    /// everything missed since is backfilled on the next push.
+    pub fn resume(&mut self, interval: OhlcInterval, last: Candle) {
+        self.last.insert((last.pair.clone(), interval), last);
+    }
+
+    /// Add a closed candle returning it, preceded by any missing
+    /// candles, in order. Nothing is returned for a candle at or
+    /// before the last one. If the backfill fails the series is
+    /// left unchanged so pushing the same candle again retries.
+    pub fn push(&mut self, closed: &CandleClosed) -> Result<Vec<Candle>> {
+        let interval = closed.candle.interval;
+        let candle = Candle::try_from(&closed.candle)?;
+        let key = (candle.pair.clone(), interval);
+        let series = match self.last.get(&key) {
+            None => vec![candle],
+            Some(last) if candle.start <= last.start => vec![],
+            Some(last) if candle.start == last.end() => vec![candle],
+            Some(last) => {
+                let fetched = self
+                    .source
+                    .candles_since(&candle.pair, interval, last.start)?;
+                fill(last, fetched, candle)
+            }
+        };
+        if let Some(newest) = series.last() {
+            self.last.insert(key, newest.clone());
+        }
+        Ok(series)
+    }
+}
+
+/// The candles strictly after the last one up to and including
+/// the next, taking each from those fetched where possible.
+fn fill(last: &Candle, fetched: Vec<Candle>, next: Candle) -> Vec<Candle> {
+    let mut by_start: HashMap<Timestamp, Candle> = fetched
+        .into_iter()
+        .filter(|c| c.period == last.period)
+        .map(|c| (c.start, c))
+        .collect();
+    let periods = next.start.saturating_duration_since(last.end()).as_micros()
+        / last.period.as_micros().max(1);
+    if periods > u128::from(MAX_FLAT) {
+        warn!(pair = %last.pair, periods, "gap too long to fill with flat candles");
+        let mut series: Vec<Candle> = by_start
+            .into_values()
+            .filter(|c| c.start >= last.end() && c.start < next.start)
+            .collect();
+        series.sort_by_key(|c| c.start);
+        series.push(next);
+        return series;
+    }
+    let mut series = vec![];
+    let mut start = last.end();
+    let mut close = last.close;
+    while start < next.start {
+        let candle = by_start.remove(&start).unwrap_or_else(|| Candle {
+            pair: last.pair.clone(),
+            start,
+            period: last.period,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            vwap: close,
+            volume: Decimal::ZERO,
+            count: 0,
+        });
+        close = candle.close;
+        start = candle.end();
+        series.push(candle);
+    }
+    series.push(next);
+    series
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::ohlc::Ohlc;
+    use anyhow::anyhow;
+    use std::cell::{Cell, RefCell};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct FakeSource {
+        candles: Vec<Candle>,
+        fail: Cell<bool>,
+        calls: RefCell<Vec<Timestamp>>,
+    }
+
+    impl CandleSource for &FakeSource {
+        fn candles_since(&self, _: &str, _: OhlcInterval, since: Timestamp) -> Result<Vec<Candle>> {
+            self.calls.borrow_mut().push(since);
+            if self.fail.get() {
+                Err(anyhow!("Unavailable"))
+            } else {
+                Ok(self.candles.clone())
+            }
+        }
+    }
+
+    fn closed(etime: u64, close: &str) -> CandleClosed {
+        CandleClosed {
+            candle: Ohlc {
+                channel_id: 1,
+                interval: OhlcInterval::Mins1,
+                pair: "XBT/USD".to_string(),
+                time: Timestamp::from_secs(etime - 1),
+                etime: Timestamp::from_secs(etime),
+                open: close.to_string(),
+                high: close.to_string(),
+                low: close.to_string(),
+                close: close.to_string(),
+                vwap: close.to_string(),
+                volume: "1.0".to_string(),
+                count: 1,
+            },
+        }
+    }
+
+    fn candle(start: u64, close: &str) -> Candle {
+        Candle::try_from(&closed(start + 60, close).candle).unwrap()
+    }
+
+    #[test]
+    fn consecutive_candles_do_not_backfill() -> Result<()> {
+        let source = FakeSource::default();
+        let mut history = CandleHistory::new(&source);
+        assert_eq!(vec![candle(0, "1")], history.push(&closed(60, "1"))?);
+        assert_eq!(vec![candle(60, "2")], history.push(&closed(120, "2"))?);
+        assert!(history.push(&closed(120, "2"))?.is_empty());
+        assert!(source.calls.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn gap_is_backfilled_without_duplicates() -> Result<()> {
+        let source = FakeSource {
+            candles: vec![
+                candle(0, "1"),
+                candle(120, "3"),
+                candle(60, "2"),
+                candle(180, "4"),
+                candle(240, "5"),
+            ],
+            ..Default::default()
+        };
+        let mut history = CandleHistory::new(&source);
+        history.push(&closed(60, "1"))?;
+        assert_eq!(
+            vec![candle(60, "2"), candle(120, "3"), candle(180, "4")],
+            history.push(&closed(240, "4"))?
+        );
+        assert_eq!(vec![Timestamp::from_secs(0)], *source.calls.borrow());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_periods_are_flat() -> Result<()> {
+        let source = FakeSource::default();
+        let mut history = CandleHistory::new(&source);
+        history.resume(OhlcInterval::Mins1, candle(0, "7"));
+        let series = history.push(&closed(240, "8"))?;
+        assert_eq!(3, series.len());
+        assert_eq!(
+            Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(60),
+                period: Duration::from_secs(60),
+                open: Decimal::from(7),
+                high: Decimal::from(7),
+                low: Decimal::from(7),
+                close: Decimal::from(7),
+                vwap: Decimal::from(7),
+                volume: Decimal::ZERO,
+                count: 0
+            },
+            series[0]
+        );
+        assert_eq!(Timestamp::from_secs(120), series[1].start);
+        assert_eq!(candle(180, "8"), series[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn long_gap_is_not_made_up() -> Result<()> {
+        let day = 24 * 60 * 60;
+        let source = FakeSource {
+            candles: vec![candle(day, "2"), candle(60, "1")],
+            ..Default::default()
+        };
+        let mut history = CandleHistory::new(&source);
+        history.resume(OhlcInterval::Mins1, candle(0, "1"));
+        assert_eq!(
+            vec![candle(60, "1"), candle(day, "2"), candle(2 * day, "3")],
+            history.push(&closed(2 * day + 60, "3"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failed_backfill_can_be_retried() -> Result<()> {
+        let source = FakeSource {
+            candles: vec![candle(60, "2")],
+            fail: Cell::new(true),
+            ..Default::default()
+        };
+        let mut history = CandleHistory::new(&source);
+        history.push(&closed(60, "1"))?;
+        assert!(history.push(&closed(180, "3")).is_err());
+        source.fail.set(false);
+        assert_eq!(
+            vec![candle(60, "2"), candle(120, "3")],
+            history.push(&closed(180, "3"))?
+        );
+        Ok(())
+    }
+}
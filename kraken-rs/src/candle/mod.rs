@@ -0,0 +1,161 @@
+mod aggregator;
+mod bars;
+mod consistency;
+mod history;
+mod resample;
+
+pub use aggregator::{CandleAggregator, CandleClosed};
+pub use bars::{BarBuilder, BarKind};
+pub use consistency::{compare, ConsistencyCheck, Discrepancy};
+pub use history::{CandleHistory, CandleSource};
+pub use resample::Resampler;
+
+use crate::resp::ohlc::Ohlc;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A candle over any period, not just those Kraken supports,
+/// with the values parsed as decimals so that candles can be
+/// combined exactly.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Candle {
+    pub pair: String,
+    pub start: Timestamp,
+    pub period: Duration,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub vwap: Decimal,
+    pub volume: Decimal,
+    pub count: u32,
+}
+
+impl Candle {
+    pub fn end(&self) -> Timestamp {
+        self.start + self.period
+    }
+
+    /// Extend this candle with a later one covering the time
+    /// directly after it, the period is left unchanged.
+    pub fn merge(&mut self, later: &Candle) {
+        let volume = self.volume + later.volume;
+        if !volume.is_zero() {
+            self.vwap = (self.vwap * self.volume + later.vwap * later.volume) / volume;
+        }
+        self.high = self.high.max(later.high);
+        self.low = self.low.min(later.low);
+        self.close = later.close;
+        self.volume = volume;
+        self.count += later.count;
+    }
+}
+
+impl TryFrom<&Ohlc> for Candle {
+    type Error = Error;
+
+    fn try_from(ohlc: &Ohlc) -> Result<Candle> {
+        let dec = |s: &str, field: &str| {
+            Decimal::from_str(s).map_err(|e| anyhow!("Invalid {} {}: {}", field, s, e))
+        };
+        Ok(Candle {
+            pair: ohlc.pair.clone(),
+            start: ohlc.start(),
+            period: ohlc.interval.duration(),
+            open: dec(&ohlc.open, "open")?,
+            high: dec(&ohlc.high, "high")?,
+            low: dec(&ohlc.low, "low")?,
+            close: dec(&ohlc.close, "close")?,
+            vwap: dec(&ohlc.vwap, "vwap")?,
+            volume: dec(&ohlc.volume, "volume")?,
+            count: ohlc.count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::req::OhlcInterval;
+
+    #[test]
+    fn from_ohlc() -> Result<()> {
+        let ohlc = Ohlc {
+            channel_id: 42,
+            interval: OhlcInterval::Mins5,
+            pair: "XBT/USD".to_string(),
+            time: Timestamp::from_secs(1542057314),
+            etime: Timestamp::from_secs(1542057600),
+            open: "3586.70001".to_string(),
+            high: "3586.70000".to_string(),
+            low: "3586.60001".to_string(),
+            close: "3586.60000".to_string(),
+            vwap: "3586.68894".to_string(),
+            volume: "0.03373000".to_string(),
+            count: 2,
+        };
+        assert_eq!(
+            Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(1542057300),
+                period: Duration::from_secs(300),
+                open: Decimal::new(358670001, 5),
+                high: Decimal::new(358670000, 5),
+                low: Decimal::new(358660001, 5),
+                close: Decimal::new(358660000, 5),
+                vwap: Decimal::new(358668894, 5),
+                volume: Decimal::new(3373000, 8),
+                count: 2
+            },
+            Candle::try_from(&ohlc)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge() {
+        let mut first = Candle {
+            pair: "XBT/USD".to_string(),
+            start: Timestamp::from_secs(0),
+            period: Duration::from_secs(60),
+            open: Decimal::new(100, 0),
+            high: Decimal::new(110, 0),
+            low: Decimal::new(95, 0),
+            close: Decimal::new(105, 0),
+            vwap: Decimal::new(100, 0),
+            volume: Decimal::new(1, 0),
+            count: 3,
+        };
+        first.merge(&Candle {
+            pair: "XBT/USD".to_string(),
+            start: Timestamp::from_secs(60),
+            period: Duration::from_secs(60),
+            open: Decimal::new(105, 0),
+            high: Decimal::new(108, 0),
+            low: Decimal::new(90, 0),
+            close: Decimal::new(92, 0),
+            vwap: Decimal::new(94, 0),
+            volume: Decimal::new(2, 0),
+            count: 4,
+        });
+        assert_eq!(
+            Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(0),
+                period: Duration::from_secs(60),
+                open: Decimal::new(100, 0),
+                high: Decimal::new(110, 0),
+                low: Decimal::new(90, 0),
+                close: Decimal::new(92, 0),
+                vwap: Decimal::new(96, 0),
+                volume: Decimal::new(3, 0),
+                count: 7
+            },
+            first
+        );
+    }
+}
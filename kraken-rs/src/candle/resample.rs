@@ -0,0 +1,201 @@
+use crate::candle::Candle;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Combines closed candles over a short base period into candles
+/// over longer periods, so that one subscription can serve many
+/// timeframes including ones Kraken does not offer such as 3m or
+/// 2h. Each longer period must be a whole multiple of the base
+/// period and candles are aligned to multiples of their period
+/// since the epoch, which matches Kraken for intraday periods.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    periods: Vec<Duration>,
+    building: HashMap<(String, Duration), Building>,
+}
+
+#[derive(Debug, Clone)]
+struct Building {
+    candle: Candle,
+    // End of the last base candle merged in, anything
+    // starting before this has already been counted.
+    covered: Timestamp,
+}
+
+impl Resampler {
+    pub fn new(periods: &[Duration]) -> Result<Resampler> {
+        if let Some(p) = periods.iter().find(|p| p.as_secs() == 0) {
+            return Err(anyhow!("Cannot resample into period of {:?}", p));
+        }
+        Ok(Resampler {
+            periods: periods.to_vec(),
+            building: HashMap::new(),
+        })
+    }
+
+    /// Add a closed base candle, returning any longer candles
+    /// which are now complete. A longer candle is complete once
+    /// its final base candle arrives, or once a base candle for
+    /// a later period arrives if the final one never does. The
+    /// candle is checked against every period before any is
+    /// updated, so an error leaves the resampler as it was.
+    pub fn push(&mut self, candle: &Candle) -> Result<Vec<Candle>> {
+        let base = candle.period.as_micros();
+        if let Some(period) = self
+            .periods
+            .iter()
+            .find(|p| base == 0 || p.as_micros() % base != 0)
+        {
+            return Err(anyhow!(
+                "Cannot resample {:?} candles into {:?} candles",
+                candle.period,
+                period
+            ));
+        }
+        let mut completed = vec![];
+        for &period in &self.periods {
+            let start = candle.start.truncate(period);
+            let key = (candle.pair.clone(), period);
+            let building = match self.building.remove(&key) {
+                Some(mut b) if b.candle.start == start && b.covered <= candle.start => {
+                    b.candle.merge(candle);
+                    b.covered = candle.end();
+                    b
+                }
+                Some(b) if start <= b.candle.start => {
+                    // A base candle we have already seen or one
+                    // from a period which is already complete.
+                    self.building.insert(key, b);
+                    continue;
+                }
+                previous => {
+                    completed.extend(previous.map(|b| b.candle));
+                    Building {
+                        candle: Candle {
+                            start,
+                            period,
+                            ..candle.clone()
+                        },
+                        covered: candle.end(),
+                    }
+                }
+            };
+            if building.covered >= building.candle.end() {
+                completed.push(building.candle);
+            } else {
+                self.building.insert(key, building);
+            }
+        }
+        Ok(completed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    const MIN: u64 = 60;
+
+    fn candle(start: u64, period: u64, open: i64, close: i64, volume: i64) -> Candle {
+        Candle {
+            pair: "XBT/USD".to_string(),
+            start: Timestamp::from_secs(start),
+            period: Duration::from_secs(period),
+            open: Decimal::new(open, 0),
+            high: Decimal::new(open.max(close), 0),
+            low: Decimal::new(open.min(close), 0),
+            close: Decimal::new(close, 0),
+            vwap: Decimal::new(close, 0),
+            volume: Decimal::new(volume, 0),
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn non_native_period() -> Result<()> {
+        let mut resampler = Resampler::new(&[Duration::from_secs(3 * MIN)])?;
+        assert!(resampler.push(&candle(0, MIN, 10, 12, 1))?.is_empty());
+        assert!(resampler.push(&candle(MIN, MIN, 12, 8, 1))?.is_empty());
+        assert_eq!(
+            vec![Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(0),
+                period: Duration::from_secs(3 * MIN),
+                open: Decimal::new(10, 0),
+                high: Decimal::new(12, 0),
+                low: Decimal::new(8, 0),
+                close: Decimal::new(9, 0),
+                vwap: Decimal::new(95, 1),
+                volume: Decimal::new(4, 0),
+                count: 3
+            }],
+            resampler.push(&candle(2 * MIN, MIN, 8, 9, 2))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn gap_completes_on_next_period() -> Result<()> {
+        let mut resampler = Resampler::new(&[Duration::from_secs(5 * MIN)])?;
+        resampler.push(&candle(0, MIN, 10, 12, 1))?;
+        resampler.push(&candle(MIN, MIN, 12, 11, 1))?;
+        let completed = resampler.push(&candle(6 * MIN, MIN, 11, 13, 1))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(Timestamp::from_secs(0), completed[0].start);
+        assert_eq!(Decimal::new(11, 0), completed[0].close);
+        assert_eq!(2, completed[0].count);
+        Ok(())
+    }
+
+    #[test]
+    fn several_periods() -> Result<()> {
+        let mut resampler = Resampler::new(&[
+            Duration::from_secs(10 * MIN),
+            Duration::from_secs(2 * 60 * MIN),
+        ])?;
+        let mut completed = vec![];
+        for i in 0..24 {
+            completed.extend(resampler.push(&candle(i * 5 * MIN, 5 * MIN, 10, 10, 1))?);
+        }
+        assert_eq!(13, completed.len());
+        let two_hour = completed.last().unwrap();
+        assert_eq!(Duration::from_secs(2 * 60 * MIN), two_hour.period);
+        assert_eq!(Decimal::new(24, 0), two_hour.volume);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_is_ignored() -> Result<()> {
+        let mut resampler = Resampler::new(&[Duration::from_secs(2 * MIN)])?;
+        resampler.push(&candle(0, MIN, 10, 12, 1))?;
+        assert!(resampler.push(&candle(0, MIN, 10, 12, 1))?.is_empty());
+        let completed = resampler.push(&candle(MIN, MIN, 12, 14, 1))?;
+        assert_eq!(Decimal::new(2, 0), completed[0].volume);
+        Ok(())
+    }
+
+    #[test]
+    fn period_must_be_multiple_of_base() -> Result<()> {
+        let mut resampler = Resampler::new(&[Duration::from_secs(7 * MIN)])?;
+        assert!(resampler.push(&candle(0, 5 * MIN, 10, 12, 1)).is_err());
+        assert!(Resampler::new(&[Duration::from_secs(0)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_candle_changes_nothing() -> Result<()> {
+        let mut resampler =
+            Resampler::new(&[Duration::from_secs(2 * MIN), Duration::from_secs(3 * MIN)])?;
+        resampler.push(&candle(0, MIN, 10, 12, 1))?;
+        // Bars closing on a single trade last no time at all.
+        assert!(resampler.push(&candle(MIN, 0, 12, 12, 5)).is_err());
+        assert!(resampler.push(&candle(MIN, 2 * MIN, 12, 12, 5)).is_err());
+        let completed = resampler.push(&candle(MIN, MIN, 12, 14, 1))?;
+        assert_eq!(1, completed.len());
+        assert_eq!(Decimal::new(2, 0), completed[0].volume);
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use crate::req::Subscription;
+use anyhow::{anyhow, bail, Result};
+use serde_derive::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What the collector subscribes to and where it writes, read
+/// from TOML or YAML, e.g.
+///
+/// ```toml
+/// pairs = ["XBT/USD", "ETH/USD"]
+/// flush_secs = 10
+/// log = "json"
+///
+/// [[channels]]
+/// name = "ohlc"
+/// interval = 15
+///
+/// [[channels]]
+/// name = "ticker"
+///
+/// [[sinks]]
+/// type = "csv"
+/// dir = "/data/csv"
+///
+/// [[sinks]]
+/// type = "metrics"
+/// listen = "0.0.0.0:9100"
+/// ```
+///
+/// Channels are written as in Kraken's subscribe requests and
+/// every channel is subscribed to for every pair. The private
+/// channels can't be collected since their tokens expire.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The websocket endpoint, Kraken's public one by default.
+    #[serde(default = "default_url")]
+    pub url: String,
+    pub pairs: Vec<String>,
+    pub channels: Vec<Subscription>,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Seconds between flushes of the sinks.
+    #[serde(default = "default_flush_secs")]
+    pub flush_secs: u64,
+    #[serde(default)]
+    pub log: LogFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// See [`crate::sink::CsvSink`].
+    Csv {
+        dir: PathBuf,
+        ticker_interval_secs: Option<u64>,
+    },
+    /// See [`crate::sink::ParquetSink`].
+    Parquet {
+        dir: PathBuf,
+        batch_rows: Option<usize>,
+        ticker_interval_secs: Option<u64>,
+    },
+    /// See [`crate::sink::SqliteSink`].
+    Sqlite { path: PathBuf },
+    /// See [`crate::sink::InfluxSink`], written to one of a
+    /// `file`, `udp` address or `http` url. An InfluxDB 2 `http`
+    /// endpoint also needs a `token`.
+    Influx {
+        file: Option<PathBuf>,
+        udp: Option<String>,
+        http: Option<String>,
+        token: Option<String>,
+    },
+    /// Prometheus metrics served on the address, see
+    /// [`crate::metrics::Metrics`].
+    Metrics { listen: String },
+    /// The latest state served over REST on `http` and
+    /// responses forwarded to websocket clients on `ws`, see
+    /// [`crate::fanout::FanoutServer`].
+    Fanout {
+        http: Option<String>,
+        ws: Option<String>,
+    },
+    /// Binary frames sent to a UDP address, usually multicast,
+    /// see [`crate::dist::UdpPublisher`].
+    Udp { addr: String, ttl: Option<u32> },
+    /// Binary frames written to a ring buffer in shared memory,
+    /// see [`crate::dist::ShmPublisher`].
+    Shm {
+        path: PathBuf,
+        slots: Option<u64>,
+        slot_bytes: Option<u64>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One json object per line.
+    #[default]
+    Json,
+    /// Human readable lines.
+    Text,
+}
+
+fn default_url() -> String {
+    crate::ENDPOINT.to_owned()
+}
+
+fn default_flush_secs() -> u64 {
+    10
+}
+
+impl Config {
+    /// Read a config file, YAML if the extension is "yaml" or
+    /// "yml" and TOML otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Can't read config {}: {}", path.display(), e))?;
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => Config::from_yaml(&text)?,
+            _ => Config::from_toml(&text)?,
+        };
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Config> {
+        let config: Config = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Config> {
+        let config: Config = serde_yaml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_secs)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.channels.is_empty() {
+            bail!("No channels to subscribe to");
+        }
+        if self.pairs.is_empty() {
+            bail!("No pairs to subscribe to");
+        }
+        for channel in &self.channels {
+            if let Subscription::OpenOrders { .. } | Subscription::OwnTrades { .. } = channel {
+                // Tokens expire unless used so one in a config
+                // can't be relied on to reconnect with.
+                bail!("Private channels can't be collected, they need a new token");
+            }
+        }
+        for sink in &self.sinks {
+            if let SinkConfig::Influx {
+                file,
+                udp,
+                http,
+                token,
+            } = sink
+            {
+                let targets = file.is_some() as u8 + udp.is_some() as u8 + http.is_some() as u8;
+                if targets != 1 {
+                    bail!("An influx sink needs one of file, udp or http");
+                }
+                if token.is_some() && http.is_none() {
+                    bail!("An influx token is only used with http");
+                }
+            }
+            if let SinkConfig::Fanout {
+                http: None,
+                ws: None,
+            } = sink
+            {
+                bail!("A fanout sink needs an http or ws address");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SinkConfig {
+    /// The sink's `type` in the config.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkConfig::Csv { .. } => "csv",
+            SinkConfig::Parquet { .. } => "parquet",
+            SinkConfig::Sqlite { .. } => "sqlite",
+            SinkConfig::Influx { .. } => "influx",
+            SinkConfig::Metrics { .. } => "metrics",
+            SinkConfig::Fanout { .. } => "fanout",
+            SinkConfig::Udp { .. } => "udp",
+            SinkConfig::Shm { .. } => "shm",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::req::{BookDepth, OhlcInterval};
+
+    const TOML: &str = r#"
+        pairs = ["XBT/USD", "ETH/USD"]
+        log = "text"
+
+        [[channels]]
+        name = "ohlc"
+        interval = 15
+
+        [[channels]]
+        name = "book"
+        depth = 10
+
+        [[sinks]]
+        type = "csv"
+        dir = "/data/csv"
+        ticker_interval_secs = 30
+
+        [[sinks]]
+        type = "influx"
+        udp = "127.0.0.1:8089"
+    "#;
+
+    const YAML: &str = r#"
+        url: ws://localhost:8080
+        pairs: [XBT/USD, ETH/USD]
+        flush_secs: 5
+        log: text
+        channels:
+          - name: ohlc
+            interval: 15
+          - name: book
+            depth: 10
+        sinks:
+          - type: csv
+            dir: /data/csv
+            ticker_interval_secs: 30
+          - type: influx
+            udp: 127.0.0.1:8089
+    "#;
+
+    #[test]
+    fn toml_and_yaml() -> Result<()> {
+        let config = Config::from_toml(TOML)?;
+        assert_eq!(
+            Config {
+                url: "wss://ws.kraken.com".to_string(),
+                pairs: vec!["XBT/USD".to_string(), "ETH/USD".to_string()],
+                channels: vec![
+                    Subscription::Ohlc {
+                        interval: OhlcInterval::Mins15
+                    },
+                    Subscription::Book {
+                        depth: BookDepth::N10
+                    },
+                ],
+                sinks: vec![
+                    SinkConfig::Csv {
+                        dir: "/data/csv".into(),
+                        ticker_interval_secs: Some(30)
+                    },
+                    SinkConfig::Influx {
+                        file: None,
+                        udp: Some("127.0.0.1:8089".to_string()),
+                        http: None,
+                        token: None
+                    },
+                ],
+                flush_secs: 10,
+                log: LogFormat::Text,
+            },
+            config
+        );
+        assert_eq!(
+            Config {
+                url: "ws://localhost:8080".to_string(),
+                flush_secs: 5,
+                ..config
+            },
+            Config::from_yaml(YAML)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let invalid = [
+            "pairs = [\"XBT/USD\"]\nchannels = []",
+            "pairs = []\n[[channels]]\nname = \"ticker\"",
+            "pairs = [\"XBT/USD\"]\n[[channels]]\nname = \"ownTrades\"\ntoken = \"abc\"",
+            "pairs = [\"XBT/USD\"]\n[[channels]]\nname = \"ohlc\"\ninterval = 2",
+            "pairs = [\"XBT/USD\"]\n[[channels]]\nname = \"ticker\"\n[[sinks]]\ntype = \"influx\"",
+            "pairs = [\"XBT/USD\"]\nchanels = []",
+            "pairs = [\"XBT/USD\"]\n[[channels]]\nname = \"ticker\"\n[[sinks]]\ntype = \"fanout\"",
+            "pairs = [\"XBT/USD\"]\n[[channels]]\nname = \"ticker\"\n[[sinks]]\ntype = \"influx\"\nudp = \"127.0.0.1:8089\"\ntoken = \"abc\"",
+        ];
+        for text in invalid.iter() {
+            assert!(Config::from_toml(text).is_err(), "{}", text);
+        }
+    }
+}
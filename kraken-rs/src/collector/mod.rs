@@ -0,0 +1,480 @@
+mod config;
+
+pub use config::{Config, LogFormat, SinkConfig};
+
+use crate::fanout::FanoutServer;
+use crate::metrics::Metrics;
+use crate::req::WsReq;
+use crate::resp::event::SubscriptionState;
+use crate::resp::Resp;
+use crate::sink::{CsvSink, InfluxSink, Sink};
+use crate::time::Timestamp;
+use crate::Kraken;
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How often the shutdown flag is checked while waiting for
+/// messages.
+const POLL: Duration = Duration::from_millis(250);
+
+/// A connection which has sent nothing for this long, not even
+/// a heartbeat, is treated as lost.
+const STALE_AFTER: Duration = Duration::from_secs(10);
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Subscribes to the channels in a [`Config`] and writes every
+/// response to its sinks until shut down, reconnecting with
+/// backoff whenever the connection is lost. Connection
+/// problems are logged and retried. A sink failing to write is
+/// logged and counted, in `kraken_sink_errors_total` when
+/// serving metrics, and the other sinks carry on.
+pub struct Collector {
+    config: Config,
+    sinks: Vec<CollectorSink>,
+    metrics: Option<Metrics>,
+}
+
+struct CollectorSink {
+    kind: &'static str,
+    sink: Box<dyn Sink + Send>,
+    errors: u64,
+}
+
+impl Collector {
+    /// Open the sinks in the config, metrics are served from
+    /// here on.
+    pub fn new(config: Config) -> Result<Collector> {
+        let mut sinks: Vec<CollectorSink> = vec![];
+        let mut metrics = None;
+        for sink in &config.sinks {
+            let opened: Box<dyn Sink + Send> = match sink {
+                SinkConfig::Metrics { listen } => {
+                    // Every address serves the same metrics.
+                    let shared: &Metrics = metrics.get_or_insert_with(|| {
+                        let shared = Metrics::new();
+                        sinks.push(CollectorSink::new(sink, Box::new(shared.clone())));
+                        shared
+                    });
+                    let addr = shared.serve(listen.as_str())?;
+                    info!(%addr, "serving metrics");
+                    continue;
+                }
+                SinkConfig::Fanout { http, ws } => {
+                    let fanout = FanoutServer::new();
+                    if let Some(http) = http {
+                        let addr = fanout.serve_http(http.as_str())?;
+                        info!(%addr, "serving latest state");
+                    }
+                    if let Some(ws) = ws {
+                        let addr = fanout.serve_ws(ws.as_str())?;
+                        info!(%addr, "serving websocket");
+                    }
+                    Box::new(fanout)
+                }
+                sink => open(sink)?,
+            };
+            sinks.push(CollectorSink::new(sink, opened));
+        }
+        Ok(Collector {
+            config,
+            sinks,
+            metrics,
+        })
+    }
+
+    /// Collect until the flag is set, then flush the sinks.
+    pub fn run(&mut self, shutdown: &AtomicBool) -> Result<()> {
+        let mut delay = MIN_RECONNECT_DELAY;
+        let mut first = true;
+        while !shutdown.load(Ordering::Relaxed) {
+            if !first {
+                info!(delay_secs = delay.as_secs(), "reconnecting");
+                if !sleep(delay, shutdown) {
+                    break;
+                }
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_reconnect();
+                }
+            }
+            first = false;
+            if self.session(shutdown)? {
+                delay = MIN_RECONNECT_DELAY;
+            } else {
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+            // Keep what was received before the connection failed.
+            self.flush();
+        }
+        info!("shutting down");
+        self.flush();
+        Ok(())
+    }
+
+    /// Writes and flushes which have failed, across every sink.
+    pub fn sink_errors(&self) -> u64 {
+        self.sinks.iter().map(|sink| sink.errors).sum()
+    }
+
+    /// Collect over one connection until it fails or the flag is
+    /// set, returning whether anything was received.
+    fn session(&mut self, shutdown: &AtomicBool) -> Result<bool> {
+        let mut client = match self.connect() {
+            Ok(client) => client,
+            Err(e) => {
+                error!(url = %self.config.url, error = %e, "connection failed");
+                return Ok(false);
+            }
+        };
+        info!(url = %self.config.url, "connected");
+        // Read on another thread so a quiet connection can't
+        // delay shutting down.
+        let closer = match client.closer() {
+            Ok(closer) => closer,
+            Err(e) => {
+                error!(error = %e, "connection failed");
+                return Ok(false);
+            }
+        };
+        let (sender, receiver) = mpsc::sync_channel(1024);
+        let reader = thread::spawn(move || loop {
+            let resp = client.recv();
+            let lost = matches!(&resp, Err(e) if !e.is::<serde_json::Error>());
+            if sender.send(resp).is_err() || lost {
+                break;
+            }
+        });
+        let received = self.read(&receiver, shutdown);
+        // Unblock the reader, then wait for it to drop the
+        // connection before making another.
+        if let Err(e) = closer.close() {
+            debug!(error = %e, "closing connection");
+        }
+        drop(receiver);
+        let _ = reader.join();
+        received
+    }
+
+    /// Write what the reader thread receives until the connection
+    /// fails or goes quiet or the flag is set.
+    fn read(&mut self, receiver: &Receiver<Result<Resp>>, shutdown: &AtomicBool) -> Result<bool> {
+        let mut received = false;
+        let mut last_message = Instant::now();
+        let mut last_flush = Instant::now();
+        while !shutdown.load(Ordering::Relaxed) {
+            match receiver.recv_timeout(POLL) {
+                Ok(Ok(resp)) => {
+                    received = true;
+                    last_message = Instant::now();
+                    self.write(&resp, Timestamp::now());
+                }
+                Ok(Err(e)) if e.is::<serde_json::Error>() => {
+                    last_message = Instant::now();
+                    warn!(error = %e, "unparsable message");
+                }
+                Ok(Err(e)) => {
+                    error!(error = %e, "connection lost");
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if last_message.elapsed() >= STALE_AFTER {
+                        error!(
+                            quiet_secs = STALE_AFTER.as_secs(),
+                            "connection stale, nothing received"
+                        );
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if last_flush.elapsed() >= self.config.flush_interval() {
+                self.flush();
+                last_flush = Instant::now();
+            }
+        }
+        Ok(received)
+    }
+
+    fn connect(&self) -> Result<Kraken> {
+        let mut client = Kraken::connect(&self.config.url)?;
+        for subscription in &self.config.channels {
+            client.send_req(WsReq::Subscribe {
+                request_id: None,
+                pair: self.config.pairs.clone(),
+                subscription: subscription.clone(),
+            })?;
+        }
+        Ok(client)
+    }
+
+    fn write(&mut self, resp: &Resp, received: Timestamp) {
+        match resp {
+            Resp::SystemStatus(status) => info!(status = ?status.status, "system status"),
+            Resp::SubscriptionStatus(status) => match status.status {
+                SubscriptionState::Error => warn!(
+                    pair = ?status.pair,
+                    error = ?status.error_message,
+                    "subscription failed"
+                ),
+                state => info!(
+                    pair = ?status.pair,
+                    channel = ?status.channel_name,
+                    status = ?state,
+                    "subscription"
+                ),
+            },
+            _ => {}
+        }
+        for sink in &mut self.sinks {
+            let written = sink.sink.write(resp, received);
+            sink.check(written, "write", &self.metrics);
+        }
+    }
+
+    fn flush(&mut self) {
+        for sink in &mut self.sinks {
+            let flushed = sink.sink.flush();
+            sink.check(flushed, "flush", &self.metrics);
+        }
+        debug!("flushed sinks");
+    }
+}
+
+impl CollectorSink {
+    fn new(config: &SinkConfig, sink: Box<dyn Sink + Send>) -> CollectorSink {
+        CollectorSink {
+            kind: config.kind(),
+            sink,
+            errors: 0,
+        }
+    }
+
+    /// Log and count a failed write or flush.
+    fn check(&mut self, result: Result<()>, action: &str, metrics: &Option<Metrics>) {
+        if let Err(e) = result {
+            self.errors += 1;
+            error!(
+                sink = self.kind,
+                action,
+                errors = self.errors,
+                error = %e,
+                "sink failed"
+            );
+            if let Some(metrics) = metrics {
+                metrics.record_sink_error(self.kind);
+            }
+        }
+    }
+}
+
+fn open(sink: &SinkConfig) -> Result<Box<dyn Sink + Send>> {
+    Ok(match sink {
+        SinkConfig::Csv {
+            dir,
+            ticker_interval_secs,
+        } => {
+            let mut csv = CsvSink::new(dir)?;
+            if let Some(secs) = ticker_interval_secs {
+                csv = csv.ticker_interval(Duration::from_secs(*secs));
+            }
+            Box::new(csv)
+        }
+        #[cfg(feature = "parquet")]
+        SinkConfig::Parquet {
+            dir,
+            batch_rows,
+            ticker_interval_secs,
+        } => {
+            let mut parquet = crate::sink::ParquetSink::new(dir)?;
+            if let Some(rows) = batch_rows {
+                parquet = parquet.batch_rows(*rows);
+            }
+            if let Some(interval) = ticker_interval_secs {
+                parquet = parquet.ticker_interval(Duration::from_secs(*interval));
+            }
+            Box::new(parquet)
+        }
+        #[cfg(feature = "sqlite")]
+        SinkConfig::Sqlite { path } => Box::new(crate::sink::SqliteSink::open(path)?),
+        SinkConfig::Influx {
+            file,
+            udp,
+            http,
+            token,
+        } => Box::new(match (file, udp, http) {
+            (Some(file), _, _) => InfluxSink::file(file)?,
+            (_, Some(udp), _) => InfluxSink::udp(udp.as_str())?,
+            (_, _, Some(http)) => InfluxSink::http(http, token.as_deref())?,
+            _ => bail!("An influx sink needs one of file, udp or http"),
+        }),
+        #[cfg(feature = "dist")]
+        SinkConfig::Udp { addr, ttl } => {
+            let mut udp = crate::dist::UdpPublisher::new(addr.as_str())?;
+            if let Some(ttl) = ttl {
+                udp = udp.multicast_ttl(*ttl)?;
+            }
+            Box::new(udp)
+        }
+        #[cfg(all(feature = "dist", unix))]
+        SinkConfig::Shm {
+            path,
+            slots,
+            slot_bytes,
+        } => {
+            use crate::dist::ShmPublisher;
+            Box::new(ShmPublisher::create(
+                path,
+                slots.unwrap_or(ShmPublisher::DEFAULT_SLOTS),
+                slot_bytes.unwrap_or(ShmPublisher::DEFAULT_SLOT_BYTES),
+            )?)
+        }
+        sink => bail!("Built without support for {:?}", sink),
+    })
+}
+
+/// Sleep unless shut down first, returning whether the whole
+/// time passed.
+fn sleep(duration: Duration, shutdown: &AtomicBool) -> bool {
+    let until = Instant::now() + duration;
+    while !shutdown.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= until {
+            return true;
+        }
+        thread::sleep(POLL.min(until - now));
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockServer;
+    use crate::req::{OhlcInterval, Subscription};
+    use std::fs;
+    use std::sync::Arc;
+
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-1","XBT/USD"]"#;
+
+    #[test]
+    fn collects_until_shutdown_across_reconnects() -> Result<()> {
+        let server = MockServer::start()?;
+        server.script("ohlc-1", "XBT/USD", vec![OHLC.to_string()]);
+        let dir = tempfile::tempdir()?;
+        let config = Config {
+            url: server.url(),
+            pairs: vec!["XBT/USD".to_string()],
+            channels: vec![Subscription::Ohlc {
+                interval: OhlcInterval::Mins1,
+            }],
+            sinks: vec![
+                SinkConfig::Influx {
+                    file: Some(dir.path().join("kraken.lp")),
+                    udp: None,
+                    http: None,
+                    token: None,
+                },
+                SinkConfig::Metrics {
+                    listen: "127.0.0.1:0".to_string(),
+                },
+            ],
+            flush_secs: 3600,
+            log: LogFormat::Text,
+        };
+        let mut collector = Collector::new(config)?;
+        let metrics = collector.metrics.clone().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let running = thread::spawn(move || collector.run(&flag));
+
+        let subscribed = Duration::from_secs(5);
+        server.wait_for("subscribe", subscribed).unwrap();
+        server.disconnect();
+        // Subscribed again after reconnecting.
+        let until = Instant::now() + Duration::from_secs(10);
+        while server.requests().len() < 2 && Instant::now() < until {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(2, server.requests().len());
+        thread::sleep(Duration::from_millis(500));
+        shutdown.store(true, Ordering::Relaxed);
+        running.join().unwrap()?;
+        // No reader is left holding a connection open.
+        let until = Instant::now() + Duration::from_secs(5);
+        while server.connections() > 0 && Instant::now() < until {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(0, server.connections());
+
+        // Written once per connection, flushed on shutdown.
+        let lines = fs::read_to_string(dir.path().join("kraken.lp"))?;
+        assert_eq!(2, lines.lines().count());
+        assert!(lines.starts_with("ohlc,interval=1,pair=XBT/USD open=3586.70000"));
+        assert!(metrics.render().contains("\nkraken_reconnects_total 1\n"));
+        Ok(())
+    }
+
+    struct Failing;
+
+    impl Sink for Failing {
+        fn write(&mut self, _: &Resp, _: Timestamp) -> Result<()> {
+            bail!("disk full")
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            bail!("disk full")
+        }
+    }
+
+    #[test]
+    fn failing_sink_does_not_stop_others() -> Result<()> {
+        let server = MockServer::start()?;
+        server.script("ohlc-1", "XBT/USD", vec![OHLC.to_string()]);
+        let dir = tempfile::tempdir()?;
+        let config = Config {
+            url: server.url(),
+            pairs: vec!["XBT/USD".to_string()],
+            channels: vec![Subscription::Ohlc {
+                interval: OhlcInterval::Mins1,
+            }],
+            sinks: vec![SinkConfig::Influx {
+                file: Some(dir.path().join("kraken.lp")),
+                udp: None,
+                http: None,
+                token: None,
+            }],
+            flush_secs: 3600,
+            log: LogFormat::Text,
+        };
+        let mut collector = Collector::new(config)?;
+        let failing = SinkConfig::Csv {
+            dir: dir.path().into(),
+            ticker_interval_secs: None,
+        };
+        collector
+            .sinks
+            .insert(0, CollectorSink::new(&failing, Box::new(Failing)));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let running = thread::spawn(move || collector.run(&flag).map(|_| collector));
+
+        server
+            .wait_for("subscribe", Duration::from_secs(5))
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+        shutdown.store(true, Ordering::Relaxed);
+        let collector = running.join().unwrap()?;
+
+        let lines = fs::read_to_string(dir.path().join("kraken.lp"))?;
+        assert_eq!(1, lines.lines().count());
+        // At least the system status, subscription status,
+        // candle and the flush on shutdown.
+        assert!(collector.sink_errors() >= 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,427 @@
+use crate::book::{OrderBook, OutOfSync};
+use crate::req::{BookDepth, OhlcInterval};
+use crate::resp::event::SystemState;
+use crate::resp::ohlc::Ohlc;
+use crate::resp::ticker::TickerState;
+use crate::resp::Resp;
+use crate::time::Timestamp;
+use anyhow::{bail, Result};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::canvas::{self, Canvas, Rectangle};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Candles kept for each pair, more than fit on a wide terminal.
+const MAX_CANDLES: usize = 240;
+
+/// State of the connection the dashboard is fed from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Connection {
+    Connecting,
+    Connected,
+    /// Lost with the error, while waiting to reconnect.
+    Lost(String),
+}
+
+/// A terminal dashboard of the tickers for a list of pairs,
+/// with candles and the order book of the selected pair and
+/// the state of the connection. It is updated from the
+/// responses on the ticker, ohlc and book channels of the
+/// pairs and drawn with [`Dashboard::render`]; reading the
+/// responses and the keyboard is up to the caller.
+pub struct Dashboard {
+    pairs: Vec<String>,
+    interval: OhlcInterval,
+    depth: BookDepth,
+    selected: usize,
+    tickers: HashMap<String, TickerState>,
+    // By candle end time.
+    candles: HashMap<String, BTreeMap<Timestamp, Ohlc>>,
+    books: HashMap<String, OrderBook>,
+    // Shown instead of a book which failed to update.
+    book_errors: HashMap<String, String>,
+    connection: Connection,
+    system: Option<SystemState>,
+    messages: u64,
+    last_message: Option<Instant>,
+}
+
+impl Dashboard {
+    /// A dashboard of at least one pair, the first is selected.
+    pub fn new(pairs: Vec<String>, interval: OhlcInterval, depth: BookDepth) -> Result<Dashboard> {
+        if pairs.is_empty() {
+            bail!("No pairs to show");
+        }
+        Ok(Dashboard {
+            pairs,
+            interval,
+            depth,
+            selected: 0,
+            tickers: HashMap::new(),
+            candles: HashMap::new(),
+            books: HashMap::new(),
+            book_errors: HashMap::new(),
+            connection: Connection::Connecting,
+            system: None,
+            messages: 0,
+            last_message: None,
+        })
+    }
+
+    pub fn pairs(&self) -> &[String] {
+        &self.pairs
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.pairs[self.selected]
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.pairs.len();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = (self.selected + self.pairs.len() - 1) % self.pairs.len();
+    }
+
+    /// Books are cleared when the connection changes, the next
+    /// snapshot rebuilds them.
+    pub fn set_connection(&mut self, connection: Connection) {
+        self.books.clear();
+        self.connection = connection;
+    }
+
+    /// Update from a response. A book which can't be updated is
+    /// dropped and the error shown in its place until the next
+    /// snapshot.
+    pub fn apply(&mut self, resp: &Resp) {
+        self.messages += 1;
+        self.last_message = Some(Instant::now());
+        match resp {
+            Resp::Ticker(ticker) => {
+                self.tickers.insert(ticker.pair.clone(), ticker.clone());
+            }
+            Resp::Ohlc(ohlc) if ohlc.interval == self.interval => {
+                let candles = self.candles.entry(ohlc.pair.clone()).or_default();
+                candles.insert(ohlc.etime, ohlc.clone());
+                while candles.len() > MAX_CANDLES {
+                    candles.pop_first();
+                }
+            }
+            Resp::Book(book) => {
+                let depth = self.depth;
+                let order_book = self
+                    .books
+                    .entry(book.pair.clone())
+                    .or_insert_with(|| OrderBook::new(depth));
+                match order_book.apply(book) {
+                    Ok(()) => {
+                        self.book_errors.remove(&book.pair);
+                    }
+                    // Shown empty until the next snapshot.
+                    Err(e) if e.is::<OutOfSync>() => {}
+                    Err(e) => {
+                        self.books.remove(&book.pair);
+                        self.book_errors.insert(book.pair.clone(), e.to_string());
+                    }
+                }
+            }
+            Resp::SystemStatus(status) => self.system = Some(status.status),
+            _ => {}
+        }
+    }
+
+    pub fn render(&self, frame: &mut Frame) {
+        let [status, body] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(frame.area());
+        let [watchlist, selected] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Min(0)]).areas(body);
+        let [chart, ladder] =
+            Layout::vertical([Constraint::Percentage(55), Constraint::Min(0)]).areas(selected);
+        self.render_status(frame, status);
+        self.render_watchlist(frame, watchlist);
+        self.render_candles(frame, chart);
+        self.render_book(frame, ladder);
+    }
+
+    fn render_status(&self, frame: &mut Frame, area: Rect) {
+        let (connection, color) = match &self.connection {
+            Connection::Connecting => ("connecting".to_owned(), Color::Yellow),
+            Connection::Connected => ("connected".to_owned(), Color::Green),
+            Connection::Lost(e) => (format!("lost: {}", e), Color::Red),
+        };
+        let system = match self.system {
+            Some(state) => format!("{:?}", state),
+            None => "-".to_owned(),
+        };
+        let last = match self.last_message {
+            Some(last) => format!("{:.1}s ago", last.elapsed().as_secs_f64()),
+            None => "never".to_owned(),
+        };
+        let line = Line::from(vec![
+            Span::styled(connection, Style::default().fg(color)),
+            Span::raw(format!(
+                " | system {} | {} messages, last {} | ↑↓ select, q quit",
+                system, self.messages, last
+            )),
+        ]);
+        frame.render_widget(Paragraph::new(line), area);
+    }
+
+    fn render_watchlist(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.pairs.iter().map(|pair| match self.tickers.get(pair) {
+            Some(ticker) => {
+                let (change, color) = match change_24h(ticker) {
+                    Some(change) if change.is_sign_negative() => {
+                        (format!("{:.2}%", change), Color::Red)
+                    }
+                    Some(change) => (format!("+{:.2}%", change), Color::Green),
+                    None => ("-".to_owned(), Color::Reset),
+                };
+                Row::new(vec![
+                    pair.clone(),
+                    ticker.close.today.clone(),
+                    ticker.bid.price.clone(),
+                    ticker.ask.price.clone(),
+                    change,
+                ])
+                .style(Style::default().fg(color))
+            }
+            None => Row::new(vec![pair.clone()]),
+        });
+        let widths = [
+            Constraint::Length(9),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+        ];
+        let table = Table::new(rows, widths)
+            .header(
+                Row::new(vec!["pair", "last", "bid", "ask", "24h"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::bordered().title("Watchlist"))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+
+    fn render_candles(&self, frame: &mut Frame, area: Rect) {
+        let pair = self.selected();
+        let title = format!("{} {}m", pair, self.interval.minutes());
+        let block = Block::bordered().title(title);
+        // One column of the terminal for each candle.
+        let shown = area.width.saturating_sub(2) as usize;
+        let candles: Vec<Bar> = self
+            .candles
+            .get(pair)
+            .map(|c| {
+                c.values()
+                    .rev()
+                    .take(shown)
+                    .rev()
+                    .filter_map(Bar::new)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let low = candles.iter().map(|c| c.low).fold(f64::INFINITY, f64::min);
+        let high = candles
+            .iter()
+            .map(|c| c.high)
+            .fold(f64::NEG_INFINITY, f64::max);
+        if candles.is_empty() {
+            frame.render_widget(Paragraph::new("waiting for candles").block(block), area);
+            return;
+        }
+        let chart = Canvas::default()
+            .block(block)
+            .x_bounds([0.0, shown.max(1) as f64])
+            .y_bounds([low, high.max(low + f64::EPSILON)])
+            .paint(move |ctx| {
+                for (i, bar) in candles.iter().enumerate() {
+                    let x = i as f64 + 0.5;
+                    let color = if bar.close >= bar.open {
+                        Color::Green
+                    } else {
+                        Color::Red
+                    };
+                    ctx.draw(&canvas::Line::new(x, bar.low, x, bar.high, color));
+                    ctx.draw(&Rectangle {
+                        x: x - 0.3,
+                        y: bar.open.min(bar.close),
+                        width: 0.6,
+                        height: (bar.close - bar.open).abs(),
+                        color,
+                    });
+                }
+            });
+        frame.render_widget(chart, area);
+    }
+
+    fn render_book(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(format!("Book {}", self.selected()));
+        let book = match self.books.get(self.selected()) {
+            Some(book) => book,
+            None => {
+                let text = match self.book_errors.get(self.selected()) {
+                    Some(e) => format!("book failed: {}", e),
+                    None => "waiting for book".to_owned(),
+                };
+                frame.render_widget(Paragraph::new(text).block(block), area);
+                return;
+            }
+        };
+        // Half the rows inside the border for each side.
+        let levels = (area.height.saturating_sub(3) / 2) as usize;
+        let asks: Vec<_> = book.asks().take(levels).collect();
+        let rows = asks
+            .iter()
+            .rev()
+            .map(|&l| (l, Color::Red))
+            .chain(book.bids().take(levels).map(|l| (l, Color::Green)))
+            .map(|(level, color)| {
+                Row::new(vec![level.price.clone(), level.volume.clone()])
+                    .style(Style::default().fg(color))
+            });
+        let table = Table::new(rows, [Constraint::Ratio(1, 2); 2])
+            .header(
+                Row::new(vec!["price", "volume"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(block);
+        frame.render_widget(table, area);
+    }
+}
+
+/// The percentage change of the last trade price from the
+/// price 24 hours ago.
+pub fn change_24h(ticker: &TickerState) -> Option<Decimal> {
+    let last = Decimal::from_str(&ticker.close.today).ok()?;
+    let open = Decimal::from_str(&ticker.open_price.last_24h).ok()?;
+    if open.is_zero() {
+        return None;
+    }
+    Some((last - open) / open * Decimal::ONE_HUNDRED)
+}
+
+/// A candle as floats for drawing.
+struct Bar {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+}
+
+impl Bar {
+    fn new(ohlc: &Ohlc) -> Option<Bar> {
+        let f = |s: &str| Decimal::from_str(s).ok()?.to_f64();
+        Some(Bar {
+            open: f(&ohlc.open)?,
+            high: f(&ohlc.high)?,
+            low: f(&ohlc.low)?,
+            close: f(&ohlc.close)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-1","XBT/USD"]"#;
+    const BOOK: &str = r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-10","XBT/USD"]"#;
+
+    fn dashboard() -> Dashboard {
+        Dashboard::new(
+            vec!["XBT/USD".to_string(), "ETH/USD".to_string()],
+            OhlcInterval::Mins1,
+            BookDepth::N10,
+        )
+        .unwrap()
+    }
+
+    fn screen(dashboard: &Dashboard) -> Result<String> {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30))?;
+        terminal.draw(|frame| dashboard.render(frame))?;
+        let buffer = terminal.backend().buffer();
+        let mut screen = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                screen.push_str(buffer[(x, y)].symbol());
+            }
+            screen.push('\n');
+        }
+        Ok(screen)
+    }
+
+    #[test]
+    fn change_from_24h_open() -> Result<()> {
+        match serde_json::from_str(TICKER)? {
+            Resp::Ticker(ticker) => {
+                let change = change_24h(&ticker).unwrap();
+                assert_eq!("-4.13", format!("{:.2}", change));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn renders_responses() -> Result<()> {
+        let mut dashboard = dashboard();
+        let empty = screen(&dashboard)?;
+        assert!(empty.contains("connecting"));
+        assert!(empty.contains("waiting for candles"));
+        assert!(empty.contains("waiting for book"));
+
+        dashboard.set_connection(Connection::Connected);
+        for frame in [TICKER, OHLC, BOOK] {
+            dashboard.apply(&serde_json::from_str(frame)?);
+        }
+        let screen = screen(&dashboard)?;
+        assert!(screen.contains("connected | system - | 3 messages"));
+        assert!(screen.contains("5525.10000"));
+        assert!(screen.contains("-4.13%"));
+        assert!(screen.contains("5541.30000"));
+        assert!(screen.contains("XBT/USD 1m"));
+        assert!(!screen.contains("waiting"));
+        Ok(())
+    }
+
+    #[test]
+    fn shows_book_errors() -> Result<()> {
+        let mut dashboard = dashboard();
+        dashboard.apply(&serde_json::from_str(BOOK)?);
+        let bad = BOOK.replace(r#""bs":[["5541.20000""#, r#""bs":[["price""#);
+        dashboard.apply(&serde_json::from_str(&bad)?);
+        assert!(screen(&dashboard)?.contains("book failed:"));
+        dashboard.apply(&serde_json::from_str(BOOK)?);
+        assert!(screen(&dashboard)?.contains("5541.30000"));
+        Ok(())
+    }
+
+    #[test]
+    fn needs_pairs() {
+        assert!(Dashboard::new(vec![], OhlcInterval::Mins1, BookDepth::N10).is_err());
+    }
+
+    #[test]
+    fn selection_wraps() {
+        let mut dashboard = dashboard();
+        dashboard.select_previous();
+        assert_eq!("ETH/USD", dashboard.selected());
+        dashboard.select_next();
+        assert_eq!("XBT/USD", dashboard.selected());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::req::{BookDepth, OhlcInterval};
+use crate::resp::book::{Book, PriceLevel};
+use crate::resp::ohlc::Ohlc;
+use crate::resp::spread::Spread;
+use crate::resp::ticker::{BidAskData, TickerState, ValueMarker};
+use crate::resp::trade::{OrderType, Side, Trade, Trades};
+use crate::resp::Resp;
+use crate::time::Timestamp;
+use anyhow::{anyhow, bail, Result};
+use std::convert::{TryFrom, TryInto};
+
+const MAGIC: [u8; 2] = *b"KR";
+
+/// Version of the binary format, frames of any other version
+/// are refused.
+pub const VERSION: u8 = 1;
+
+/// Bytes before the message in every frame.
+pub const HEADER_LEN: usize = 28;
+
+// Kinds of message, anything without a binary layout of its own
+// is sent as Kraken's json.
+const WIRE_JSON: u8 = 0;
+const TICKER: u8 = 1;
+const OHLC: u8 = 2;
+const TRADE: u8 = 3;
+const SPREAD: u8 = 4;
+const BOOK: u8 = 5;
+
+/// What a frame says about the message it holds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Header {
+    /// Identifies the publisher, sequence numbers start again
+    /// from one when it changes.
+    pub session: u64,
+    pub seq: u64,
+    /// When the publisher received the message from Kraken.
+    pub received: Timestamp,
+}
+
+/// Append a frame holding the response to `out`. Frames are
+/// little endian, the header is
+///
+/// - the bytes "KR" and the [`VERSION`]
+/// - the kind of message as a byte
+/// - the session, sequence number and received time in
+///   microseconds as `u64`s
+///
+/// followed by the message. Market data is written field by
+/// field with strings prefixed by their `u16` length and lists
+/// by their `u32` length, while other responses are written as
+/// Kraken's json.
+pub fn encode(header: &Header, resp: &Resp, out: &mut Vec<u8>) -> Result<()> {
+    let kind = match resp {
+        Resp::Ticker(_) => TICKER,
+        Resp::Ohlc(_) => OHLC,
+        Resp::Trade(_) => TRADE,
+        Resp::Spread(_) => SPREAD,
+        Resp::Book(_) => BOOK,
+        _ => WIRE_JSON,
+    };
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    out.push(kind);
+    let mut w = Writer(out);
+    w.u64(header.session);
+    w.u64(header.seq);
+    w.time(header.received);
+    match resp {
+        Resp::Ticker(t) => {
+            w.u32(t.channel_id);
+            w.str(&t.pair)?;
+            for side in [&t.ask, &t.bid] {
+                w.str(&side.price)?;
+                w.u64(side.whole_lot_volume);
+                w.str(&side.lot_volume)?;
+            }
+            for marker in [
+                &t.close,
+                &t.volume,
+                &t.volume_weighted_avg_price,
+                &t.low_price,
+                &t.high_price,
+                &t.open_price,
+            ] {
+                w.str(&marker.today)?;
+                w.str(&marker.last_24h)?;
+            }
+            w.u32(t.trade_count.today);
+            w.u32(t.trade_count.last_24h);
+        }
+        Resp::Ohlc(o) => {
+            w.u32(o.channel_id);
+            w.u32(o.interval.minutes());
+            w.str(&o.pair)?;
+            w.time(o.time);
+            w.time(o.etime);
+            for value in [&o.open, &o.high, &o.low, &o.close, &o.vwap, &o.volume] {
+                w.str(value)?;
+            }
+            w.u32(o.count);
+        }
+        Resp::Trade(t) => {
+            w.u32(t.channel_id);
+            w.str(&t.pair)?;
+            w.len(t.trades.len())?;
+            for trade in &t.trades {
+                w.str(&trade.price)?;
+                w.str(&trade.volume)?;
+                w.time(trade.time);
+                w.u8(match trade.side {
+                    Side::Buy => 0,
+                    Side::Sell => 1,
+                });
+                w.u8(match trade.order_type {
+                    OrderType::Market => 0,
+                    OrderType::Limit => 1,
+                });
+                w.str(&trade.misc)?;
+            }
+        }
+        Resp::Spread(s) => {
+            w.u32(s.channel_id);
+            w.str(&s.pair)?;
+            w.str(&s.bid)?;
+            w.str(&s.ask)?;
+            w.time(s.time);
+            w.str(&s.bid_volume)?;
+            w.str(&s.ask_volume)?;
+        }
+        Resp::Book(b) => {
+            w.u32(b.channel_id);
+            w.str(&b.pair)?;
+            w.u32(b.depth.levels());
+            w.u8(b.snapshot as u8);
+            for levels in [&b.asks, &b.bids] {
+                w.len(levels.len())?;
+                for level in levels {
+                    w.str(&level.price)?;
+                    w.str(&level.volume)?;
+                    w.time(level.time);
+                    w.u8(level.republish as u8);
+                }
+            }
+            match b.checksum {
+                Some(checksum) => {
+                    w.u8(1);
+                    w.u32(checksum);
+                }
+                None => w.u8(0),
+            }
+        }
+        resp => w.0.extend_from_slice(resp.to_wire()?.as_bytes()),
+    }
+    Ok(())
+}
+
+/// Read a frame written by [`encode`].
+pub fn decode(frame: &[u8]) -> Result<(Header, Resp)> {
+    if frame.len() < HEADER_LEN || frame[..2] != MAGIC {
+        bail!("Not a frame");
+    }
+    if frame[2] != VERSION {
+        bail!("Unsupported frame version {}", frame[2]);
+    }
+    let kind = frame[3];
+    let mut r = Reader {
+        bytes: frame,
+        pos: 4,
+    };
+    let header = Header {
+        session: r.u64()?,
+        seq: r.u64()?,
+        received: r.time()?,
+    };
+    let resp = match kind {
+        TICKER => {
+            let channel_id = r.u32()?;
+            let pair = r.str()?;
+            let mut side = || -> Result<BidAskData> {
+                Ok(BidAskData {
+                    price: r.str()?,
+                    whole_lot_volume: r.u64()?,
+                    lot_volume: r.str()?,
+                })
+            };
+            let ask = side()?;
+            let bid = side()?;
+            let mut marker = || -> Result<ValueMarker<String>> {
+                Ok(ValueMarker {
+                    today: r.str()?,
+                    last_24h: r.str()?,
+                })
+            };
+            let close = marker()?;
+            let volume = marker()?;
+            let volume_weighted_avg_price = marker()?;
+            let low_price = marker()?;
+            let high_price = marker()?;
+            let open_price = marker()?;
+            Resp::Ticker(TickerState {
+                channel_id,
+                pair,
+                ask,
+                bid,
+                close,
+                volume,
+                volume_weighted_avg_price,
+                trade_count: ValueMarker {
+                    today: r.u32()?,
+                    last_24h: r.u32()?,
+                },
+                low_price,
+                high_price,
+                open_price,
+            })
+        }
+        OHLC => Resp::Ohlc(Ohlc {
+            channel_id: r.u32()?,
+            interval: OhlcInterval::try_from(r.u32()?)?,
+            pair: r.str()?,
+            time: r.time()?,
+            etime: r.time()?,
+            open: r.str()?,
+            high: r.str()?,
+            low: r.str()?,
+            close: r.str()?,
+            vwap: r.str()?,
+            volume: r.str()?,
+            count: r.u32()?,
+        }),
+        TRADE => {
+            let channel_id = r.u32()?;
+            let pair = r.str()?;
+            let count = r.u32()?;
+            let trades = (0..count)
+                .map(|_| {
+                    Ok(Trade {
+                        price: r.str()?,
+                        volume: r.str()?,
+                        time: r.time()?,
+                        side: match r.u8()? {
+                            0 => Side::Buy,
+                            1 => Side::Sell,
+                            side => bail!("Unknown side {}", side),
+                        },
+                        order_type: match r.u8()? {
+                            0 => OrderType::Market,
+                            1 => OrderType::Limit,
+                            order_type => bail!("Unknown order type {}", order_type),
+                        },
+                        misc: r.str()?,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Resp::Trade(Trades {
+                channel_id,
+                pair,
+                trades,
+            })
+        }
+        SPREAD => Resp::Spread(Spread {
+            channel_id: r.u32()?,
+            pair: r.str()?,
+            bid: r.str()?,
+            ask: r.str()?,
+            time: r.time()?,
+            bid_volume: r.str()?,
+            ask_volume: r.str()?,
+        }),
+        BOOK => {
+            let channel_id = r.u32()?;
+            let pair = r.str()?;
+            let depth = BookDepth::try_from(r.u32()?)?;
+            let snapshot = r.u8()? != 0;
+            let mut levels = || -> Result<Vec<PriceLevel>> {
+                let count = r.u32()?;
+                (0..count)
+                    .map(|_| {
+                        Ok(PriceLevel {
+                            price: r.str()?,
+                            volume: r.str()?,
+                            time: r.time()?,
+                            republish: r.u8()? != 0,
+                        })
+                    })
+                    .collect()
+            };
+            let asks = levels()?;
+            let bids = levels()?;
+            let checksum = match r.u8()? {
+                0 => None,
+                _ => Some(r.u32()?),
+            };
+            Resp::Book(Book {
+                channel_id,
+                pair,
+                depth,
+                snapshot,
+                asks,
+                bids,
+                checksum,
+            })
+        }
+        WIRE_JSON => {
+            let json = std::str::from_utf8(&frame[r.pos..])?;
+            r.pos = frame.len();
+            serde_json::from_str(json)?
+        }
+        kind => bail!("Unknown kind of message {}", kind),
+    };
+    if r.pos != frame.len() {
+        bail!("{} bytes left after the message", frame.len() - r.pos);
+    }
+    Ok((header, resp))
+}
+
+struct Writer<'a>(&'a mut Vec<u8>);
+
+impl Writer<'_> {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn time(&mut self, time: Timestamp) {
+        self.u64(time.as_micros());
+    }
+
+    fn len(&mut self, len: usize) -> Result<()> {
+        self.u32(u32::try_from(len)?);
+        Ok(())
+    }
+
+    fn str(&mut self, value: &str) -> Result<()> {
+        let len = u16::try_from(value.len()).map_err(|_| anyhow!("String too long"))?;
+        self.0.extend_from_slice(&len.to_le_bytes());
+        self.0.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos + len;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| anyhow!("Frame ended early"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn time(&mut self) -> Result<Timestamp> {
+        Ok(Timestamp::from_micros(self.u64()?))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FRAMES: [&str; 8] = [
+        r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#,
+        r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-1","XBT/USD"]"#,
+        r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["6060.00000","0.02455000","1534614057.324998","b","m",""]],"trade","XBT/USD"]"#,
+        r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#,
+        r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-10","XBT/USD"]"#,
+        r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738","r"]],"c":"974942666"},"book-10","XBT/USD"]"#,
+        r#"{"event":"heartbeat"}"#,
+        r#"{"connectionID":8628615390848610000,"event":"systemStatus","status":"online","version":"1.0.0"}"#,
+    ];
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        for (seq, text) in FRAMES.iter().enumerate() {
+            let resp: Resp = serde_json::from_str(text)?;
+            let header = Header {
+                session: 7,
+                seq: seq as u64,
+                received: Timestamp::from_micros(1542057400123456),
+            };
+            let mut frame = vec![];
+            encode(&header, &resp, &mut frame)?;
+            assert_eq!((header, resp), decode(&frame)?, "{}", text);
+            // Market data is smaller than its json.
+            if !text.starts_with('{') {
+                assert!(frame.len() - HEADER_LEN < text.len(), "{}", text);
+            }
+            assert!(decode(&frame[..frame.len() - 1]).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_other_versions() -> Result<()> {
+        let header = Header {
+            session: 1,
+            seq: 1,
+            received: Timestamp::from_secs(1),
+        };
+        let mut frame = vec![];
+        encode(&header, &Resp::Heartbeat, &mut frame)?;
+        frame[2] = VERSION + 1;
+        assert!(decode(&frame).is_err());
+        assert!(decode(b"{}").is_err());
+        Ok(())
+    }
+}
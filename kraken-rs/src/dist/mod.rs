@@ -0,0 +1,170 @@
+pub mod codec;
+#[cfg(unix)]
+mod shm;
+mod udp;
+
+#[cfg(unix)]
+pub use self::shm::{ShmPublisher, ShmSubscriber};
+pub use self::udp::{UdpPublisher, UdpSubscriber};
+
+use crate::resp::Resp;
+use crate::time::Timestamp;
+use anyhow::Result;
+use codec::Header;
+use tracing::warn;
+
+/// A response as received by a subscriber of a
+/// [`UdpPublisher`] or [`ShmPublisher`]. Sequence numbers count
+/// from one for each publisher session so that subscribers can
+/// tell when they have missed messages, whether a dropped
+/// datagram or a reader lapped by the ring's writer.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Message {
+    pub session: u64,
+    pub seq: u64,
+    /// When the publisher received the response from Kraken.
+    pub received: Timestamp,
+    pub resp: Resp,
+    /// Messages missed between the previous one and this one.
+    pub missed: u64,
+}
+
+/// Tracks the sequence numbers a subscriber has seen. Messages
+/// from before the last one seen are stale, a duplicate or
+/// reordered datagram, and should be dropped. The first message
+/// of a session is never counted as a gap, since there is no
+/// telling what came before it.
+#[derive(Debug, Clone, Default)]
+pub struct GapDetector {
+    session: Option<u64>,
+    next: u64,
+    missed: u64,
+    gaps: u64,
+    stale: u64,
+}
+
+impl GapDetector {
+    pub fn new() -> GapDetector {
+        GapDetector::default()
+    }
+
+    /// The number of messages missed just before this one, or
+    /// `None` if it is stale.
+    pub fn check(&mut self, session: u64, seq: u64) -> Option<u64> {
+        if self.session != Some(session) {
+            self.session = Some(session);
+            self.next = seq + 1;
+            return Some(0);
+        }
+        if seq < self.next {
+            self.stale += 1;
+            return None;
+        }
+        let missed = seq - self.next;
+        if missed > 0 {
+            self.missed += missed;
+            self.gaps += 1;
+        }
+        self.next = seq + 1;
+        Some(missed)
+    }
+
+    /// Messages missed in total.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// The number of gaps the missed messages fell in.
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+
+    /// Stale messages dropped.
+    pub fn stale(&self) -> u64 {
+        self.stale
+    }
+
+    /// Decode a frame, returning the message unless it is stale.
+    pub(crate) fn accept(&mut self, frame: &[u8]) -> Result<Option<Message>> {
+        let (header, resp) = codec::decode(frame)?;
+        Ok(self
+            .check(header.session, header.seq)
+            .map(|missed| Message {
+                session: header.session,
+                seq: header.seq,
+                received: header.received,
+                resp,
+                missed,
+            }))
+    }
+}
+
+/// Numbers and encodes a publisher's frames.
+#[derive(Debug)]
+pub(crate) struct Framer {
+    session: u64,
+    seq: u64,
+    frame: Vec<u8>,
+    skipped: u64,
+}
+
+impl Framer {
+    pub(crate) fn new() -> Framer {
+        Framer {
+            // Unique enough, publishers aren't started twice
+            // in a microsecond.
+            session: Timestamp::now().as_micros(),
+            seq: 0,
+            frame: Vec::with_capacity(1024),
+            skipped: 0,
+        }
+    }
+
+    pub(crate) fn session(&self) -> u64 {
+        self.session
+    }
+
+    /// Encode the next frame, returning its sequence number and
+    /// bytes.
+    pub(crate) fn frame(&mut self, resp: &Resp, received: Timestamp) -> Result<(u64, &[u8])> {
+        let header = Header {
+            session: self.session,
+            seq: self.seq + 1,
+            received,
+        };
+        self.frame.clear();
+        codec::encode(&header, resp, &mut self.frame)?;
+        self.seq = header.seq;
+        Ok((header.seq, &self.frame))
+    }
+
+    /// Drop a frame too big to publish. Its sequence number is
+    /// left unused, so subscribers see a gap.
+    pub(crate) fn skip(&mut self, seq: u64, len: usize, limit: usize) {
+        self.skipped += 1;
+        warn!(seq, len, limit, "message too big to publish, skipped");
+    }
+
+    pub(crate) fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detects_gaps() {
+        let mut gaps = GapDetector::new();
+        assert_eq!(Some(0), gaps.check(1, 5));
+        assert_eq!(Some(0), gaps.check(1, 6));
+        assert_eq!(Some(3), gaps.check(1, 10));
+        assert_eq!(None, gaps.check(1, 8));
+        assert_eq!(None, gaps.check(1, 10));
+        assert_eq!(Some(1), gaps.check(1, 12));
+        // A new publisher starts again.
+        assert_eq!(Some(0), gaps.check(2, 1));
+        assert_eq!((4, 2, 2), (gaps.missed(), gaps.gaps(), gaps.stale()));
+    }
+}
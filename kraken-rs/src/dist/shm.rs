@@ -0,0 +1,458 @@
+use super::codec::HEADER_LEN;
+use super::{Framer, GapDetector, Message};
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::{anyhow, bail, Result};
+use memmap2::{MmapOptions, MmapRaw};
+use std::fs::{File, OpenOptions};
+use std::hint;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const MAGIC: [u8; 8] = *b"KRAKENRB";
+
+// The ring's header, then its slots. A session of zero means
+// the ring is being set up.
+const RING_HEADER: u64 = 64;
+const SLOTS_AT: u64 = 8;
+const SLOT_BYTES_AT: u64 = 16;
+const SESSION_AT: u64 = 24;
+const LAST_AT: u64 = 32;
+
+// Each slot starts with the sequence number of the frame in it,
+// or one of these, then the frame's length as a u32.
+const EMPTY: u64 = 0;
+const WRITING: u64 = u64::MAX;
+const SLOT_HEADER: usize = 12;
+
+// How long `recv` polls for before it starts sleeping, and the
+// longest it sleeps between polls.
+const SPINS: u32 = 100;
+const MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Writes each response to a ring buffer in a file mapped into
+/// memory, normally on a RAM backed filesystem such as
+/// `/dev/shm`, for [`ShmSubscriber`]s in other processes to
+/// read without any syscalls. The ring has a fixed number of
+/// slots of a fixed size and the oldest message is overwritten
+/// by each new one, a subscriber which falls a whole ring
+/// behind misses messages. A response too big for a slot, such
+/// as a snapshot of a deep book, is logged and skipped, leaving
+/// a gap in the sequence numbers.
+///
+/// Slots are marked as being written while they change and
+/// then with the message's sequence number, so readers can
+/// tell a complete message from one being overwritten.
+#[derive(Debug)]
+pub struct ShmPublisher {
+    ring: Ring,
+    slots: u64,
+    slot_bytes: u64,
+    framer: Framer,
+}
+
+impl ShmPublisher {
+    pub const DEFAULT_SLOTS: u64 = 1024;
+    pub const DEFAULT_SLOT_BYTES: u64 = 64 * 1024;
+
+    /// Create the ring, or take over an existing one which
+    /// subscribers may already have open. Slots are a multiple
+    /// of 8 bytes. An existing file is never made smaller, since
+    /// subscribers have it mapped.
+    pub fn create<P: AsRef<Path>>(path: P, slots: u64, slot_bytes: u64) -> Result<ShmPublisher> {
+        let min = (SLOT_HEADER + HEADER_LEN) as u64;
+        if slots == 0 || slot_bytes < min || !slot_bytes.is_multiple_of(8) {
+            bail!(
+                "A ring needs slots of a multiple of 8 bytes, at least {}",
+                min
+            );
+        }
+        let len = slots
+            .checked_mul(slot_bytes)
+            .and_then(|slots| slots.checked_add(RING_HEADER))
+            .ok_or_else(|| anyhow!("Ring too big"))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < len {
+            file.set_len(len)?;
+        }
+        let ring = Ring {
+            map: MmapOptions::new().map_raw(&file)?,
+        };
+        // Subscribers wait until the session is set, after the
+        // slots are empty.
+        ring.word(SESSION_AT).store(0, Ordering::SeqCst);
+        for slot in 0..slots {
+            ring.word(RING_HEADER + slot * slot_bytes)
+                .store(EMPTY, Ordering::Relaxed);
+        }
+        ring.write(0, &MAGIC);
+        ring.word(SLOTS_AT).store(slots, Ordering::Relaxed);
+        ring.word(SLOT_BYTES_AT)
+            .store(slot_bytes, Ordering::Relaxed);
+        ring.word(LAST_AT).store(0, Ordering::Relaxed);
+        let framer = Framer::new();
+        ring.word(SESSION_AT)
+            .store(framer.session(), Ordering::Release);
+        Ok(ShmPublisher {
+            ring,
+            slots,
+            slot_bytes,
+            framer,
+        })
+    }
+
+    pub fn session(&self) -> u64 {
+        self.framer.session()
+    }
+
+    /// Messages too big for a slot which were skipped.
+    pub fn skipped(&self) -> u64 {
+        self.framer.skipped()
+    }
+
+    /// Write a response, returning its sequence number unless it
+    /// was too big for a slot.
+    pub fn publish(&mut self, resp: &Resp, received: Timestamp) -> Result<Option<u64>> {
+        let (seq, frame) = self.framer.frame(resp, received)?;
+        let limit = self.slot_bytes as usize - SLOT_HEADER;
+        if frame.len() > limit {
+            let len = frame.len();
+            self.framer.skip(seq, len, limit);
+            return Ok(None);
+        }
+        let at = slot_at(seq, self.slots, self.slot_bytes);
+        let marker = self.ring.word(at);
+        marker.store(WRITING, Ordering::Relaxed);
+        // Readers who see any of the new frame see it marked.
+        fence(Ordering::Release);
+        self.ring.write(at + 8, &(frame.len() as u32).to_le_bytes());
+        self.ring.write(at + SLOT_HEADER as u64, frame);
+        marker.store(seq, Ordering::Release);
+        self.ring.word(LAST_AT).store(seq, Ordering::Release);
+        Ok(Some(seq))
+    }
+}
+
+impl Sink for ShmPublisher {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        self.publish(resp, received).map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the responses written by a [`ShmPublisher`], starting
+/// with the next one written after it is opened. When lapped by
+/// the publisher it skips ahead to the oldest message still in
+/// the ring, and a publisher restarting on the same file is
+/// followed from its first message.
+#[derive(Debug)]
+pub struct ShmSubscriber {
+    file: File,
+    ring: Ring,
+    slots: u64,
+    slot_bytes: u64,
+    // Zero until the ring has been set up.
+    session: u64,
+    next: u64,
+    gaps: GapDetector,
+    buf: Vec<u8>,
+}
+
+impl ShmSubscriber {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ShmSubscriber> {
+        let file = File::open(path)?;
+        let ring = Ring::read_only(&file)?;
+        let mut magic = [0; 8];
+        ring.read(0, &mut magic);
+        if magic != MAGIC {
+            bail!("Not a ring buffer");
+        }
+        let mut subscriber = ShmSubscriber {
+            file,
+            ring,
+            slots: 0,
+            slot_bytes: 0,
+            session: 0,
+            next: 1,
+            gaps: GapDetector::new(),
+            buf: vec![],
+        };
+        let session = subscriber.ring.word(SESSION_AT).load(Ordering::Acquire);
+        if subscriber.restart(session)? {
+            subscriber.next = subscriber.ring.word(LAST_AT).load(Ordering::Acquire) + 1;
+        }
+        Ok(subscriber)
+    }
+
+    /// The next message if one has been written.
+    pub fn try_recv(&mut self) -> Result<Option<Message>> {
+        loop {
+            let session = self.ring.word(SESSION_AT).load(Ordering::Acquire);
+            if session != self.session {
+                if !self.restart(session)? {
+                    return Ok(None);
+                }
+                self.next = 1;
+            }
+            let at = slot_at(self.next, self.slots, self.slot_bytes);
+            let marker = self.ring.word(at).load(Ordering::Acquire);
+            if marker == self.next {
+                let mut len = [0; 4];
+                self.ring.read(at + 8, &mut len);
+                let len = u32::from_le_bytes(len) as usize;
+                let fits = SLOT_HEADER + len <= self.slot_bytes as usize;
+                if fits {
+                    self.buf.resize(len, 0);
+                    self.ring.read(at + SLOT_HEADER as u64, &mut self.buf);
+                }
+                // Only a frame left as it was read is whole.
+                fence(Ordering::Acquire);
+                if self.ring.word(at).load(Ordering::Relaxed) != marker {
+                    continue;
+                }
+                if !fits {
+                    bail!("Message {} is longer than its slot", marker);
+                }
+                self.next += 1;
+                match self.gaps.accept(&self.buf)? {
+                    Some(message) => return Ok(Some(message)),
+                    None => continue,
+                }
+            }
+            if marker == WRITING {
+                return Ok(None);
+            }
+            let last = self.ring.word(LAST_AT).load(Ordering::Acquire);
+            if last < self.next {
+                return Ok(None);
+            }
+            // Written since the marker was read.
+            if self.ring.word(at).load(Ordering::Acquire) != marker {
+                continue;
+            }
+            // Skipped as too big, or lapped, in which case skip to
+            // the oldest message left.
+            self.next = (last + 1).saturating_sub(self.slots).max(self.next + 1);
+        }
+    }
+
+    /// Wait for the next message, polling for a while and then
+    /// sleeping for up to a millisecond between polls. Use
+    /// [`ShmSubscriber::try_recv`] to poll without sleeping.
+    pub fn recv(&mut self) -> Result<Message> {
+        let mut polls = 0;
+        let mut backoff = Duration::from_micros(10);
+        loop {
+            if let Some(message) = self.try_recv()? {
+                return Ok(message);
+            }
+            if polls < SPINS {
+                polls += 1;
+                hint::spin_loop();
+            } else {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    /// The sequence numbers seen so far.
+    pub fn gaps(&self) -> &GapDetector {
+        &self.gaps
+    }
+
+    // Follow a new session once the ring is set up, mapping the
+    // file again since it may have grown.
+    fn restart(&mut self, session: u64) -> Result<bool> {
+        if session == 0 {
+            return Ok(false);
+        }
+        self.ring = Ring::read_only(&self.file)?;
+        let slots = self.ring.word(SLOTS_AT).load(Ordering::Relaxed);
+        let slot_bytes = self.ring.word(SLOT_BYTES_AT).load(Ordering::Relaxed);
+        let len = slots
+            .checked_mul(slot_bytes)
+            .and_then(|slots| slots.checked_add(RING_HEADER));
+        let valid = slots > 0
+            && slot_bytes.is_multiple_of(8)
+            && slot_bytes as usize > SLOT_HEADER
+            && len.is_some_and(|len| len <= self.ring.len());
+        // Set up again while being read, try again later.
+        if self.ring.word(SESSION_AT).load(Ordering::Acquire) != session {
+            return Ok(false);
+        }
+        if !valid {
+            bail!("Ring buffer has an invalid layout");
+        }
+        self.slots = slots;
+        self.slot_bytes = slot_bytes;
+        self.session = session;
+        Ok(true)
+    }
+}
+
+fn slot_at(seq: u64, slots: u64, slot_bytes: u64) -> u64 {
+    RING_HEADER + (seq.wrapping_sub(1) % slots) * slot_bytes
+}
+
+// The mapped file, which other processes change underneath us,
+// so it is only accessed through raw pointers. Markers and the
+// header are 8 byte words accessed atomically, frames are
+// copied in and out between setting and checking the markers.
+#[derive(Debug)]
+struct Ring {
+    map: MmapRaw,
+}
+
+impl Ring {
+    fn read_only(file: &File) -> Result<Ring> {
+        let ring = Ring {
+            map: MmapOptions::new().map_raw_read_only(file)?,
+        };
+        if ring.len() < RING_HEADER {
+            bail!("Not a ring buffer");
+        }
+        Ok(ring)
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    fn word(&self, at: u64) -> &AtomicU64 {
+        assert!(at.is_multiple_of(8) && at + 8 <= self.len());
+        // SAFETY: the word is within the mapping and aligned,
+        // since mappings are page aligned, and is only accessed
+        // atomically by every process.
+        unsafe { &*(self.map.as_ptr().add(at as usize) as *const AtomicU64) }
+    }
+
+    fn read(&self, at: u64, buf: &mut [u8]) {
+        assert!(at + buf.len() as u64 <= self.len());
+        // SAFETY: within the mapping. The publisher may be
+        // writing these bytes, readers check the slot's marker
+        // afterwards to discard anything torn.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.map.as_ptr().add(at as usize),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        }
+    }
+
+    // Only called by the publisher, on a writable mapping.
+    fn write(&self, at: u64, bytes: &[u8]) {
+        assert!(at + bytes.len() as u64 <= self.len());
+        // SAFETY: within the mapping, which there is only one
+        // writer of.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.map.as_mut_ptr().add(at as usize),
+                bytes.len(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-1","XBT/USD"]"#;
+
+    #[test]
+    fn reads_what_is_written() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ring");
+        let mut publisher = ShmPublisher::create(&path, 4, 1024)?;
+        let received = Timestamp::from_secs(1542057400);
+        let ohlc: Resp = serde_json::from_str(OHLC)?;
+        publisher.publish(&Resp::Heartbeat, received)?;
+
+        // Starts after what was already written.
+        let mut subscriber = ShmSubscriber::open(&path)?;
+        assert_eq!(None, subscriber.try_recv()?);
+        assert_eq!(Some(2), publisher.publish(&ohlc, received)?);
+        let message = subscriber.recv()?;
+        assert_eq!((2, 0, &ohlc), (message.seq, message.missed, &message.resp));
+        assert_eq!(None, subscriber.try_recv()?);
+
+        // Lapped, the oldest four are left.
+        for _ in 0..6 {
+            publisher.publish(&ohlc, received)?;
+        }
+        let message = subscriber.recv()?;
+        assert_eq!((5, 2), (message.seq, message.missed));
+        let seqs: Vec<_> = (0..3).map(|_| subscriber.recv().unwrap().seq).collect();
+        assert_eq!(vec![6, 7, 8], seqs);
+        assert_eq!(2, subscriber.gaps().missed());
+        Ok(())
+    }
+
+    #[test]
+    fn follows_new_publishers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ring");
+        let received = Timestamp::from_secs(1542057400);
+        let ohlc: Resp = serde_json::from_str(OHLC)?;
+        let mut publisher = ShmPublisher::create(&path, 4, 1024)?;
+        let mut subscriber = ShmSubscriber::open(&path)?;
+
+        // Growing the ring, then shrinking it, which leaves the
+        // file as it is under the subscriber.
+        for (slots, slot_bytes) in [(8, 2048), (2, 512)] {
+            for _ in 0..3 {
+                publisher.publish(&ohlc, received)?;
+            }
+            let seqs: Vec<_> = (0..3).map(|_| subscriber.recv().unwrap().seq).collect();
+            assert_eq!(vec![1, 2, 3], seqs);
+            publisher = ShmPublisher::create(&path, slots, slot_bytes)?;
+            assert_eq!(None, subscriber.try_recv()?);
+            publisher.publish(&ohlc, received)?;
+            let message = subscriber.recv()?;
+            assert_eq!(
+                (publisher.session(), 1, 0),
+                (message.session, message.seq, message.missed)
+            );
+            // Carry on from where the new publisher is.
+            publisher = ShmPublisher::create(&path, slots, slot_bytes)?;
+        }
+        assert_eq!(64 + 8 * 2048, fs::metadata(&path)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn skips_messages_too_big_for_a_slot() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ring");
+        let mut publisher = ShmPublisher::create(&path, 4, 64)?;
+        let mut subscriber = ShmSubscriber::open(&path)?;
+        let ohlc: Resp = serde_json::from_str(OHLC)?;
+        publisher.write(&Resp::Heartbeat, Timestamp::now())?;
+        assert_eq!(1, subscriber.recv()?.seq);
+        assert_eq!(None, publisher.publish(&ohlc, Timestamp::now())?);
+        assert_eq!(1, publisher.skipped());
+        publisher.write(&Resp::Heartbeat, Timestamp::now())?;
+        let message = subscriber.recv()?;
+        assert_eq!((3, 1), (message.seq, message.missed));
+
+        assert!(ShmPublisher::create(dir.path().join("small"), 4, 16).is_err());
+        assert!(ShmPublisher::create(dir.path().join("odd"), 4, 100).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+use super::{Framer, GapDetector, Message};
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::{anyhow, bail, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use tracing::warn;
+
+/// The largest payload of a UDP datagram over IPv4, IPv6
+/// allows 40 bytes more but this keeps to the smaller.
+const MAX_DATAGRAM: usize = 65507;
+
+/// Sends each response as one datagram to a UDP address,
+/// usually a multicast group such as "239.1.1.1:5000" so any
+/// number of [`UdpSubscriber`]s can receive them. Datagrams
+/// larger than the network's MTU are fragmented, which is fine
+/// on one machine, but over a network losing any fragment
+/// loses the whole message. A response too big for a datagram,
+/// such as a snapshot of a deep book, is logged and skipped,
+/// leaving a gap in the sequence numbers. So is one which
+/// fails to send, UDP being best effort anyway.
+#[derive(Debug)]
+pub struct UdpPublisher {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    framer: Framer,
+    failed_sends: u64,
+}
+
+impl UdpPublisher {
+    pub fn new<A: ToSocketAddrs>(addr: A) -> Result<UdpPublisher> {
+        let addr = resolve(addr)?;
+        let unspecified: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((unspecified, 0))?;
+        Ok(UdpPublisher {
+            socket,
+            addr,
+            framer: Framer::new(),
+            failed_sends: 0,
+        })
+    }
+
+    /// Hops multicast datagrams may take, one by default which
+    /// keeps them on the local network.
+    pub fn multicast_ttl(self, ttl: u32) -> Result<UdpPublisher> {
+        match self.addr {
+            SocketAddr::V4(_) => self.socket.set_multicast_ttl_v4(ttl)?,
+            SocketAddr::V6(_) => bail!("Multicast TTL is only supported for IPv4"),
+        }
+        Ok(self)
+    }
+
+    pub fn session(&self) -> u64 {
+        self.framer.session()
+    }
+
+    /// Messages too big for a datagram which were skipped.
+    pub fn skipped(&self) -> u64 {
+        self.framer.skipped()
+    }
+
+    /// Messages dropped because sending them failed.
+    pub fn failed_sends(&self) -> u64 {
+        self.failed_sends
+    }
+
+    /// Send a response, returning its sequence number unless it
+    /// was too big for a datagram or failed to send.
+    pub fn publish(&mut self, resp: &Resp, received: Timestamp) -> Result<Option<u64>> {
+        let (seq, frame) = self.framer.frame(resp, received)?;
+        if frame.len() > MAX_DATAGRAM {
+            let len = frame.len();
+            self.framer.skip(seq, len, MAX_DATAGRAM);
+            return Ok(None);
+        }
+        if let Err(e) = self.socket.send_to(frame, self.addr) {
+            self.failed_sends += 1;
+            warn!(seq, error = %e, "udp send failed, message dropped");
+            return Ok(None);
+        }
+        Ok(Some(seq))
+    }
+}
+
+impl Sink for UdpPublisher {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        self.publish(resp, received).map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Receives the responses sent by a [`UdpPublisher`]. The
+/// socket is bound with the address reused, so several
+/// subscribers on one machine can join the same group.
+#[derive(Debug)]
+pub struct UdpSubscriber {
+    socket: UdpSocket,
+    gaps: GapDetector,
+    buf: Vec<u8>,
+}
+
+impl UdpSubscriber {
+    /// Join the multicast group the address is in, or listen on
+    /// it if it is not a multicast address.
+    pub fn join<A: ToSocketAddrs>(addr: A) -> Result<UdpSubscriber> {
+        let addr = resolve(addr)?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        let bind: SocketAddr = match addr.ip() {
+            IpAddr::V4(group) if group.is_multicast() => {
+                socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+                (Ipv4Addr::UNSPECIFIED, addr.port()).into()
+            }
+            IpAddr::V6(group) if group.is_multicast() => {
+                socket.join_multicast_v6(&group, 0)?;
+                (Ipv6Addr::UNSPECIFIED, addr.port()).into()
+            }
+            _ => addr,
+        };
+        socket.bind(&bind.into())?;
+        Ok(UdpSubscriber {
+            socket: socket.into(),
+            gaps: GapDetector::new(),
+            buf: vec![0; MAX_DATAGRAM],
+        })
+    }
+
+    /// The address bound to, for finding the port when bound to
+    /// port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Wait at most this long in [`UdpSubscriber::recv`], forever
+    /// if `None`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    /// Wait for the next message, dropping stale ones.
+    pub fn recv(&mut self) -> Result<Message> {
+        loop {
+            let len = self.socket.recv(&mut self.buf)?;
+            if let Some(message) = self.gaps.accept(&self.buf[..len])? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// The sequence numbers seen so far.
+    pub fn gaps(&self) -> &GapDetector {
+        &self.gaps
+    }
+}
+
+fn resolve<A: ToSocketAddrs>(addr: A) -> Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("No address to send to"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sends_to_subscribers() -> Result<()> {
+        let mut subscriber = UdpSubscriber::join("127.0.0.1:0")?;
+        subscriber.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut publisher = UdpPublisher::new(subscriber.local_addr()?)?;
+        let received = Timestamp::from_secs(1542057400);
+        let spread: Resp = serde_json::from_str(
+            r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#,
+        )?;
+        assert_eq!(Some(1), publisher.publish(&spread, received)?);
+        assert_eq!(
+            Message {
+                session: publisher.session(),
+                seq: 1,
+                received,
+                resp: spread.clone(),
+                missed: 0,
+            },
+            subscriber.recv()?
+        );
+
+        // The subscriber is told of the lost message.
+        let lost = publisher.framer.frame(&Resp::Heartbeat, received)?.0;
+        assert_eq!(2, lost);
+        publisher.publish(&spread, received)?;
+        let message = subscriber.recv()?;
+        assert_eq!((3, 1), (message.seq, message.missed));
+        assert_eq!(1, subscriber.gaps().missed());
+
+        // As is one too big to send.
+        let level = r#"["5541.30000","2.50700000","1534614248.123678"]"#;
+        let levels = vec![level; 5000].join(",");
+        let book: Resp = serde_json::from_str(&format!(
+            r#"[0,{{"as":[{0}],"bs":[{0}]}},"book-1000","XBT/USD"]"#,
+            levels
+        ))?;
+        assert_eq!(None, publisher.publish(&book, received)?);
+        publisher.write(&spread, received)?;
+        let message = subscriber.recv()?;
+        assert_eq!(
+            (5, 1, 1),
+            (message.seq, message.missed, publisher.skipped())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn drops_failed_sends() -> Result<()> {
+        // Broadcast isn't enabled on the socket.
+        let mut publisher = UdpPublisher::new("255.255.255.255:5000")?;
+        let received = Timestamp::from_secs(1542057400);
+        assert_eq!(None, publisher.publish(&Resp::Heartbeat, received)?);
+        publisher.write(&Resp::Heartbeat, received)?;
+        assert_eq!(2, publisher.failed_sends());
+        Ok(())
+    }
+}
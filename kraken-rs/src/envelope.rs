@@ -0,0 +1,335 @@
+use crate::resp::book::Book;
+use crate::resp::event::{
+    AddOrderStatus, CancelOrderStatus, Pong, RequestError, SubscriptionStatus, SystemStatus,
+};
+use crate::resp::ohlc::Ohlc;
+use crate::resp::private::{OpenOrders, OwnTrades};
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::Trades;
+use crate::resp::Resp;
+use crate::time::Timestamp;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::ser::{self, Serialize, Serializer};
+use serde_derive::Serialize;
+use serde_json::Value;
+
+/// Version of the envelope format. It is bumped whenever a
+/// change could break a consumer, such as a field being
+/// removed, renamed or changing type. Adding a field or a new
+/// type of message does not change the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A response in the normalized form we publish for other
+/// services, serialized as an object with the fields
+///
+/// - `version`: [`SCHEMA_VERSION`]
+/// - `type`: the channel or event name e.g. "ticker", "book" or
+///   "systemStatus", ohlc and book depths are not included
+/// - `pair`: the pair the message is about, or null
+/// - `exchangeTime`: Kraken's time for the message, or null for
+///   those without one e.g. tickers
+/// - `receiveTime`: when the message was received
+/// - `payload`: the response as its own `Serialize` writes it,
+///   absent for heartbeats
+///
+/// Every number in the payload is written as a decimal string
+/// so that no precision is lost by consumers parsing numbers
+/// as floats, times are strings of seconds e.g.
+/// "1542057314.748456". See [`schema`] for the JSON Schema.
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope<'a> {
+    pub resp: &'a Resp,
+    pub receive_time: Timestamp,
+}
+
+impl<'a> Envelope<'a> {
+    pub fn new(resp: &'a Resp, receive_time: Timestamp) -> Envelope<'a> {
+        Envelope { resp, receive_time }
+    }
+
+    /// The `type` of the envelope.
+    pub fn kind(&self) -> &'static str {
+        match self.resp {
+            Resp::Ticker(_) => "ticker",
+            Resp::Ohlc(_) => "ohlc",
+            Resp::Trade(_) => "trade",
+            Resp::Spread(_) => "spread",
+            Resp::Book(_) => "book",
+            Resp::OwnTrades(_) => "ownTrades",
+            Resp::OpenOrders(_) => "openOrders",
+            Resp::Heartbeat => "heartbeat",
+            Resp::Pong(_) => "pong",
+            Resp::SystemStatus(_) => "systemStatus",
+            Resp::SubscriptionStatus(_) => "subscriptionStatus",
+            Resp::AddOrderStatus(_) => "addOrderStatus",
+            Resp::CancelOrderStatus(_) => "cancelOrderStatus",
+            Resp::RequestError(_) => "error",
+        }
+    }
+
+    pub fn pair(&self) -> Option<&'a str> {
+        match self.resp {
+            Resp::Ticker(t) => Some(&t.pair),
+            Resp::Ohlc(o) => Some(&o.pair),
+            Resp::Trade(t) => Some(&t.pair),
+            Resp::Spread(s) => Some(&s.pair),
+            Resp::Book(b) => Some(&b.pair),
+            Resp::SubscriptionStatus(s) => s.pair.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The latest time Kraken gives in the message: the last
+    /// update of a candle, the last of a batch of trades or
+    /// the newest level in a book message.
+    pub fn exchange_time(&self) -> Option<Timestamp> {
+        match self.resp {
+            Resp::Ohlc(o) => Some(o.time),
+            Resp::Trade(t) => t.trades.iter().map(|t| t.time).max(),
+            Resp::Spread(s) => Some(s.time),
+            Resp::Book(b) => b.asks.iter().chain(&b.bids).map(|l| l.time).max(),
+            Resp::OwnTrades(t) => t.trades.iter().map(|t| t.time).max(),
+            _ => None,
+        }
+    }
+
+    fn normalized(&self) -> Normalized<'a> {
+        Normalized {
+            version: SCHEMA_VERSION,
+            pair: self.pair(),
+            exchange_time: self.exchange_time(),
+            receive_time: self.receive_time,
+            payload: match self.resp {
+                Resp::Ticker(t) => Payload::Ticker(t),
+                Resp::Ohlc(o) => Payload::Ohlc(o),
+                Resp::Trade(t) => Payload::Trade(t),
+                Resp::Spread(s) => Payload::Spread(s),
+                Resp::Book(b) => Payload::Book(b),
+                Resp::OwnTrades(t) => Payload::OwnTrades(t),
+                Resp::OpenOrders(o) => Payload::OpenOrders(o),
+                Resp::Heartbeat => Payload::Heartbeat,
+                Resp::Pong(p) => Payload::Pong(p),
+                Resp::SystemStatus(s) => Payload::SystemStatus(s),
+                Resp::SubscriptionStatus(s) => Payload::SubscriptionStatus(s),
+                Resp::AddOrderStatus(s) => Payload::AddOrderStatus(s),
+                Resp::CancelOrderStatus(s) => Payload::CancelOrderStatus(s),
+                Resp::RequestError(e) => Payload::RequestError(e),
+            },
+        }
+    }
+}
+
+impl Serialize for Envelope<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut envelope = serde_json::to_value(self.normalized()).map_err(ser::Error::custom)?;
+        if let Some(payload) = envelope.get_mut("payload") {
+            numbers_to_strings(payload);
+        }
+        envelope.serialize(serializer)
+    }
+}
+
+// The envelope before numbers in the payload are made strings,
+// the schema is generated from this.
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Envelope"))]
+#[serde(rename_all = "camelCase")]
+struct Normalized<'a> {
+    version: u32,
+    pair: Option<&'a str>,
+    exchange_time: Option<Timestamp>,
+    receive_time: Timestamp,
+    #[serde(flatten)]
+    payload: Payload<'a>,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(tag = "type", content = "payload")]
+enum Payload<'a> {
+    #[serde(rename = "ticker")]
+    Ticker(&'a TickerState),
+    #[serde(rename = "ohlc")]
+    Ohlc(&'a Ohlc),
+    #[serde(rename = "trade")]
+    Trade(&'a Trades),
+    #[serde(rename = "spread")]
+    Spread(&'a Spread),
+    #[serde(rename = "book")]
+    Book(&'a Book),
+    #[serde(rename = "ownTrades")]
+    OwnTrades(&'a OwnTrades),
+    #[serde(rename = "openOrders")]
+    OpenOrders(&'a OpenOrders),
+    #[serde(rename = "heartbeat")]
+    Heartbeat,
+    #[serde(rename = "pong")]
+    Pong(&'a Pong),
+    #[serde(rename = "systemStatus")]
+    SystemStatus(&'a SystemStatus),
+    #[serde(rename = "subscriptionStatus")]
+    SubscriptionStatus(&'a SubscriptionStatus),
+    #[serde(rename = "addOrderStatus")]
+    AddOrderStatus(&'a AddOrderStatus),
+    #[serde(rename = "cancelOrderStatus")]
+    CancelOrderStatus(&'a CancelOrderStatus),
+    #[serde(rename = "error")]
+    RequestError(&'a RequestError),
+}
+
+fn numbers_to_strings(value: &mut Value) {
+    match value {
+        Value::Number(n) => *value = Value::String(n.to_string()),
+        Value::Array(values) => values.iter_mut().for_each(numbers_to_strings),
+        Value::Object(fields) => fields.values_mut().for_each(numbers_to_strings),
+        _ => {}
+    }
+}
+
+/// The JSON Schema of serialized envelopes, generated from the
+/// response types. Numbers in the payload types are described
+/// as the decimal strings they are written as.
+#[cfg(feature = "schema")]
+pub fn schema() -> Value {
+    let mut schema =
+        serde_json::to_value(schemars::schema_for!(Normalized)).expect("schemas always serialize");
+    // Only the payload types are kept in definitions.
+    if let Some(definitions) = schema.get_mut("definitions") {
+        number_schemas_to_strings(definitions);
+    }
+    schema
+}
+
+#[cfg(feature = "schema")]
+fn number_schemas_to_strings(schema: &mut Value) {
+    let is_number = |t: &Value| t == "integer" || t == "number";
+    if let Some(fields) = schema.as_object_mut() {
+        let numeric = match fields.get_mut("type") {
+            Some(Value::Array(types)) => {
+                let numeric = types.iter().any(is_number);
+                types.retain(|t| !is_number(t));
+                if numeric {
+                    types.insert(0, Value::from("string"));
+                }
+                numeric
+            }
+            Some(t) if is_number(t) => {
+                *t = Value::from("string");
+                true
+            }
+            _ => false,
+        };
+        if numeric {
+            fields.remove("format");
+            fields.remove("minimum");
+            fields.remove("maximum");
+            match fields.get_mut("enum") {
+                Some(values) => numbers_to_strings(values),
+                None => {
+                    fields.insert("pattern".into(), Value::from("^-?[0-9]+(\\.[0-9]+)?$"));
+                }
+            }
+        }
+    }
+    match schema {
+        Value::Array(values) => values.iter_mut().for_each(number_schemas_to_strings),
+        Value::Object(fields) => fields.values_mut().for_each(number_schemas_to_strings),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    const RECEIVED: u64 = 1542057400000000;
+
+    fn envelope(frame: &str) -> Result<Value> {
+        let resp: Resp = serde_json::from_str(frame)?;
+        let envelope = Envelope::new(&resp, Timestamp::from_micros(RECEIVED));
+        Ok(serde_json::to_value(envelope)?)
+    }
+
+    #[test]
+    fn ohlc_envelope() -> Result<()> {
+        assert_eq!(
+            json!({
+                "version": 1,
+                "type": "ohlc",
+                "pair": "XBT/USD",
+                "exchangeTime": "1542057314.748456",
+                "receiveTime": "1542057400.000000",
+                "payload": {
+                    "channelId": "42",
+                    "interval": "5",
+                    "pair": "XBT/USD",
+                    "time": "1542057314.748456",
+                    "etime": "1542057360.435743",
+                    "open": "3586.70001",
+                    "high": "3586.70000",
+                    "low": "3586.60001",
+                    "close": "3586.60000",
+                    "vwap": "3586.68894",
+                    "volume": "0.03373000",
+                    "count": "2"
+                }
+            }),
+            envelope(
+                r#"[42,["1542057314.748456","1542057360.435743","3586.70001","3586.70000","3586.60001","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn event_envelopes() -> Result<()> {
+        assert_eq!(
+            json!({
+                "version": 1,
+                "type": "heartbeat",
+                "pair": null,
+                "exchangeTime": null,
+                "receiveTime": "1542057400.000000"
+            }),
+            envelope(r#"{"event":"heartbeat"}"#)?
+        );
+        let status = envelope(
+            r#"{"channelID":10001,"channelName":"ohlc-5","event":"subscriptionStatus","pair":"XBT/EUR","status":"subscribed","subscription":{"interval":5,"name":"ohlc"}}"#,
+        )?;
+        assert_eq!("subscriptionStatus", status["type"]);
+        assert_eq!("XBT/EUR", status["pair"]);
+        assert_eq!("10001", status["payload"]["channelID"]);
+        Ok(())
+    }
+
+    #[test]
+    fn book_exchange_time_is_newest_level() -> Result<()> {
+        let book = envelope(
+            r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738"]]},{"b":[["5540.10000","1.00000000","1534614335.345903","r"]],"c":"974942666"},"book-10","XBT/USD"]"#,
+        )?;
+        assert_eq!("1534614335.345903", book["exchangeTime"]);
+        assert_eq!("974942666", book["payload"]["checksum"]);
+        assert_eq!(true, book["payload"]["bids"][0]["republish"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn schema_is_up_to_date() -> Result<()> {
+        // Run with UPDATE_SCHEMA set to regenerate the file.
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/envelope-v1.json");
+        let generated = serde_json::to_string_pretty(&schema())? + "\n";
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(path, &generated)?;
+        }
+        assert_eq!(std::fs::read_to_string(path)?, generated);
+        Ok(())
+    }
+}
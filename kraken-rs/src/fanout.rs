@@ -0,0 +1,540 @@
+use crate::book::{OrderBook, OutOfSync};
+use crate::envelope::Envelope;
+use crate::req::OhlcInterval;
+use crate::resp::book::PriceLevel;
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::{anyhow, bail, Result};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+use websocket::sync::server::upgrade::IntoWs;
+use websocket::sync::Writer;
+use websocket::OwnedMessage;
+
+/// Candles kept for each pair and interval.
+const MAX_CANDLES: usize = 720;
+
+/// Messages queued for a websocket client before it is
+/// disconnected for being too slow.
+const CLIENT_QUEUE: usize = 1024;
+
+/// Shares one upstream connection with local tools, so they
+/// don't each need their own connection and subscriptions.
+/// Written to as a [`Sink`], usually by the collector, it keeps
+/// the latest state for a REST api served by
+/// [`FanoutServer::serve_http`]:
+///
+/// - `GET /ticker/XBT-USD`: the last ticker envelope
+/// - `GET /spread/XBT-USD`: the last spread envelope
+/// - `GET /candles/XBT-USD/15`: the last 720 candles of the
+///   interval in minutes as envelopes, oldest first
+/// - `GET /book/XBT-USD`: the order book
+/// - `GET /status`: Kraken's system status and the pairs with
+///   data
+///
+/// Pairs are written with a `-` in place of the `/`.
+///
+/// A websocket served by [`FanoutServer::serve_ws`] forwards
+/// every response as an [`Envelope`] to the clients which
+/// subscribed to its type and pair, by sending e.g.
+///
+/// ```json
+/// {"op": "subscribe", "types": ["ticker", "ohlc"], "pairs": ["XBT/USD"]}
+/// ```
+///
+/// Leaving out `types` or `pairs` subscribes to all of them and
+/// responses without a pair, such as system status, match any
+/// pair. `"op": "unsubscribe"` with the same fields undoes a
+/// subscription. Each request is answered with the client's
+/// subscriptions, as `{"subscriptions": [{"type": "ticker",
+/// "pair": "XBT/USD"}]}` where `"*"` stands for all. A client
+/// which falls too far behind is disconnected rather than
+/// holding up the others.
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct FanoutServer {
+    latest: Arc<Mutex<Latest>>,
+    clients: Arc<Mutex<Vec<Client>>>,
+    next_client: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Default)]
+struct Latest {
+    // Envelopes already serialized, as they are served.
+    tickers: BTreeMap<String, String>,
+    spreads: BTreeMap<String, String>,
+    candles: BTreeMap<(String, u32), BTreeMap<Timestamp, String>>,
+    books: BTreeMap<String, OrderBook>,
+    status: Option<String>,
+}
+
+#[derive(Debug)]
+struct Client {
+    id: u64,
+    subscriptions: BTreeSet<(String, String)>,
+    queue: SyncSender<OwnedMessage>,
+    stream: TcpStream,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    op: Op,
+    types: Option<Vec<String>>,
+    pairs: Option<Vec<String>>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    Subscribe,
+    Unsubscribe,
+}
+
+const ALL: &str = "*";
+
+impl FanoutServer {
+    pub fn new() -> FanoutServer {
+        FanoutServer::default()
+    }
+
+    /// Update the latest state and forward the response to the
+    /// websocket clients subscribed to it.
+    pub fn publish(&self, resp: &Resp, received: Timestamp) -> Result<()> {
+        let envelope = Envelope::new(resp, received);
+        let json = serde_json::to_string(&envelope)?;
+        self.update(resp, &json)?;
+        let kind = envelope.kind();
+        let pair = envelope.pair();
+        self.clients.lock().unwrap().retain(|client| {
+            if !client.matches(kind, pair) {
+                return true;
+            }
+            match client.queue.try_send(OwnedMessage::Text(json.clone())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        Ok(())
+    }
+
+    /// The number of connected websocket clients.
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    fn update(&self, resp: &Resp, json: &str) -> Result<()> {
+        let mut latest = self.latest.lock().unwrap();
+        match resp {
+            Resp::Ticker(ticker) => {
+                latest.tickers.insert(ticker.pair.clone(), json.to_owned());
+            }
+            Resp::Spread(spread) => {
+                latest.spreads.insert(spread.pair.clone(), json.to_owned());
+            }
+            Resp::Ohlc(ohlc) => {
+                let key = (ohlc.pair.clone(), ohlc.interval.minutes());
+                let candles = latest.candles.entry(key).or_default();
+                candles.insert(ohlc.start(), json.to_owned());
+                while candles.len() > MAX_CANDLES {
+                    candles.pop_first();
+                }
+            }
+            Resp::Book(book) => {
+                let depth = book.depth;
+                let order_book = latest
+                    .books
+                    .entry(book.pair.clone())
+                    .or_insert_with(|| OrderBook::new(depth));
+                if order_book.depth() != depth {
+                    *order_book = OrderBook::new(depth);
+                }
+                // Served empty until the next snapshot.
+                match order_book.apply(book) {
+                    Err(e) if e.is::<OutOfSync>() => {
+                        warn!(pair = %book.pair, error = %e, "book out of sync")
+                    }
+                    applied => applied?,
+                }
+            }
+            Resp::SystemStatus(_) => latest.status = Some(json.to_owned()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Serve the REST api from a background thread, returning
+    /// the address bound to.
+    pub fn serve_http<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let server = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.respond(stream));
+            }
+        });
+        Ok(addr)
+    }
+
+    fn respond(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers, requests have no body.
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(path)) => match self.route(path) {
+                Some(body) => ("200 OK", body),
+                None => ("404 Not Found", json!({"error": "Not found"}).to_string()),
+            },
+            _ => (
+                "405 Method Not Allowed",
+                json!({"error": "Only GET is supported"}).to_string(),
+            ),
+        };
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+
+    fn route(&self, path: &str) -> Option<String> {
+        let path = path.split('?').next().unwrap_or(path);
+        let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+        let latest = self.latest.lock().unwrap();
+        match segments.as_slice() {
+            ["ticker", pair] => latest.tickers.get(&pair_from_path(pair)).cloned(),
+            ["spread", pair] => latest.spreads.get(&pair_from_path(pair)).cloned(),
+            ["candles", pair, interval] => {
+                let minutes = OhlcInterval::from_str(interval).ok()?.minutes();
+                let candles = latest.candles.get(&(pair_from_path(pair), minutes))?;
+                let candles: Vec<_> = candles.values().map(String::as_str).collect();
+                Some(format!("[{}]", candles.join(",")))
+            }
+            ["book", pair] => {
+                let pair = pair_from_path(pair);
+                let book = latest.books.get(&pair)?;
+                let levels = |levels: Vec<_>| -> Value {
+                    levels
+                        .into_iter()
+                        .map(|l: &PriceLevel| {
+                            json!({
+                                "price": l.price,
+                                "volume": l.volume,
+                                "time": l.time.to_string(),
+                            })
+                        })
+                        .collect()
+                };
+                Some(
+                    json!({
+                        "pair": pair,
+                        "depth": book.depth().levels(),
+                        "asks": levels(book.asks().collect()),
+                        "bids": levels(book.bids().collect()),
+                    })
+                    .to_string(),
+                )
+            }
+            ["status"] => {
+                let status: Value = match &latest.status {
+                    Some(status) => serde_json::from_str(status).ok()?,
+                    None => Value::Null,
+                };
+                let pairs: BTreeSet<_> = latest
+                    .tickers
+                    .keys()
+                    .chain(latest.spreads.keys())
+                    .chain(latest.candles.keys().map(|(pair, _)| pair))
+                    .chain(latest.books.keys())
+                    .collect();
+                Some(
+                    json!({
+                        "systemStatus": status,
+                        "pairs": pairs,
+                        "clients": self.clients(),
+                    })
+                    .to_string(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Serve the websocket from a background thread, returning
+    /// the address bound to.
+    pub fn serve_ws<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let server = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                // A failed client only affects that client.
+                thread::spawn(move || server.serve_client(stream));
+            }
+        });
+        Ok(addr)
+    }
+
+    // Read a client's requests until it disconnects, a thread
+    // of its own writes to it from its queue.
+    fn serve_client(&self, stream: TcpStream) -> Result<()> {
+        let client = stream
+            .into_ws()
+            .map_err(|(_, _, _, e)| anyhow!("Invalid upgrade: {:?}", e))?
+            .accept()
+            .map_err(|(_, e)| e)?;
+        let stream = client.stream_ref().try_clone()?;
+        let (mut reader, writer) = client.split()?;
+        let (queue, outgoing) = mpsc::sync_channel(CLIENT_QUEUE);
+        thread::spawn(move || write_client(writer, outgoing));
+        let id = self.next_client.fetch_add(1, Ordering::SeqCst);
+        self.clients.lock().unwrap().push(Client {
+            id,
+            subscriptions: BTreeSet::new(),
+            queue: queue.clone(),
+            stream: stream.try_clone()?,
+        });
+
+        let served = (|| {
+            for message in reader.incoming_messages() {
+                let reply = match message? {
+                    OwnedMessage::Text(text) => self.handle(id, &text),
+                    OwnedMessage::Ping(data) => OwnedMessage::Pong(data),
+                    OwnedMessage::Close(_) => break,
+                    _ => continue,
+                };
+                if queue.send(reply).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })();
+        self.clients.lock().unwrap().retain(|c| c.id != id);
+        let _ = stream.shutdown(Shutdown::Both);
+        served
+    }
+
+    // Apply a client's request, replying with its subscriptions
+    // or the error.
+    fn handle(&self, id: u64, text: &str) -> OwnedMessage {
+        let reply = match self.subscribe(id, text) {
+            Ok(subscriptions) => json!({ "subscriptions": subscriptions }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        OwnedMessage::Text(reply.to_string())
+    }
+
+    fn subscribe(&self, id: u64, text: &str) -> Result<Vec<Value>> {
+        let request: Request = serde_json::from_str(text)?;
+        let all = || vec![ALL.to_owned()];
+        let types = request.types.unwrap_or_else(all);
+        let pairs = request.pairs.unwrap_or_else(all);
+        let mut clients = self.clients.lock().unwrap();
+        let client = match clients.iter_mut().find(|c| c.id == id) {
+            Some(client) => client,
+            None => bail!("Disconnected"),
+        };
+        for kind in &types {
+            for pair in &pairs {
+                let subscription = (kind.clone(), pair.clone());
+                match request.op {
+                    Op::Subscribe => client.subscriptions.insert(subscription),
+                    Op::Unsubscribe => client.subscriptions.remove(&subscription),
+                };
+            }
+        }
+        Ok(client
+            .subscriptions
+            .iter()
+            .map(|(kind, pair)| json!({"type": kind, "pair": pair}))
+            .collect())
+    }
+}
+
+impl Client {
+    fn matches(&self, kind: &str, pair: Option<&str>) -> bool {
+        self.subscriptions.iter().any(|(k, p)| {
+            (k == ALL || k == kind) && (p == ALL || pair.is_none_or(|pair| p == pair))
+        })
+    }
+}
+
+fn write_client(mut writer: Writer<TcpStream>, outgoing: Receiver<OwnedMessage>) {
+    for message in outgoing {
+        if writer.send_message(&message).is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown_all();
+}
+
+/// Pairs are written as "XBT-USD" in paths, since a "/" would
+/// split the path.
+fn pair_from_path(pair: &str) -> String {
+    pair.replace("%2F", "/")
+        .replace("%2f", "/")
+        .replace('-', "/")
+}
+
+impl Sink for FanoutServer {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        self.publish(resp, received)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use websocket::ClientBuilder;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-1","XBT/USD"]"#;
+    const BOOK: &str = r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-10","XBT/USD"]"#;
+
+    fn parse(text: &str) -> Resp {
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> Result<(String, Value)> {
+        let mut stream = TcpStream::connect(addr)?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_owned();
+        Ok((status, serde_json::from_str(body)?))
+    }
+
+    #[test]
+    fn serves_latest_state() -> Result<()> {
+        let server = FanoutServer::new();
+        let addr = server.serve_http("127.0.0.1:0")?;
+        let received = Timestamp::from_secs(1542057400);
+        for text in [TICKER, OHLC, BOOK] {
+            server.publish(&parse(text), received)?;
+        }
+
+        let (status, ticker) = get(addr, "/ticker/XBT-USD")?;
+        assert_eq!("HTTP/1.1 200 OK", status);
+        assert_eq!("ticker", ticker["type"]);
+        assert_eq!("5525.10000", ticker["payload"]["close"]["today"]);
+
+        let (_, candles) = get(addr, "/candles/XBT-USD/1")?;
+        assert_eq!(1, candles.as_array().unwrap().len());
+        assert_eq!("3586.60000", candles[0]["payload"]["close"]);
+
+        let (_, book) = get(addr, "/book/XBT%2FUSD")?;
+        assert_eq!(
+            json!({
+                "pair": "XBT/USD",
+                "depth": 10,
+                "asks": [{"price": "5541.30000", "volume": "2.50700000", "time": "1534614248.123678"}],
+                "bids": [{"price": "5541.20000", "volume": "1.52900000", "time": "1534614248.765567"}],
+            }),
+            book
+        );
+
+        let (_, status) = get(addr, "/status")?;
+        assert_eq!(json!(["XBT/USD"]), status["pairs"]);
+        assert_eq!("HTTP/1.1 404 Not Found", get(addr, "/ticker/ETH-USD")?.0);
+        assert_eq!("HTTP/1.1 404 Not Found", get(addr, "/candles/XBT-USD/2")?.0);
+        Ok(())
+    }
+
+    #[test]
+    fn forwards_subscribed_envelopes() -> Result<()> {
+        let server = FanoutServer::new();
+        let addr = server.serve_ws("127.0.0.1:0")?;
+        let mut client = ClientBuilder::new(&format!("ws://{}", addr))?.connect_insecure()?;
+        // Replies are sent once the client is registered.
+        assert_eq!(
+            json!({"subscriptions": [{"type": "ohlc", "pair": "XBT/USD"}]}),
+            request(
+                &mut client,
+                json!({"op": "subscribe", "types": ["ohlc"], "pairs": ["XBT/USD"]})
+            )?
+        );
+        assert!(request(&mut client, json!({"op": "bogus"}))?["error"].is_string());
+        assert_eq!(1, server.clients());
+
+        // Only the candle is forwarded.
+        let received = Timestamp::from_secs(1542057400);
+        for text in [TICKER, OHLC] {
+            server.publish(&parse(text), received)?;
+        }
+        let forwarded = recv(&mut client)?;
+        assert_eq!("ohlc", forwarded["type"]);
+        assert_eq!("XBT/USD", forwarded["pair"]);
+        assert_eq!("1542057400.000000", forwarded["receiveTime"]);
+
+        // Subscriptions for all pairs, less the tickers.
+        request(
+            &mut client,
+            json!({"op": "unsubscribe", "types": ["ohlc"], "pairs": ["XBT/USD"]}),
+        )?;
+        request(
+            &mut client,
+            json!({"op": "subscribe", "types": ["ticker", "ohlc"]}),
+        )?;
+        let reply = request(
+            &mut client,
+            json!({"op": "unsubscribe", "types": ["ticker"]}),
+        )?;
+        assert_eq!(
+            json!([{"type": "ohlc", "pair": "*"}]),
+            reply["subscriptions"]
+        );
+        for text in [TICKER, OHLC] {
+            server.publish(&parse(text), received)?;
+        }
+        assert_eq!("ohlc", recv(&mut client)?["type"]);
+        Ok(())
+    }
+
+    fn request(client: &mut websocket::sync::Client<TcpStream>, request: Value) -> Result<Value> {
+        client.send_message(&OwnedMessage::Text(request.to_string()))?;
+        recv(client)
+    }
+
+    fn recv(client: &mut websocket::sync::Client<TcpStream>) -> Result<Value> {
+        match client.recv_message()? {
+            OwnedMessage::Text(text) => Ok(serde_json::from_str(&text)?),
+            message => bail!("Unexpected {:?}", message),
+        }
+    }
+}
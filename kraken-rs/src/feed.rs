@@ -0,0 +1,369 @@
+use crate::req::{Subscription, WsReq};
+use crate::resp::book::Book;
+use crate::resp::ohlc::Ohlc;
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::Trades;
+use crate::resp::{Resp, RespRef};
+use crate::Shared;
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Market data which a feed can carry.
+pub trait FeedItem: Sized {
+    fn from_resp(resp: Resp) -> Option<Self>;
+}
+
+impl FeedItem for TickerState {
+    fn from_resp(resp: Resp) -> Option<Self> {
+        match resp {
+            Resp::Ticker(ticker) => Some(ticker),
+            _ => None,
+        }
+    }
+}
+
+impl FeedItem for Ohlc {
+    fn from_resp(resp: Resp) -> Option<Self> {
+        match resp {
+            Resp::Ohlc(ohlc) => Some(ohlc),
+            _ => None,
+        }
+    }
+}
+
+impl FeedItem for Trades {
+    fn from_resp(resp: Resp) -> Option<Self> {
+        match resp {
+            Resp::Trade(trades) => Some(trades),
+            _ => None,
+        }
+    }
+}
+
+impl FeedItem for Spread {
+    fn from_resp(resp: Resp) -> Option<Self> {
+        match resp {
+            Resp::Spread(spread) => Some(spread),
+            _ => None,
+        }
+    }
+}
+
+impl FeedItem for Book {
+    fn from_resp(resp: Resp) -> Option<Self> {
+        match resp {
+            Resp::Book(book) => Some(book),
+            _ => None,
+        }
+    }
+}
+
+/// Handle to a subscription yielding only the messages for the
+/// subscribed channel and pairs, dropping it unsubscribes.
+///
+/// Feeds share the client's connection, whichever feed is
+/// waiting reads from the connection and passes on messages
+/// for the others. Messages which no feed wants are dropped
+/// unless read by the client itself, so a client with feeds
+/// should only use `recv` if it is also being read from.
+pub struct Feed<T> {
+    shared: Arc<Shared>,
+    id: u64,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T: FeedItem> Feed<T> {
+    pub(crate) fn new(shared: Arc<Shared>, id: u64) -> Feed<T> {
+        Feed {
+            shared,
+            id,
+            item: PhantomData,
+        }
+    }
+
+    /// Block until the next message for this feed, erroring
+    /// if the connection fails.
+    pub fn recv(&self) -> Result<T> {
+        loop {
+            if let Some(item) = self.pop() {
+                return Ok(item);
+            }
+            let mut conn = self.shared.conn.lock().unwrap();
+            // Another feed may have read one for us while waiting.
+            if let Some(item) = self.pop() {
+                return Ok(item);
+            }
+            let text = self.shared.next_text(&mut conn)?;
+            self.shared.route(&text);
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut routes = self.shared.routes.lock().unwrap();
+        std::iter::from_fn(|| routes.pop(self.id)).find_map(T::from_resp)
+    }
+
+    /// Unsubscribe, sending the request before returning and
+    /// failing if it can't be sent. Dropping a feed only queues
+    /// the request when another thread is reading, to be sent
+    /// before its next read, and can't report a failure.
+    pub fn close(self) -> Result<()> {
+        match self.unsubscribe() {
+            Some(req) => self.shared.send_now(req),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> Feed<T> {
+    /// Remove the feed's route, returning the request to
+    /// unsubscribe from the pairs no other feed wants.
+    fn unsubscribe(&self) -> Option<WsReq> {
+        let removed = self.shared.routes.lock().unwrap().remove(self.id);
+        removed.map(|(subscription, pairs)| WsReq::Unsubscribe {
+            request_id: None,
+            pair: pairs,
+            subscription,
+        })
+    }
+}
+
+/// Messages which fail to parse are skipped, so the iterator
+/// ends only when the connection fails. It is not fused, calling
+/// `next` again tries to read from the connection again.
+impl<T: FeedItem> Iterator for Feed<T> {
+    type Item = T;
+
+    /// The next message, or `None` once the connection fails.
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+impl<T> Drop for Feed<T> {
+    fn drop(&mut self) {
+        if let Some(req) = self.unsubscribe() {
+            // Nothing can be done if this fails, the connection
+            // is broken so there is nothing to unsubscribe from.
+            // Use close to know the request was sent.
+            let _ = self.shared.send_later(req);
+        }
+    }
+}
+
+/// The subscriptions of each feed and the messages read for
+/// them which they are yet to receive.
+#[derive(Default)]
+pub(crate) struct Routes {
+    next_id: u64,
+    routes: HashMap<u64, Route>,
+}
+
+struct Route {
+    subscription: Subscription,
+    pairs: Vec<String>,
+    queue: VecDeque<Resp>,
+}
+
+impl Routes {
+    pub(crate) fn add(&mut self, subscription: Subscription, pairs: Vec<String>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let route = Route {
+            subscription,
+            pairs,
+            queue: VecDeque::new(),
+        };
+        self.routes.insert(id, route);
+        id
+    }
+
+    /// Remove a feed's route, returning the subscription and the
+    /// pairs which no other feed still wants so should be
+    /// unsubscribed from.
+    pub(crate) fn remove(&mut self, id: u64) -> Option<(Subscription, Vec<String>)> {
+        let mut route = self.routes.remove(&id)?;
+        let others = self
+            .routes
+            .values()
+            .filter(|other| other.subscription == route.subscription);
+        let wanted: Vec<&String> = others.flat_map(|other| &other.pairs).collect();
+        route.pairs.retain(|pair| !wanted.contains(&pair));
+        match route.pairs.is_empty() {
+            true => None,
+            false => Some((route.subscription, route.pairs)),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Queue a message for every feed subscribed to its channel
+    /// and pair, returning false if there are none. It is only
+    /// copied out of the frame when a feed wants it.
+    pub(crate) fn route(&mut self, resp: RespRef) -> bool {
+        self.queue(resp, Resp::into_owned)
+    }
+
+    /// As [`Routes::route`], for a message which could not be
+    /// borrowed from its frame.
+    pub(crate) fn route_owned(&mut self, resp: Resp) -> bool {
+        self.queue(resp, |resp| resp)
+    }
+
+    fn queue<S, F>(&mut self, resp: Resp<S>, into_owned: F) -> bool
+    where
+        S: AsRef<str>,
+        F: FnOnce(Resp<S>) -> Resp,
+    {
+        let (subscription, pair) = match &resp {
+            Resp::Ticker(ticker) => (Subscription::Ticker, ticker.pair.as_ref()),
+            Resp::Ohlc(ohlc) => (
+                Subscription::Ohlc {
+                    interval: ohlc.interval,
+                },
+                ohlc.pair.as_ref(),
+            ),
+            Resp::Trade(trades) => (Subscription::Trade, trades.pair.as_ref()),
+            Resp::Spread(spread) => (Subscription::Spread, spread.pair.as_ref()),
+            Resp::Book(book) => (Subscription::Book { depth: book.depth }, book.pair.as_ref()),
+            _ => return false,
+        };
+        let mut wanting: Vec<u64> = self
+            .routes
+            .iter()
+            .filter(|(_, route)| {
+                route.subscription == subscription && route.pairs.iter().any(|p| p == pair)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let last = match wanting.pop() {
+            Some(id) => id,
+            None => return false,
+        };
+        let resp = into_owned(resp);
+        for id in wanting {
+            self.routes
+                .get_mut(&id)
+                .unwrap()
+                .queue
+                .push_back(resp.clone());
+        }
+        self.routes.get_mut(&last).unwrap().queue.push_back(resp);
+        true
+    }
+
+    pub(crate) fn pop(&mut self, id: u64) -> Option<Resp> {
+        self.routes.get_mut(&id)?.queue.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockServer;
+    use crate::req::OhlcInterval;
+    use crate::Kraken;
+    use std::time::Duration;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+
+    fn route(routes: &mut Routes, msg: &str) -> Result<bool> {
+        Ok(routes.route(serde_json::from_str(msg)?))
+    }
+
+    fn pairs(pairs: &[&str]) -> Vec<String> {
+        pairs.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn routes_by_channel_and_pair() -> Result<()> {
+        let mut routes = Routes::default();
+        let ticker = routes.add(Subscription::Ticker, pairs(&["XBT/USD", "ETH/USD"]));
+        let eth_ticker = routes.add(Subscription::Ticker, pairs(&["ETH/USD"]));
+        let ohlc_1 = routes.add(
+            Subscription::Ohlc {
+                interval: OhlcInterval::Mins1,
+            },
+            pairs(&["XBT/USD"]),
+        );
+        let ohlc_5 = routes.add(
+            Subscription::Ohlc {
+                interval: OhlcInterval::Mins5,
+            },
+            pairs(&["XBT/USD"]),
+        );
+
+        assert!(route(&mut routes, TICKER)?);
+        assert!(route(&mut routes, OHLC)?);
+        assert!(!route(&mut routes, r#"{"event":"heartbeat"}"#)?);
+
+        assert!(matches!(routes.pop(ticker), Some(Resp::Ticker(_))));
+        assert_eq!(None, routes.pop(ticker));
+        assert_eq!(None, routes.pop(eth_ticker));
+        assert_eq!(None, routes.pop(ohlc_1));
+        let ohlc = routes.pop(ohlc_5).and_then(Ohlc::from_resp).unwrap();
+        assert_eq!(OhlcInterval::Mins5, ohlc.interval);
+        Ok(())
+    }
+
+    #[test]
+    fn remove_keeps_pairs_other_feeds_want() -> Result<()> {
+        let mut routes = Routes::default();
+        let both = routes.add(Subscription::Ticker, pairs(&["XBT/USD", "ETH/USD"]));
+        let eth = routes.add(Subscription::Ticker, pairs(&["ETH/USD"]));
+        let trade = routes.add(Subscription::Trade, pairs(&["XBT/USD"]));
+
+        let (subscription, unsubscribe) = routes.remove(both).unwrap();
+        assert!(subscription == Subscription::Ticker);
+        assert_eq!(pairs(&["XBT/USD"]), unsubscribe);
+        assert!(!route(&mut routes, TICKER)?);
+
+        assert!(routes.remove(eth).is_some());
+        assert!(routes.remove(eth).is_none());
+        assert!(routes.remove(trade).is_some());
+        assert!(routes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn feeds_over_connection() -> Result<()> {
+        let server = MockServer::start()?;
+        server.script("ticker", "XBT/USD", vec![TICKER.to_string()]);
+        let escaped = OHLC.replace("XBT/USD", r"XBT\/USD");
+        server.script("ohlc-5", "XBT/USD", vec![OHLC.to_string(), escaped]);
+        let mut client = Kraken::connect(&server.url())?;
+        assert!(matches!(client.recv()?, Resp::SystemStatus(_)));
+        let ticker = client.ticker(&["XBT/USD"])?;
+        let ohlc = client.ohlc("XBT/USD", OhlcInterval::Mins5)?;
+        assert_eq!("3586.70000", ohlc.recv()?.open);
+        // Routed even though it can't be borrowed.
+        assert_eq!("XBT/USD", ohlc.recv()?.pair);
+        assert_eq!("XBT/USD", ticker.recv()?.pair);
+
+        drop(ticker);
+        let unsubscribe = server
+            .wait_for("unsubscribe", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!("ticker", unsubscribe["subscription"]["name"]);
+        assert_eq!("XBT/USD", unsubscribe["pair"][0]);
+        Ok(())
+    }
+
+    #[test]
+    fn close_sends_unsubscribe() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = Kraken::connect(&server.url())?;
+        assert!(matches!(client.recv()?, Resp::SystemStatus(_)));
+        let ticker = client.ticker(&["XBT/USD"])?;
+        ticker.close()?;
+        let unsubscribe = server.wait_for("unsubscribe", Duration::from_secs(5));
+        assert_eq!("ticker", unsubscribe.unwrap()["subscription"]["name"]);
+        Ok(())
+    }
+}
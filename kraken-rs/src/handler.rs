@@ -0,0 +1,361 @@
+use crate::resp::book::Book;
+use crate::resp::event::{
+    AddOrderStatus, CancelOrderStatus, RequestError, SubscriptionStatus, SystemStatus,
+};
+use crate::resp::ohlc::Ohlc;
+use crate::resp::private::{OrderUpdate, OwnTrade};
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::Trades;
+use crate::resp::Resp;
+use crate::RespSource;
+use anyhow::{Error, Result};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+/// Status messages about the connection, its subscriptions
+/// and the orders placed or cancelled on it.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Status {
+    System(SystemStatus),
+    Subscription(SubscriptionStatus),
+    AddOrder(AddOrderStatus),
+    CancelOrder(CancelOrderStatus),
+    /// A request which couldn't be handled at all.
+    Error(RequestError),
+}
+
+/// Callbacks for each kind of message, every one does nothing
+/// by default so handlers only implement those they need.
+#[allow(unused_variables)]
+pub trait KrakenHandler {
+    fn on_ticker(&mut self, ticker: TickerState) {}
+
+    fn on_ohlc(&mut self, ohlc: Ohlc) {}
+
+    fn on_trade(&mut self, trades: Trades) {}
+
+    fn on_spread(&mut self, spread: Spread) {}
+
+    fn on_book(&mut self, book: Book) {}
+
+    /// Called once for each trade in an ownTrades message.
+    fn on_own_trade(&mut self, trade: OwnTrade) {}
+
+    /// Called once for each order in an openOrders message.
+    fn on_order_update(&mut self, update: OrderUpdate) {}
+
+    fn on_status(&mut self, status: Status) {}
+
+    /// Called with a message which could not be parsed, such as
+    /// an event this crate doesn't know. The connection is still
+    /// read after these.
+    fn on_parse_error(&mut self, error: &Error) {}
+
+    /// Called last with the error which ended the connection.
+    fn on_disconnect(&mut self, error: &Error) {}
+}
+
+/// Pass a message to the matching handler method, heartbeats
+/// and pongs are not passed on.
+pub fn dispatch<H: KrakenHandler + ?Sized>(handler: &mut H, resp: Resp) {
+    match resp {
+        Resp::Ticker(ticker) => handler.on_ticker(ticker),
+        Resp::Ohlc(ohlc) => handler.on_ohlc(ohlc),
+        Resp::Trade(trades) => handler.on_trade(trades),
+        Resp::Spread(spread) => handler.on_spread(spread),
+        Resp::Book(book) => handler.on_book(book),
+        Resp::OwnTrades(trades) => trades
+            .trades
+            .into_iter()
+            .for_each(|trade| handler.on_own_trade(trade)),
+        Resp::OpenOrders(orders) => orders
+            .orders
+            .into_iter()
+            .for_each(|update| handler.on_order_update(update)),
+        Resp::SystemStatus(status) => handler.on_status(Status::System(status)),
+        Resp::SubscriptionStatus(status) => handler.on_status(Status::Subscription(status)),
+        Resp::AddOrderStatus(status) => handler.on_status(Status::AddOrder(status)),
+        Resp::CancelOrderStatus(status) => handler.on_status(Status::CancelOrder(status)),
+        Resp::RequestError(error) => handler.on_status(Status::Error(error)),
+        Resp::Heartbeat | Resp::Pong(_) => {}
+    }
+}
+
+/// What the runner does with a message when the handler has
+/// fallen so far behind that the queue is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Overflow {
+    /// Stop reading until the handler catches up. Kraken will
+    /// close the connection if it is not read for too long.
+    Block,
+    /// Drop the message, keeping the connection read.
+    Drop,
+}
+
+/// Counts of the messages read by a runner.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct RunStats {
+    pub handled: u64,
+    pub dropped: u64,
+    /// Messages which could not be parsed.
+    pub unparsed: u64,
+}
+
+/// Drives a connection, reading on a separate thread so that
+/// the handler runs on the calling thread and need not be
+/// `Send`. Messages are queued between the two threads and
+/// when the queue is full the overflow policy applies.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Default for Runner {
+    fn default() -> Runner {
+        Runner {
+            capacity: 1024,
+            overflow: Overflow::Block,
+        }
+    }
+}
+
+impl Runner {
+    pub fn new() -> Runner {
+        Default::default()
+    }
+
+    /// The most messages queued for the handler, at least one.
+    pub fn capacity(mut self, capacity: usize) -> Runner {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Runner {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Read from the source and dispatch to the handler until
+    /// the connection fails, which is passed to `on_disconnect`.
+    /// Messages which can't be parsed are passed to
+    /// `on_parse_error` and reading goes on.
+    pub fn run<R, H>(&self, source: R, handler: &mut H) -> RunStats
+    where
+        R: RespSource + Send + 'static,
+        H: KrakenHandler + ?Sized,
+    {
+        let (sender, receiver) = sync_channel(self.capacity);
+        let overflow = self.overflow;
+        let reader = thread::spawn(move || read(source, sender, overflow));
+        let (handled, unparsed) = handle(receiver, handler);
+        // The reader ends as soon as it sends the disconnect.
+        let dropped = reader.join().unwrap_or(0);
+        RunStats {
+            handled,
+            dropped,
+            unparsed,
+        }
+    }
+}
+
+// Read until the connection fails, returning how many
+// messages were dropped.
+fn read<R: RespSource>(mut source: R, sender: SyncSender<Result<Resp>>, overflow: Overflow) -> u64 {
+    let mut dropped = 0;
+    loop {
+        let resp = source.recv();
+        let end = matches!(&resp, Err(e) if !is_parse_error(e));
+        let sent = match (overflow, end) {
+            (Overflow::Drop, false) => match sender.try_send(resp) {
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+                Ok(()) => true,
+            },
+            _ => sender.send(resp).is_ok(),
+        };
+        if end || !sent {
+            return dropped;
+        }
+    }
+}
+
+// Returns how many messages were handled and how many could
+// not be parsed.
+fn handle<H: KrakenHandler + ?Sized>(
+    receiver: Receiver<Result<Resp>>,
+    handler: &mut H,
+) -> (u64, u64) {
+    let mut handled = 0;
+    let mut unparsed = 0;
+    for resp in receiver {
+        match resp {
+            Ok(resp) => {
+                handled += 1;
+                dispatch(handler, resp);
+            }
+            Err(error) if is_parse_error(&error) => {
+                unparsed += 1;
+                handler.on_parse_error(&error);
+            }
+            Err(error) => {
+                handler.on_disconnect(&error);
+                break;
+            }
+        }
+    }
+    (handled, unparsed)
+}
+
+fn is_parse_error(error: &Error) -> bool {
+    error.is::<serde_json::Error>()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::mpsc::{channel, Sender};
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const OWN_TRADES: &str = r#"[[{"T1":{"ordertxid":"O1","postxid":"P1","pair":"XBT/EUR","time":"1560516023.070651","type":"sell","ordertype":"limit","price":"100.0","cost":"100.0","fee":"0.1","vol":"1.0","margin":"0.0"}},{"T2":{"ordertxid":"O1","postxid":"P1","pair":"XBT/EUR","time":"1560516023.070651","type":"sell","ordertype":"limit","price":"100.0","cost":"100.0","fee":"0.1","vol":"1.0","margin":"0.0"}}],"ownTrades",{"sequence":1}]"#;
+    const STATUS: &str =
+        r#"{"connectionID":1,"event":"systemStatus","status":"online","version":"1.0.0"}"#;
+
+    // Yields the messages then errors, signalling once it has.
+    struct FakeSource {
+        messages: Vec<&'static str>,
+        done: Option<Sender<()>>,
+    }
+
+    impl RespSource for FakeSource {
+        fn recv(&mut self) -> Result<Resp> {
+            if self.messages.is_empty() {
+                self.done.take().map(|done| done.send(()));
+                return Err(anyhow!("closed"));
+            }
+            Ok(serde_json::from_str(self.messages.remove(0))?)
+        }
+    }
+
+    #[derive(Default)]
+    struct Recording {
+        calls: Vec<String>,
+        // Blocks the first ticker until the source is done.
+        wait: Option<Receiver<()>>,
+    }
+
+    impl KrakenHandler for Recording {
+        fn on_ticker(&mut self, ticker: TickerState) {
+            if let Some(wait) = self.wait.take() {
+                wait.recv().unwrap();
+            }
+            self.calls.push(format!("ticker {}", ticker.pair));
+        }
+
+        fn on_own_trade(&mut self, trade: OwnTrade) {
+            self.calls.push(format!("own trade {}", trade.trade_id));
+        }
+
+        fn on_status(&mut self, status: Status) {
+            let status = match status {
+                Status::System(_) => "system status",
+                Status::Subscription(_) => "subscription status",
+                _ => "order status",
+            };
+            self.calls.push(status.to_string());
+        }
+
+        fn on_parse_error(&mut self, _: &Error) {
+            self.calls.push("parse error".to_string());
+        }
+
+        fn on_disconnect(&mut self, error: &Error) {
+            self.calls.push(format!("disconnect {}", error));
+        }
+    }
+
+    #[test]
+    fn dispatches_until_disconnect() {
+        let source = FakeSource {
+            messages: vec![STATUS, TICKER, r#"{"event":"heartbeat"}"#, OWN_TRADES],
+            done: None,
+        };
+        let mut handler = Recording::default();
+        let stats = Runner::new().run(source, &mut handler);
+        assert_eq!(
+            vec![
+                "system status",
+                "ticker XBT/USD",
+                "own trade T1",
+                "own trade T2",
+                "disconnect closed"
+            ],
+            handler.calls
+        );
+        assert_eq!(
+            RunStats {
+                handled: 4,
+                dropped: 0,
+                unparsed: 0
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn reads_on_after_unparsable_messages() {
+        let source = FakeSource {
+            messages: vec![r#"{"event":"unknown"}"#, "[0,{", TICKER],
+            done: None,
+        };
+        let mut handler = Recording::default();
+        let stats = Runner::new().run(source, &mut handler);
+        assert_eq!(
+            vec![
+                "parse error",
+                "parse error",
+                "ticker XBT/USD",
+                "disconnect closed"
+            ],
+            handler.calls
+        );
+        assert_eq!(2, stats.unparsed);
+    }
+
+    #[test]
+    fn block_when_full() {
+        let source = FakeSource {
+            messages: vec![TICKER; 10],
+            done: None,
+        };
+        let mut handler = Recording::default();
+        let stats = Runner::new().capacity(1).run(source, &mut handler);
+        assert_eq!(10, stats.handled);
+        assert_eq!(0, stats.dropped);
+    }
+
+    #[test]
+    fn drop_when_full() {
+        let (done, wait) = channel();
+        let source = FakeSource {
+            messages: vec![TICKER; 10],
+            done: Some(done),
+        };
+        let mut handler = Recording {
+            calls: vec![],
+            wait: Some(wait),
+        };
+        let stats = Runner::new()
+            .capacity(2)
+            .overflow(Overflow::Drop)
+            .run(source, &mut handler);
+        assert_eq!(10, stats.handled + stats.dropped);
+        // At most one being handled and two queued.
+        assert!(stats.handled <= 3, "{:?}", stats);
+    }
+}
@@ -0,0 +1,324 @@
+pub mod book;
+pub mod candle;
+#[cfg(feature = "collector")]
+pub mod collector;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "dist")]
+pub mod dist;
+pub mod envelope;
+pub mod fanout;
+pub mod feed;
+pub mod handler;
+pub mod market;
+pub mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod record;
+pub mod replay;
+pub mod req;
+pub mod resp;
+pub mod rest;
+pub mod sink;
+pub mod time;
+
+use crate::feed::{Feed, FeedItem, Routes};
+use crate::record::{Direction, Frame, FrameKind, Recorder};
+use crate::req::{BookDepth, OhlcInterval, Subscription, WsReq};
+use crate::resp::book::Book;
+use crate::resp::ohlc::Ohlc;
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::Trades;
+use crate::resp::{Resp, RespRef};
+use crate::time::Timestamp;
+use anyhow::{Error, Result};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use websocket::client::sync::Client;
+use websocket::websocket_base::stream::sync::{AsTcpStream, NetworkStream};
+use websocket::{ClientBuilder, Message, OwnedMessage};
+
+/// Kraken's public websocket endpoint.
+pub const ENDPOINT: &str = "wss://ws.kraken.com";
+
+type Conn = Client<Box<dyn NetworkStream + Send>>;
+
+pub struct Kraken {
+    shared: Arc<Shared>,
+    // The socket under the connection, for closing it while
+    // another thread is reading.
+    socket: TcpStream,
+}
+
+/// Closes a [`Kraken`] connection from another thread, ending
+/// any read in progress with an error.
+#[derive(Debug)]
+pub struct Closer {
+    socket: TcpStream,
+}
+
+impl Closer {
+    pub fn close(&self) -> Result<()> {
+        Ok(self.socket.shutdown(Shutdown::Both)?)
+    }
+}
+
+// The connection along with the state shared with feeds,
+// whichever of the client or its feeds is reading holds the
+// connection and routes every message it reads.
+pub(crate) struct Shared {
+    conn: Mutex<Conn>,
+    // Requests from feeds waiting to be sent, so that feeds
+    // never block on the connection while another is reading.
+    outbox: Mutex<Vec<WsReq>>,
+    routes: Mutex<Routes>,
+    recorder: Mutex<Option<Recorder>>,
+    // Identifies this connection in recordings, the time it
+    // was made in microseconds.
+    connection: u64,
+}
+
+impl Kraken {
+    pub fn new() -> Result<Kraken> {
+        Kraken::connect(ENDPOINT)
+    }
+
+    /// Connect to another endpoint, such as a mock server.
+    pub fn connect(url: &str) -> Result<Kraken> {
+        let conn = ClientBuilder::new(url)?.connect(None)?;
+        Ok(Kraken {
+            socket: conn.stream_ref().as_tcp().try_clone()?,
+            shared: Arc::new(Shared {
+                conn: Mutex::new(conn),
+                outbox: Mutex::new(vec![]),
+                routes: Mutex::new(Routes::default()),
+                recorder: Mutex::new(None),
+                connection: Timestamp::now().as_micros(),
+            }),
+        })
+    }
+
+    /// A handle for closing the connection, such as to stop a
+    /// thread blocked in [`Kraken::recv`].
+    pub fn closer(&self) -> Result<Closer> {
+        Ok(Closer {
+            socket: self.socket.try_clone()?,
+        })
+    }
+
+    pub fn send_req(&mut self, req: WsReq) -> Result<()> {
+        self.shared
+            .send(&mut self.shared.conn.lock().unwrap(), &req)
+    }
+
+    /// Record every frame sent or received from now on,
+    /// including those which cannot be parsed. Should the
+    /// recorder fail it is logged and recording stops, the
+    /// connection carries on.
+    pub fn record(&self, recorder: Recorder) {
+        *self.shared.recorder.lock().unwrap() = Some(recorder);
+    }
+
+    /// Subscribe to the ticker for the given pairs.
+    pub fn ticker(&self, pairs: &[&str]) -> Result<Feed<TickerState>> {
+        self.feed(pairs, Subscription::Ticker)
+    }
+
+    /// Subscribe to candles of the given interval for a pair.
+    pub fn ohlc(&self, pair: &str, interval: OhlcInterval) -> Result<Feed<Ohlc>> {
+        self.feed(&[pair], Subscription::Ohlc { interval })
+    }
+
+    pub fn trade(&self, pairs: &[&str]) -> Result<Feed<Trades>> {
+        self.feed(pairs, Subscription::Trade)
+    }
+
+    pub fn spread(&self, pairs: &[&str]) -> Result<Feed<Spread>> {
+        self.feed(pairs, Subscription::Spread)
+    }
+
+    pub fn book(&self, pairs: &[&str], depth: BookDepth) -> Result<Feed<Book>> {
+        self.feed(pairs, Subscription::Book { depth })
+    }
+
+    fn feed<T: FeedItem>(&self, pairs: &[&str], subscription: Subscription) -> Result<Feed<T>> {
+        let pairs: Vec<String> = pairs.iter().map(|&pair| pair.to_owned()).collect();
+        let id = self
+            .shared
+            .routes
+            .lock()
+            .unwrap()
+            .add(subscription.clone(), pairs.clone());
+        let feed = Feed::new(self.shared.clone(), id);
+        self.shared.send_later(WsReq::Subscribe {
+            request_id: None,
+            pair: pairs,
+            subscription,
+        })?;
+        Ok(feed)
+    }
+
+    /// Block until the next text frame arrives and parse it, frames
+    /// which are not text (pings, binary data) are skipped, as are
+    /// messages wanted by one of the client's feeds.
+    pub fn recv(&mut self) -> Result<Resp> {
+        let text = self.shared.next_unrouted()?;
+        serde_json::from_str(text.as_str()).map_err(Error::from)
+    }
+
+    /// Block until the next text frame arrives and pass the
+    /// response parsed from it, borrowing from the frame, to the
+    /// given function. This avoids copying the market data out
+    /// of every frame when only part of it is needed, but fails
+    /// on a frame holding escaped strings, see [`RespRef`], where
+    /// [`Kraken::recv`] would not.
+    pub fn recv_with<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(RespRef) -> T,
+    {
+        let text = self.shared.next_unrouted()?;
+        Ok(f(serde_json::from_str(text.as_str())?))
+    }
+
+    /// Iterator over the successfully parsed messages received on
+    /// this connection, ending when the connection errors.
+    pub fn incoming(&mut self) -> impl Iterator<Item = Resp> + '_ {
+        std::iter::from_fn(move || loop {
+            match self.shared.next_unrouted() {
+                Ok(text) => {
+                    if let Ok(resp) = serde_json::from_str(text.as_str()) {
+                        return Some(resp);
+                    }
+                }
+                Err(_) => return None,
+            }
+        })
+    }
+}
+
+/// Anything messages can be read from one at a time, such
+/// as a live connection.
+pub trait RespSource {
+    fn recv(&mut self) -> Result<Resp>;
+}
+
+impl RespSource for Kraken {
+    fn recv(&mut self) -> Result<Resp> {
+        Kraken::recv(self)
+    }
+}
+
+impl Shared {
+    /// Queue a request, sending it straight away unless another
+    /// thread is reading in which case it is sent before the
+    /// next read. Kraken sends a heartbeat every second while
+    /// subscribed so this is never long.
+    fn send_later(&self, req: WsReq) -> Result<()> {
+        self.outbox.lock().unwrap().push(req);
+        match self.conn.try_lock() {
+            Ok(mut conn) => self.flush(&mut conn),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Send a request, waiting for the connection if another
+    /// thread is reading, which is never long for the same
+    /// reason as [`Shared::send_later`].
+    fn send_now(&self, req: WsReq) -> Result<()> {
+        self.outbox.lock().unwrap().push(req);
+        let mut conn = self.conn.lock().unwrap();
+        self.flush(&mut conn)
+    }
+
+    fn flush(&self, conn: &mut Conn) -> Result<()> {
+        let queued: Vec<_> = self.outbox.lock().unwrap().drain(..).collect();
+        queued.iter().try_for_each(|req| self.send(conn, req))
+    }
+
+    /// Read the next text frame after sending any queued requests.
+    fn next_text(&self, conn: &mut Conn) -> Result<String> {
+        self.flush(conn)?;
+        loop {
+            match conn.recv_message()? {
+                OwnedMessage::Text(s) => {
+                    self.record(Direction::In, FrameKind::Text, &s);
+                    return Ok(s);
+                }
+                OwnedMessage::Binary(data) => {
+                    self.record(Direction::In, FrameKind::Binary, &hex(&data))
+                }
+                OwnedMessage::Ping(data) => {
+                    self.record(Direction::In, FrameKind::Ping, &hex(&data))
+                }
+                OwnedMessage::Pong(data) => {
+                    self.record(Direction::In, FrameKind::Pong, &hex(&data))
+                }
+                OwnedMessage::Close(data) => {
+                    let text = data
+                        .map(|data| format!("{} {}", data.status_code, data.reason))
+                        .unwrap_or_default();
+                    self.record(Direction::In, FrameKind::Close, &text)
+                }
+            }
+        }
+    }
+
+    /// Pass a message to every feed which wants it, returning
+    /// false if none do.
+    fn route(&self, text: &str) -> bool {
+        let mut routes = self.routes.lock().unwrap();
+        if routes.is_empty() {
+            return false;
+        }
+        match serde_json::from_str::<RespRef>(text) {
+            Ok(resp) => routes.route(resp),
+            // A string with escapes can't be borrowed, see RespRef.
+            Err(_) => match serde_json::from_str::<Resp>(text) {
+                Ok(resp) => routes.route_owned(resp),
+                Err(_) => false,
+            },
+        }
+    }
+
+    fn send(&self, conn: &mut Conn, req: &WsReq) -> Result<()> {
+        let text = serde_json::to_string(req)?;
+        self.record(Direction::Out, FrameKind::Text, &text);
+        conn.send_message(&Message::text(text)).map_err(Error::from)
+    }
+
+    /// Record a frame, giving up on the recorder if it fails
+    /// rather than failing the connection.
+    fn record(&self, direction: Direction, kind: FrameKind, text: &str) {
+        let mut recorder = self.recorder.lock().unwrap();
+        let recorded = match recorder.as_ref() {
+            Some(recorder) => recorder.record(&Frame {
+                time: Timestamp::now(),
+                connection: self.connection,
+                direction,
+                kind,
+                text: text.to_owned(),
+            }),
+            None => Ok(()),
+        };
+        if let Err(e) = recorded {
+            warn!(error = %e, "recording failed, no longer recording");
+            *recorder = None;
+        }
+    }
+
+    fn next_unrouted(&self) -> Result<String> {
+        let mut conn = self.conn.lock().unwrap();
+        loop {
+            let text = self.next_text(&mut conn)?;
+            if !self.route(&text) {
+                return Ok(text);
+            }
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
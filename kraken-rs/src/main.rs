@@ -1,82 +1,878 @@
-mod req;
-mod resp;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use kraken_rs::book::{OrderBook, OutOfSync};
+use kraken_rs::collector::{Collector, Config, LogFormat, SinkConfig};
+use kraken_rs::envelope::Envelope;
+use kraken_rs::record::Recorder;
+use kraken_rs::replay::{ReplaySource, Speed};
+use kraken_rs::req::{BookDepth, OhlcInterval, Subscription, WsReq};
+use kraken_rs::resp::private::OrderSide;
+use kraken_rs::resp::Resp;
+use kraken_rs::rest::KrakenRest;
+use kraken_rs::sink::{CsvSink, Sink};
+use kraken_rs::time::Timestamp;
+use kraken_rs::{Kraken, ENDPOINT};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::env;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
-use crate::req::{OhlcInterval, Subscription, WsReq};
-use anyhow::{Error, Result};
-use resp::ticker::TickerState;
-use websocket::client::sync::Client;
-use websocket::websocket_base::stream::sync::NetworkStream;
-use websocket::ws::dataframe::DataFrame;
-use websocket::{ClientBuilder, Message, OwnedMessage};
+const DEFAULT_CONFIG: &str = "/etc/kraken-rs/config.toml";
 
-const ENDPOINT: &'static str = "wss://ws.kraken.com";
+/// Kraken websocket client, run with no command to start the
+/// collector.
+#[derive(Debug, Parser)]
+#[command(name = "kraken-rs", version)]
+struct Cli {
+    /// Websocket endpoint for market data, wss://ws.kraken.com
+    /// unless given here or, for the collector, in its config.
+    #[arg(long, global = true)]
+    url: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Collect market data as configured until SIGTERM or
+    /// SIGINT. The config defaults to $KRAKEN_CONFIG or else
+    /// /etc/kraken-rs/config.toml.
+    Run { config: Option<String> },
+    /// Write closed candles and tickers for the pairs to CSV
+    /// files.
+    Csv {
+        dir: PathBuf,
+        /// Candle interval in minutes.
+        interval: OhlcInterval,
+        #[arg(required = true)]
+        pairs: Vec<String>,
+    },
+    /// Subscribe to a channel and print every message.
+    Subscribe {
+        #[command(flatten)]
+        channel: ChannelArgs,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Send a ping and wait for the pong.
+    Ping,
+    /// Print Kraken's system status.
+    Status,
+    /// Show the top of a pair's order book as it changes.
+    Book {
+        pair: String,
+        /// Depth to subscribe to.
+        #[arg(long, default_value = "10")]
+        depth: BookDepth,
+        /// Levels shown on each side.
+        #[arg(long, default_value_t = 10)]
+        levels: usize,
+        /// Print the first snapshot and exit.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Fetch candle history from the REST api as CSV.
+    Candles {
+        pair: String,
+        /// Candle interval in minutes.
+        #[arg(long, default_value = "60")]
+        interval: OhlcInterval,
+        /// Only candles after this time, in seconds since the
+        /// epoch.
+        #[arg(long)]
+        since: Option<u64>,
+    },
+    /// Record every frame of a subscription to a directory
+    /// until SIGTERM or SIGINT.
+    Record {
+        dir: PathBuf,
+        #[command(flatten)]
+        channel: ChannelArgs,
+        /// Compress the recording with zstd.
+        #[arg(long)]
+        compress: bool,
+    },
+    /// Print the messages from a recorded file or directory.
+    Replay {
+        path: PathBuf,
+        /// Replay this many times faster than recorded, as fast
+        /// as possible if not given.
+        #[arg(long)]
+        speed: Option<f64>,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Share one connection to Kraken with local tools until
+    /// SIGTERM or SIGINT, serving the latest state over REST
+    /// and forwarding responses to websocket clients.
+    Serve {
+        #[arg(required = true)]
+        pairs: Vec<String>,
+        /// Channels to subscribe to, any of ticker, trade,
+        /// spread, ohlc or book.
+        #[arg(long = "channel", default_values = ["ticker", "ohlc", "book"])]
+        channels: Vec<String>,
+        /// Candle interval in minutes, for ohlc.
+        #[arg(long, default_value = "1")]
+        interval: OhlcInterval,
+        /// Levels on each side, for book.
+        #[arg(long, default_value = "10")]
+        depth: BookDepth,
+        /// Address for the REST api.
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        http: String,
+        /// Address for the websocket.
+        #[arg(long, default_value = "127.0.0.1:8081")]
+        ws: String,
+    },
+    /// Print the messages from a udp or shm sink, reporting
+    /// any that were missed.
+    #[cfg(feature = "dist")]
+    Listen {
+        #[command(flatten)]
+        source: ListenSource,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Live dashboard of the pairs' tickers, with candles and
+    /// the order book of the selected pair.
+    #[cfg(feature = "tui")]
+    Tui {
+        #[arg(required = true)]
+        pairs: Vec<String>,
+        /// Candle interval in minutes.
+        #[arg(long, default_value = "1")]
+        interval: OhlcInterval,
+        /// Book depth to subscribe to.
+        #[arg(long, default_value = "10")]
+        depth: BookDepth,
+    },
+    /// Place an order.
+    AddOrder {
+        #[command(flatten)]
+        trading: TradingArgs,
+        pair: String,
+        #[arg(value_enum)]
+        side: Side,
+        /// Order type e.g. "market" or "limit".
+        order_type: String,
+        volume: String,
+        #[arg(long)]
+        price: Option<String>,
+        /// Only have Kraken check the order, without placing it.
+        #[arg(long)]
+        validate: bool,
+    },
+    /// Cancel orders by id.
+    CancelOrder {
+        #[command(flatten)]
+        trading: TradingArgs,
+        #[arg(required = true)]
+        txids: Vec<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct ChannelArgs {
+    /// One of ticker, trade, spread, ohlc or book.
+    channel: String,
+    #[arg(short, long = "pair", required = true)]
+    pairs: Vec<String>,
+    /// Candle interval in minutes, for ohlc.
+    #[arg(long, default_value = "1")]
+    interval: OhlcInterval,
+    /// Levels on each side, for book.
+    #[arg(long, default_value = "10")]
+    depth: BookDepth,
+}
+
+#[cfg(feature = "dist")]
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+struct ListenSource {
+    /// UDP address or multicast group to receive from.
+    #[arg(long)]
+    udp: Option<String>,
+    /// Ring buffer file to read.
+    #[arg(long)]
+    shm: Option<PathBuf>,
+}
+
+/// Trading commands are refused unless explicitly allowed, so
+/// that a mistyped command can't place an order.
+#[derive(Debug, Args)]
+struct TradingArgs {
+    /// Allow sending trading requests.
+    #[arg(long)]
+    allow_trading: bool,
+    /// Websocket token from the REST GetWebSocketsToken call.
+    #[arg(long, env = "KRAKEN_WS_TOKEN", hide_env_values = true)]
+    token: String,
+    /// Authenticated websocket endpoint.
+    #[arg(long, default_value = "wss://ws-auth.kraken.com")]
+    auth_url: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+enum Format {
+    /// The normalized envelope, see kraken_rs::envelope.
+    Json,
+    /// As Kraken sends it.
+    Wire,
+    /// Rust's debug formatting.
+    Debug,
+}
 
-pub struct Kraken {
-    inner: Client<Box<dyn NetworkStream + Send>>,
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+enum Side {
+    Buy,
+    Sell,
 }
 
-impl Kraken {
-    pub fn new() -> Result<Kraken> {
-        Ok(Kraken {
-            inner: ClientBuilder::new(ENDPOINT)?.connect(None)?,
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let url = cli.url.as_deref().unwrap_or(ENDPOINT);
+    match cli.command {
+        None => run(None, cli.url),
+        Some(Command::Run { config }) => run(config, cli.url),
+        Some(Command::Csv {
+            dir,
+            interval,
+            pairs,
+        }) => csv(url, dir, interval, pairs),
+        Some(Command::Subscribe { channel, format }) => subscribe(url, &channel, format),
+        Some(Command::Ping) => ping(url),
+        Some(Command::Status) => status(url),
+        Some(Command::Book {
+            pair,
+            depth,
+            levels,
+            once,
+        }) => book(url, pair, depth, levels, once),
+        Some(Command::Candles {
+            pair,
+            interval,
+            since,
+        }) => candles(&pair, interval, since),
+        Some(Command::Record {
+            dir,
+            channel,
+            compress,
+        }) => record(url, dir, &channel, compress),
+        Some(Command::Replay {
+            path,
+            speed,
+            format,
+        }) => replay(path, speed, format),
+        Some(Command::Serve {
+            pairs,
+            channels,
+            interval,
+            depth,
+            http,
+            ws,
+        }) => {
+            let channels = channels
+                .iter()
+                .map(|channel| subscription(channel, interval, depth))
+                .collect::<Result<_>>()?;
+            serve(url.to_owned(), pairs, channels, http, ws)
+        }
+        #[cfg(feature = "dist")]
+        Some(Command::Listen { source, format }) => listen(source, format),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui {
+            pairs,
+            interval,
+            depth,
+        }) => tui::run(url, pairs, interval, depth),
+        Some(Command::AddOrder {
+            trading,
+            pair,
+            side,
+            order_type,
+            volume,
+            price,
+            validate,
+        }) => {
+            let side = match side {
+                Side::Buy => OrderSide::Buy,
+                Side::Sell => OrderSide::Sell,
+            };
+            trade(&trading, |token| WsReq::AddOrder {
+                request_id: Some(1),
+                token,
+                pair,
+                side,
+                order_type,
+                price,
+                volume,
+                validate: Some(validate).filter(|&v| v),
+            })
+        }
+        Some(Command::CancelOrder { trading, txids }) => {
+            trade(&trading, |token| WsReq::CancelOrder {
+                request_id: Some(1),
+                token,
+                txid: txids,
+            })
+        }
+    }
+}
+
+fn run(path: Option<String>, url: Option<String>) -> Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => env::var("KRAKEN_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_owned()),
+    };
+    let mut config = Config::load(&path)?;
+    if let Some(url) = url {
+        config.url = url;
+    }
+    init_logging(config.log);
+    let shutdown = shutdown_flag()?;
+    info!(config = %path, "starting");
+    let result = Collector::new(config).and_then(|mut collector| collector.run(&shutdown));
+    if let Err(e) = &result {
+        error!(error = %e, "stopped");
+    }
+    result
+}
+
+/// Log at info and above unless RUST_LOG says otherwise.
+fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let logger = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Json => logger.json().init(),
+        LogFormat::Text => logger.init(),
+    }
+}
+
+/// Set once SIGTERM or SIGINT is received.
+fn shutdown_flag() -> Result<Arc<AtomicBool>> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, shutdown.clone())?;
+    }
+    Ok(shutdown)
+}
+
+fn csv(url: &str, dir: PathBuf, interval: OhlcInterval, pairs: Vec<String>) -> Result<()> {
+    let mut sink = CsvSink::new(dir)?;
+    let mut client = Kraken::connect(url)?;
+    for subscription in [Subscription::Ohlc { interval }, Subscription::Ticker] {
+        client.send_req(WsReq::Subscribe {
+            request_id: None,
+            pair: pairs.clone(),
+            subscription,
+        })?;
+    }
+    loop {
+        let resp = recv(&mut client)?;
+        sink.write(&resp, Timestamp::now())?;
+        // Kraken sends a heartbeat every second when quiet.
+        if resp == Resp::Heartbeat {
+            sink.flush()?;
+        }
+    }
+}
+
+fn serve(
+    url: String,
+    pairs: Vec<String>,
+    channels: Vec<Subscription>,
+    http: String,
+    ws: String,
+) -> Result<()> {
+    let config = Config {
+        url,
+        pairs,
+        channels,
+        sinks: vec![SinkConfig::Fanout {
+            http: Some(http),
+            ws: Some(ws),
+        }],
+        flush_secs: 10,
+        log: LogFormat::Text,
+    };
+    init_logging(config.log);
+    let shutdown = shutdown_flag()?;
+    Collector::new(config)?.run(&shutdown)
+}
+
+fn subscription(channel: &str, interval: OhlcInterval, depth: BookDepth) -> Result<Subscription> {
+    Ok(match channel {
+        "ticker" => Subscription::Ticker,
+        "trade" => Subscription::Trade,
+        "spread" => Subscription::Spread,
+        "ohlc" => Subscription::Ohlc { interval },
+        "book" => Subscription::Book { depth },
+        channel => bail!("Unknown channel {}", channel),
+    })
+}
+
+impl ChannelArgs {
+    fn subscription(&self) -> Result<Subscription> {
+        subscription(&self.channel, self.interval, self.depth)
+    }
+
+    fn subscribe(&self, client: &mut Kraken) -> Result<()> {
+        client.send_req(WsReq::Subscribe {
+            request_id: None,
+            pair: self.pairs.clone(),
+            subscription: self.subscription()?,
         })
     }
+}
 
-    pub fn send_req(&mut self, req: WsReq) -> Result<()> {
-        self.inner
-            .send_message(&Message::text(serde_json::to_string(&req)?))
-            .map_err(Error::from)
+/// The next response, skipping frames which can't be parsed
+/// so that only losing the connection ends a command.
+fn recv(client: &mut Kraken) -> Result<Resp> {
+    loop {
+        match client.recv() {
+            Err(e) if e.is::<serde_json::Error>() => {
+                eprintln!("skipping unparsable message: {}", e)
+            }
+            resp => return resp,
+        }
     }
 }
 
-fn main() -> Result<()> {
-    let mut client = Kraken::new()?;
+fn print(resp: &Resp, received: Timestamp, format: Format) -> Result<()> {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string(&Envelope::new(resp, received))?),
+        Format::Wire => println!("{}", resp.to_wire()?),
+        Format::Debug => println!("{:?}", resp),
+    }
+    Ok(())
+}
+
+fn subscribe(url: &str, channel: &ChannelArgs, format: Format) -> Result<()> {
+    let mut client = Kraken::connect(url)?;
+    channel.subscribe(&mut client)?;
+    loop {
+        let resp = recv(&mut client)?;
+        if resp != Resp::Heartbeat {
+            print(&resp, Timestamp::now(), format)?;
+        }
+    }
+}
+
+fn ping(url: &str) -> Result<()> {
+    let mut client = Kraken::connect(url)?;
+    // Not the wall clock, which can step backwards.
+    let sent = Instant::now();
     client.send_req(WsReq::Ping {
-        request_id: Some(10),
-    })?;
-    println!(
-        "{}",
-        String::from_utf8(client.inner.recv_message()?.take_payload())?
-    );
-    println!(
-        "{}",
-        String::from_utf8(client.inner.recv_message()?.take_payload())?
-    );
-
-    client.send_req(WsReq::Subscribe {
-        request_id: Some(12),
-        pair: vec!["ETH/USD".to_string()],
-        subscription: Subscription::Ohlc {
-            interval: OhlcInterval::Mins15,
-        },
-    })?;
-    client.send_req(WsReq::Subscribe {
-        request_id: Some(12),
-        pair: vec!["ETH/USD".to_string()],
-        subscription: Subscription::Ticker,
+        request_id: Some(1),
     })?;
+    loop {
+        if let Resp::Pong(_) = recv(&mut client)? {
+            let millis = sent.elapsed().as_secs_f64() * 1000.0;
+            println!("pong in {:.1}ms", millis);
+            return Ok(());
+        }
+    }
+}
 
-    for message in client
-        .inner
-        .incoming_messages()
-        .filter_map(|x| x.ok())
-        .filter_map(|x| match x {
-            OwnedMessage::Text(s) => serde_json::from_str::<resp::Resp>(s.as_str()).ok(),
-            _ => None,
-        })
-    {
-        match message {
-            resp::Resp::Ticker(TickerState { channel_id: _, .. }) => {}
-            _ => {}
+fn status(url: &str) -> Result<()> {
+    let mut client = Kraken::connect(url)?;
+    loop {
+        if let Resp::SystemStatus(status) = recv(&mut client)? {
+            println!(
+                "{:?} version {} connection {}",
+                status.status, status.version, status.connection_id
+            );
+            return Ok(());
         }
-        println!("{:?}", message)
     }
+}
 
-    client.send_req(WsReq::Unsubscribe {
-        request_id: Some(12),
-        pair: vec!["ETH/USD".to_string()],
-        subscription: Subscription::Ticker,
-    })?;
+fn book(url: &str, pair: String, depth: BookDepth, levels: usize, once: bool) -> Result<()> {
+    let mut client = Kraken::connect(url)?;
+    let subscribe = WsReq::Subscribe {
+        request_id: None,
+        pair: vec![pair.clone()],
+        subscription: Subscription::Book { depth },
+    };
+    client.send_req(subscribe.clone())?;
+    let mut order_book = OrderBook::new(depth);
+    loop {
+        if let Resp::Book(update) = recv(&mut client)? {
+            match order_book.apply(&update) {
+                // Subscribe again for a new snapshot.
+                Err(e) if e.is::<OutOfSync>() => {
+                    eprintln!("{}", e);
+                    client.send_req(WsReq::Unsubscribe {
+                        request_id: None,
+                        pair: vec![pair.clone()],
+                        subscription: Subscription::Book { depth },
+                    })?;
+                    client.send_req(subscribe.clone())?;
+                    continue;
+                }
+                applied => applied?,
+            }
+            let rendered = render_book(&pair, &order_book, levels);
+            if once {
+                print!("{}", rendered);
+                return Ok(());
+            }
+            // Clear the terminal and redraw from the top.
+            print!("\x1b[2J\x1b[H{}", rendered);
+        }
+    }
+}
+
+/// Asks from the highest shown down to the best, then bids from
+/// the best down, so the spread is in the middle.
+fn render_book(pair: &str, book: &OrderBook, levels: usize) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:>20} {:>20}   {}", "price", "volume", pair);
+    let asks: Vec<_> = book.asks().take(levels).collect();
+    for level in asks.iter().rev() {
+        let _ = writeln!(out, "{:>20} {:>20}   ask", level.price, level.volume);
+    }
+    let _ = writeln!(out, "{:-<45}", "");
+    for level in book.bids().take(levels) {
+        let _ = writeln!(out, "{:>20} {:>20}   bid", level.price, level.volume);
+    }
+    out
+}
+
+fn candles(pair: &str, interval: OhlcInterval, since: Option<u64>) -> Result<()> {
+    let candles = KrakenRest::new().ohlc(pair, interval, since.map(Timestamp::from_secs))?;
+    println!("start,open,high,low,close,vwap,volume,count");
+    for c in candles {
+        println!(
+            "{},{},{},{},{},{},{},{}",
+            c.start, c.open, c.high, c.low, c.close, c.vwap, c.volume, c.count
+        );
+    }
+    Ok(())
+}
+
+fn record(url: &str, dir: PathBuf, channel: &ChannelArgs, compress: bool) -> Result<()> {
+    let recorder = Recorder::new(dir)?;
+    #[cfg(feature = "zstd")]
+    let recorder = recorder.compress(compress);
+    #[cfg(not(feature = "zstd"))]
+    if compress {
+        bail!("Built without the zstd feature");
+    }
+    let shutdown = shutdown_flag()?;
+    let mut client = Kraken::connect(url)?;
+    client.record(recorder.clone());
+    channel.subscribe(&mut client)?;
+    // Heartbeats arrive every second, so the flag is checked
+    // at least that often.
+    while !shutdown.load(Ordering::Relaxed) {
+        match client.recv_with(|resp| matches!(resp, Resp::Heartbeat)) {
+            Ok(true) => recorder.flush()?,
+            Ok(false) => {}
+            // Recorded all the same.
+            Err(e) if e.is::<serde_json::Error>() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    recorder.flush()
+}
 
+fn replay(path: PathBuf, speed: Option<f64>, format: Format) -> Result<()> {
+    let speed = speed.map_or(Speed::Max, Speed::Factor);
+    let mut source = ReplaySource::open(path)?.speed(speed)?;
+    for resp in source.incoming() {
+        if resp != Resp::Heartbeat {
+            print(&resp, Timestamp::now(), format)?;
+        }
+    }
     Ok(())
 }
+
+#[cfg(feature = "dist")]
+fn listen(source: ListenSource, format: Format) -> Result<()> {
+    use kraken_rs::dist::{Message, UdpSubscriber};
+    let mut next: Box<dyn FnMut() -> Result<Message>> = match (source.udp, source.shm) {
+        (Some(addr), _) => {
+            let mut subscriber = UdpSubscriber::join(addr.as_str())?;
+            Box::new(move || subscriber.recv())
+        }
+        #[cfg(unix)]
+        (_, Some(path)) => {
+            let mut subscriber = kraken_rs::dist::ShmSubscriber::open(path)?;
+            Box::new(move || subscriber.recv())
+        }
+        _ => bail!("Nothing to listen to"),
+    };
+    loop {
+        let message = next()?;
+        if message.missed > 0 {
+            eprintln!("missed {} messages before {}", message.missed, message.seq);
+        }
+        if message.resp != Resp::Heartbeat {
+            print(&message.resp, message.received, format)?;
+        }
+    }
+}
+
+/// Send a trading request on the authenticated endpoint and
+/// print the reply.
+fn trade<F: FnOnce(String) -> WsReq>(trading: &TradingArgs, req: F) -> Result<()> {
+    if !trading.allow_trading {
+        bail!("Trading commands need --allow-trading");
+    }
+    let mut client = Kraken::connect(&trading.auth_url)?;
+    client.send_req(req(trading.token.clone()))?;
+    loop {
+        match recv(&mut client)? {
+            resp @ Resp::AddOrderStatus(_) | resp @ Resp::CancelOrderStatus(_) => {
+                println!("{}", resp.to_wire()?);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+mod tui {
+    use anyhow::Result;
+    use kraken_rs::dashboard::{Connection, Dashboard};
+    use kraken_rs::req::{BookDepth, OhlcInterval, Subscription, WsReq};
+    use kraken_rs::resp::Resp;
+    use kraken_rs::Kraken;
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use ratatui::DefaultTerminal;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+    enum Update {
+        Resp(Box<Resp>),
+        Connection(Connection),
+    }
+
+    pub fn run(
+        url: &str,
+        pairs: Vec<String>,
+        interval: OhlcInterval,
+        depth: BookDepth,
+    ) -> Result<()> {
+        let (sender, updates) = mpsc::channel();
+        let subscriptions = vec![
+            Subscription::Ticker,
+            Subscription::Ohlc { interval },
+            Subscription::Book { depth },
+        ];
+        let (url, subscribed) = (url.to_owned(), pairs.clone());
+        thread::spawn(move || read(&url, &subscribed, &subscriptions, &sender));
+
+        let mut dashboard = Dashboard::new(pairs, interval, depth)?;
+        let mut terminal = ratatui::init();
+        let result = draw(&mut terminal, &mut dashboard, &updates);
+        ratatui::restore();
+        result
+    }
+
+    /// Redraw after every key press or batch of updates until
+    /// asked to quit.
+    fn draw(
+        terminal: &mut DefaultTerminal,
+        dashboard: &mut Dashboard,
+        updates: &Receiver<Update>,
+    ) -> Result<()> {
+        loop {
+            terminal.draw(|frame| dashboard.render(frame))?;
+            if event::poll(Duration::from_millis(200))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                            KeyCode::Up | KeyCode::Char('k') => dashboard.select_previous(),
+                            KeyCode::Down | KeyCode::Char('j') => dashboard.select_next(),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            for update in updates.try_iter() {
+                match update {
+                    Update::Resp(resp) => dashboard.apply(&resp),
+                    Update::Connection(connection) => dashboard.set_connection(connection),
+                }
+            }
+        }
+    }
+
+    /// Pass on every response, reconnecting whenever the
+    /// connection is lost, until the dashboard is closed.
+    fn read(url: &str, pairs: &[String], subscriptions: &[Subscription], sender: &Sender<Update>) {
+        loop {
+            let _ = sender.send(Update::Connection(Connection::Connecting));
+            let lost = match subscribe(url, pairs, subscriptions) {
+                Ok(mut client) => {
+                    if sender
+                        .send(Update::Connection(Connection::Connected))
+                        .is_err()
+                    {
+                        return;
+                    }
+                    loop {
+                        match client.recv() {
+                            Ok(resp) => {
+                                if sender.send(Update::Resp(Box::new(resp))).is_err() {
+                                    return;
+                                }
+                            }
+                            Err(e) if e.is::<serde_json::Error>() => {}
+                            Err(e) => break e,
+                        }
+                    }
+                }
+                Err(e) => e,
+            };
+            if sender
+                .send(Update::Connection(Connection::Lost(lost.to_string())))
+                .is_err()
+            {
+                return;
+            }
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn subscribe(url: &str, pairs: &[String], subscriptions: &[Subscription]) -> Result<Kraken> {
+        let mut client = Kraken::connect(url)?;
+        for subscription in subscriptions {
+            client.send_req(WsReq::Subscribe {
+                request_id: None,
+                pair: pairs.to_vec(),
+                subscription: subscription.clone(),
+            })?;
+        }
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+    use kraken_rs::resp::book::Book;
+
+    #[test]
+    fn cli_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn url_overrides_only_when_given() -> Result<()> {
+        let cli = Cli::try_parse_from(["kraken-rs", "run", "--url", "ws://localhost:8080"])?;
+        assert_eq!(Some("ws://localhost:8080"), cli.url.as_deref());
+        assert_eq!(None, Cli::try_parse_from(["kraken-rs"])?.url);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_subscriptions() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "kraken-rs",
+            "subscribe",
+            "ohlc",
+            "-p",
+            "XBT/USD",
+            "--pair",
+            "ETH/USD",
+            "--interval",
+            "15",
+            "--format",
+            "wire",
+        ])?;
+        match cli.command {
+            Some(Command::Subscribe { channel, format }) => {
+                assert_eq!(vec!["XBT/USD", "ETH/USD"], channel.pairs);
+                assert_eq!(
+                    Subscription::Ohlc {
+                        interval: OhlcInterval::Mins15
+                    },
+                    channel.subscription()?
+                );
+                assert_eq!(Format::Wire, format);
+            }
+            other => panic!("{:?}", other),
+        }
+        for args in [
+            &[
+                "kraken-rs",
+                "subscribe",
+                "ohlc",
+                "-p",
+                "XBT/USD",
+                "--interval",
+                "7",
+            ][..],
+            &["kraken-rs", "book", "XBT/USD", "--depth", "20"],
+            &["kraken-rs", "subscribe", "ticker"],
+            &["kraken-rs", "serve"],
+            &["kraken-rs", "listen"],
+            &[
+                "kraken-rs",
+                "listen",
+                "--udp",
+                "239.1.1.1:5000",
+                "--shm",
+                "ring",
+            ],
+        ] {
+            assert!(Cli::try_parse_from(args).is_err(), "{:?}", args);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn trading_needs_allowing() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "kraken-rs",
+            "cancel-order",
+            "--token",
+            "abc",
+            "OGTT3Y-C6I3P-XRI6HX",
+        ])?;
+        match cli.command {
+            Some(Command::CancelOrder { trading, .. }) => {
+                let err = trade(&trading, |_| unreachable!()).unwrap_err();
+                assert_eq!("Trading commands need --allow-trading", err.to_string());
+            }
+            other => panic!("{:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn renders_book() -> Result<()> {
+        let snapshot: Book = serde_json::from_str(
+            r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"],["5541.80000","0.33000000","1534614098.345543"]],"bs":[["5541.20000","1.52900000","1534614248.765567"],["5539.90000","0.30000000","1534614241.769870"]]},"book-10","XBT/USD"]"#,
+        )?;
+        let mut book = OrderBook::new(BookDepth::N10);
+        book.apply(&snapshot)?;
+        let rendered = render_book("XBT/USD", &book, 1);
+        let lines: Vec<&str> = rendered.lines().map(str::trim).collect();
+        assert_eq!(
+            vec![
+                "price               volume   XBT/USD",
+                "5541.30000           2.50700000   ask",
+                "---------------------------------------------",
+                "5541.20000           1.52900000   bid",
+            ],
+            lines
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use crate::book::{BookTop, OrderBook};
+use crate::req::OhlcInterval;
+use crate::resp::intern::PairInterner;
+use crate::resp::ohlc::Ohlc;
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::Trade;
+use crate::resp::Resp;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Changes a watcher can fall behind by before it is dropped.
+const WATCH_QUEUE: usize = 1024;
+
+/// The latest market data seen for one pair.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PairState {
+    pub ticker: Option<TickerState>,
+    pub last_trade: Option<Trade>,
+    pub spread: Option<Spread>,
+    /// The in progress candle for each subscribed interval.
+    pub candles: HashMap<OhlcInterval, Ohlc>,
+    pub book: Option<BookTop>,
+}
+
+/// What changed for a pair after a message was ingested.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ChangeKind {
+    Ticker,
+    Trade,
+    Spread,
+    Candle(OhlcInterval),
+    Book,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Change {
+    pub pair: Arc<str>,
+    pub kind: ChangeKind,
+}
+
+/// Latest state of the market for every pair, built from the
+/// messages received on one connection. Cloning gives another
+/// handle onto the same state so that it can be fed by the
+/// thread reading the connection and read from any others.
+#[derive(Debug, Clone, Default)]
+pub struct MarketState {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    pairs: RwLock<Pairs>,
+    watchers: Mutex<Vec<SyncSender<Change>>>,
+}
+
+#[derive(Debug, Default)]
+struct Pairs {
+    interner: PairInterner,
+    states: HashMap<Arc<str>, PairState>,
+    // Full books are kept apart from the state so that
+    // taking a snapshot only copies the top of each.
+    books: HashMap<Arc<str>, OrderBook>,
+}
+
+impl MarketState {
+    pub fn new() -> MarketState {
+        Default::default()
+    }
+
+    /// Update the state from a message, messages which are not
+    /// market data are ignored. Errors only if a book message
+    /// holds a price or volume which is not a number, or fails
+    /// its checksum with an [`OutOfSync`](crate::book::OutOfSync)
+    /// error, in which case the pair's book is empty until the
+    /// next snapshot.
+    pub fn ingest(&self, resp: Resp) -> Result<()> {
+        let (change, applied) = self.update(resp);
+        if let Some(change) = change {
+            let mut watchers = self.inner.watchers.lock().unwrap();
+            watchers.retain(|watcher| watcher.try_send(change.clone()).is_ok());
+        }
+        applied
+    }
+
+    // The change along with the result of applying a book
+    // message, which changes the book even when it fails.
+    fn update(&self, resp: Resp) -> (Option<Change>, Result<()>) {
+        let mut pairs = self.inner.pairs.write().unwrap();
+        let Pairs {
+            interner,
+            states,
+            books,
+        } = &mut *pairs;
+        let pair = match &resp {
+            Resp::Ticker(ticker) => interner.intern(&ticker.pair),
+            Resp::Ohlc(ohlc) => interner.intern(&ohlc.pair),
+            Resp::Trade(trades) => interner.intern(&trades.pair),
+            Resp::Spread(spread) => interner.intern(&spread.pair),
+            Resp::Book(book) => interner.intern(&book.pair),
+            _ => return (None, Ok(())),
+        };
+        let mut applied = Ok(());
+        let state = states.entry(pair.clone()).or_default();
+        let kind = match resp {
+            Resp::Ticker(ticker) => {
+                state.ticker = Some(ticker);
+                ChangeKind::Ticker
+            }
+            Resp::Ohlc(ohlc) => {
+                let interval = ohlc.interval;
+                // Ignore updates to a candle which has been replaced.
+                if state
+                    .candles
+                    .get(&interval)
+                    .is_some_and(|current| ohlc.etime < current.etime)
+                {
+                    return (None, Ok(()));
+                }
+                state.candles.insert(interval, ohlc);
+                ChangeKind::Candle(interval)
+            }
+            Resp::Trade(mut trades) => match trades.trades.pop() {
+                Some(trade) => {
+                    state.last_trade = Some(trade);
+                    ChangeKind::Trade
+                }
+                None => return (None, Ok(())),
+            },
+            Resp::Spread(spread) => {
+                state.spread = Some(spread);
+                ChangeKind::Spread
+            }
+            Resp::Book(book) => {
+                let order_book = books
+                    .entry(pair.clone())
+                    .or_insert_with(|| OrderBook::new(book.depth));
+                if book.snapshot && order_book.depth() != book.depth {
+                    *order_book = OrderBook::new(book.depth);
+                }
+                applied = order_book.apply(&book);
+                state.book = Some(order_book.top());
+                ChangeKind::Book
+            }
+            _ => return (None, Ok(())),
+        };
+        (Some(Change { pair, kind }), applied)
+    }
+
+    /// Receive a change each time a message updates the state,
+    /// sent after the update so reading the state on receipt
+    /// sees at least that update. A watcher which falls 1024
+    /// changes behind is dropped, disconnecting its receiver.
+    pub fn subscribe(&self) -> Receiver<Change> {
+        let (sender, receiver) = sync_channel(WATCH_QUEUE);
+        self.inner.watchers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Copy of everything known about a pair.
+    pub fn snapshot(&self, pair: &str) -> Option<PairState> {
+        self.read(pair, |state| Some(state.clone()))
+    }
+
+    pub fn ticker(&self, pair: &str) -> Option<TickerState> {
+        self.read(pair, |state| state.ticker.clone())
+    }
+
+    pub fn last_trade(&self, pair: &str) -> Option<Trade> {
+        self.read(pair, |state| state.last_trade.clone())
+    }
+
+    pub fn spread(&self, pair: &str) -> Option<Spread> {
+        self.read(pair, |state| state.spread.clone())
+    }
+
+    pub fn candle(&self, pair: &str, interval: OhlcInterval) -> Option<Ohlc> {
+        self.read(pair, |state| state.candles.get(&interval).cloned())
+    }
+
+    pub fn book_top(&self, pair: &str) -> Option<BookTop> {
+        self.read(pair, |state| state.book.clone())
+    }
+
+    /// Every pair any market data has been seen for.
+    pub fn pairs(&self) -> Vec<Arc<str>> {
+        let pairs = self.inner.pairs.read().unwrap();
+        let mut names: Vec<_> = pairs.states.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn read<T, F>(&self, pair: &str, f: F) -> Option<T>
+    where
+        F: FnOnce(&PairState) -> Option<T>,
+    {
+        self.inner
+            .pairs
+            .read()
+            .unwrap()
+            .states
+            .get(pair)
+            .and_then(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+    const STALE_OHLC: &str = r#"[42,["1542057014.748456","1542057060.435743","3500.00000","3500.00000","3500.00000","3500.00000","3500.00000","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+    const TRADE: &str = r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["6060.00000","0.02455000","1534614057.324998","b","l",""]],"trade","XBT/USD"]"#;
+    const BOOK: &str = r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-10","XBT/USD"]"#;
+    const BOOK_UPDATE: &str = r#"[0,{"b":[["5541.20000","0.00000000","1534614335.345903"]],"c":"425429334"},"book-10","XBT/USD"]"#;
+
+    fn ingest(market: &MarketState, msg: &str) -> Result<()> {
+        market.ingest(serde_json::from_str(msg)?)
+    }
+
+    #[test]
+    fn keeps_latest_state() -> Result<()> {
+        let market = MarketState::new();
+        for msg in &[TICKER, OHLC, STALE_OHLC, TRADE, BOOK, BOOK_UPDATE] {
+            ingest(&market, msg)?;
+        }
+        assert_eq!(vec![Arc::<str>::from("XBT/USD")], market.pairs());
+        assert_eq!("5525.10000", market.ticker("XBT/USD").unwrap().close.today);
+        assert_eq!(
+            "3586.70000",
+            market.candle("XBT/USD", OhlcInterval::Mins5).unwrap().open
+        );
+        assert_eq!("6060.00000", market.last_trade("XBT/USD").unwrap().price);
+        let top = market.book_top("XBT/USD").unwrap();
+        assert_eq!(None, top.bid);
+        assert_eq!("5541.30000", top.ask.unwrap().price);
+        assert_eq!(None, market.spread("XBT/USD"));
+        assert_eq!(None, market.snapshot("ETH/USD"));
+        Ok(())
+    }
+
+    #[test]
+    fn notifies_changes() -> Result<()> {
+        let market = MarketState::new();
+        let changes = market.subscribe();
+        ingest(&market, TICKER)?;
+        ingest(&market, STALE_OHLC)?;
+        ingest(&market, OHLC)?;
+        ingest(&market, STALE_OHLC)?;
+        ingest(&market, r#"{"event":"heartbeat"}"#)?;
+        let kinds: Vec<_> = changes.try_iter().map(|c| c.kind).collect();
+        assert_eq!(
+            vec![
+                ChangeKind::Ticker,
+                ChangeKind::Candle(OhlcInterval::Mins5),
+                ChangeKind::Candle(OhlcInterval::Mins5)
+            ],
+            kinds
+        );
+
+        drop(changes);
+        ingest(&market, TICKER)?;
+        assert!(market.inner.watchers.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn drops_watchers_which_fall_behind() -> Result<()> {
+        let market = MarketState::new();
+        let changes = market.subscribe();
+        for _ in 0..=WATCH_QUEUE {
+            ingest(&market, TICKER)?;
+        }
+        assert!(market.inner.watchers.lock().unwrap().is_empty());
+        assert_eq!(WATCH_QUEUE, changes.try_iter().count());
+        assert!(changes.recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn shared_between_threads() -> Result<()> {
+        let market = MarketState::new();
+        let changes = market.subscribe();
+        let writer = market.clone();
+        thread::spawn(move || ingest(&writer, TICKER))
+            .join()
+            .unwrap()?;
+        let change = changes.recv()?;
+        assert_eq!("XBT/USD", &*change.pair);
+        assert!(market.ticker(&change.pair).is_some());
+        Ok(())
+    }
+}
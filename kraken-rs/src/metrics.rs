@@ -0,0 +1,354 @@
+use crate::envelope::Envelope;
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Upper bounds in seconds of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Feed health and prices in the Prometheus text format, from
+/// the responses recorded by [`Metrics::record`] or written to
+/// it as a [`Sink`]. The metrics are
+///
+/// - `kraken_last_price{pair}`: the last traded price, from
+///   tickers and trades
+/// - `kraken_spread{pair}`: the best ask less the best bid,
+///   from tickers and spreads
+/// - `kraken_messages_total{channel}`: responses received by
+///   channel or event name, graph with `rate()` for message
+///   rates
+/// - `kraken_reconnects_total`: see [`Metrics::record_reconnect`]
+/// - `kraken_sink_errors_total{sink}`: see
+///   [`Metrics::record_sink_error`]
+/// - `kraken_latency_seconds{channel}`: a histogram of the time
+///   from Kraken's time for a message to when it was received,
+///   for channels which give a time. Differences between the
+///   clocks can make this negative, which is counted as zero.
+///
+/// Clones share the same metrics so one can be kept to serve
+/// them, see [`Metrics::serve`].
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last_price: BTreeMap<String, Decimal>,
+    spread: BTreeMap<String, Decimal>,
+    messages: BTreeMap<&'static str, u64>,
+    reconnects: u64,
+    sink_errors: BTreeMap<&'static str, u64>,
+    latency: BTreeMap<&'static str, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Counts for each bucket alone, summed when rendered.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Update the metrics with a response received at the
+    /// given time.
+    pub fn record(&self, resp: &Resp, received: Timestamp) {
+        let envelope = Envelope::new(resp, received);
+        let mut state = self.state.lock().unwrap();
+        *state.messages.entry(envelope.kind()).or_insert(0) += 1;
+        if let Some(time) = envelope.exchange_time() {
+            let latency = received.as_micros() as f64 - time.as_micros() as f64;
+            state
+                .latency
+                .entry(envelope.kind())
+                .or_default()
+                .observe((latency / 1e6).max(0.0));
+        }
+        match resp {
+            Resp::Ticker(ticker) => {
+                state.set_price(&ticker.pair, &ticker.close.today);
+                state.set_spread(&ticker.pair, &ticker.ask.price, &ticker.bid.price);
+            }
+            Resp::Trade(trades) => {
+                if let Some(last) = trades.trades.iter().max_by_key(|t| t.time) {
+                    state.set_price(&trades.pair, &last.price);
+                }
+            }
+            Resp::Spread(spread) => state.set_spread(&spread.pair, &spread.ask, &spread.bid),
+            _ => {}
+        }
+    }
+
+    /// Count a reconnection to Kraken, made by whatever owns
+    /// the client.
+    pub fn record_reconnect(&self) {
+        self.state.lock().unwrap().reconnects += 1;
+    }
+
+    /// Count a sink of the given type failing to write or
+    /// flush, for whatever owns the sinks.
+    pub fn record_sink_error(&self, sink: &'static str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .sink_errors
+            .entry(sink)
+            .or_insert(0) += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        let _ = state.render(&mut out);
+        out
+    }
+
+    /// Serve the metrics at "/metrics" on the given address from
+    /// a background thread, returning the address bound to.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A failed scrape only affects that scrape.
+                let _ = metrics.respond(stream);
+            }
+        });
+        Ok(addr)
+    }
+
+    fn respond(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers, scrapes have no body.
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let mut parts = request_line.split_whitespace();
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                ("200 OK", "text/plain; version=0.0.4", self.render())
+            }
+            _ => ("404 Not Found", "text/plain", "Not found\n".to_owned()),
+        };
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+}
+
+impl Sink for Metrics {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        self.record(resp, received);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl State {
+    // Prices which don't parse are left out rather than failing
+    // the message.
+    fn set_price(&mut self, pair: &str, price: &str) {
+        if let Ok(price) = Decimal::from_str(price) {
+            self.last_price.insert(pair.to_owned(), price);
+        }
+    }
+
+    fn set_spread(&mut self, pair: &str, ask: &str, bid: &str) {
+        if let (Ok(ask), Ok(bid)) = (Decimal::from_str(ask), Decimal::from_str(bid)) {
+            self.spread.insert(pair.to_owned(), ask - bid);
+        }
+    }
+
+    fn render(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "# HELP kraken_last_price Last traded price.")?;
+        writeln!(out, "# TYPE kraken_last_price gauge")?;
+        for (pair, price) in &self.last_price {
+            writeln!(
+                out,
+                "kraken_last_price{{pair=\"{}\"}} {}",
+                label(pair),
+                price
+            )?;
+        }
+        writeln!(out, "# HELP kraken_spread Best ask less best bid.")?;
+        writeln!(out, "# TYPE kraken_spread gauge")?;
+        for (pair, spread) in &self.spread {
+            writeln!(out, "kraken_spread{{pair=\"{}\"}} {}", label(pair), spread)?;
+        }
+        writeln!(out, "# HELP kraken_messages_total Responses received.")?;
+        writeln!(out, "# TYPE kraken_messages_total counter")?;
+        for (channel, count) in &self.messages {
+            writeln!(
+                out,
+                "kraken_messages_total{{channel=\"{}\"}} {}",
+                channel, count
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP kraken_reconnects_total Reconnections to Kraken."
+        )?;
+        writeln!(out, "# TYPE kraken_reconnects_total counter")?;
+        writeln!(out, "kraken_reconnects_total {}", self.reconnects)?;
+        writeln!(
+            out,
+            "# HELP kraken_sink_errors_total Failed writes and flushes to sinks."
+        )?;
+        writeln!(out, "# TYPE kraken_sink_errors_total counter")?;
+        for (sink, count) in &self.sink_errors {
+            writeln!(
+                out,
+                "kraken_sink_errors_total{{sink=\"{}\"}} {}",
+                sink, count
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP kraken_latency_seconds Time from Kraken's timestamp to receipt."
+        )?;
+        writeln!(out, "# TYPE kraken_latency_seconds histogram")?;
+        for (channel, histogram) in &self.latency {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "kraken_latency_seconds_bucket{{channel=\"{}\",le=\"{}\"}} {}",
+                    channel, bound, cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "kraken_latency_seconds_bucket{{channel=\"{}\",le=\"+Inf\"}} {}",
+                channel, histogram.count
+            )?;
+            writeln!(
+                out,
+                "kraken_latency_seconds_sum{{channel=\"{}\"}} {}",
+                channel, histogram.sum
+            )?;
+            writeln!(
+                out,
+                "kraken_latency_seconds_count{{channel=\"{}\"}} {}",
+                channel, histogram.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// A label value with backslashes, quotes and newlines escaped.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const TRADES: &str = r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["5542.00000","0.02455000","1534614057.324998","b","m",""]],"trade","XBT/USD"]"#;
+    const SPREAD: &str = r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#;
+
+    #[test]
+    fn records_prices_counts_and_latency() -> Result<()> {
+        let metrics = Metrics::new();
+        metrics.record(&serde_json::from_str(TICKER)?, Timestamp::now());
+        let trades_received = Timestamp::from_micros(1534614057324998) + Duration::from_millis(30);
+        metrics.record(&serde_json::from_str(TRADES)?, trades_received);
+        let spread_received = Timestamp::from_micros(1542057299545897) + Duration::from_secs(2);
+        metrics.record(&serde_json::from_str(SPREAD)?, spread_received);
+        metrics.record_reconnect();
+        metrics.record_sink_error("csv");
+
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            vec![
+                "kraken_last_price{pair=\"XBT/USD\"} 5542.00000",
+                "kraken_spread{pair=\"XBT/USD\"} 1.60000",
+                "kraken_messages_total{channel=\"spread\"} 1",
+                "kraken_messages_total{channel=\"ticker\"} 1",
+                "kraken_messages_total{channel=\"trade\"} 1",
+                "kraken_reconnects_total 1",
+                "kraken_sink_errors_total{sink=\"csv\"} 1",
+            ],
+            lines[..7].to_vec()
+        );
+        assert!(lines.contains(&"kraken_latency_seconds_bucket{channel=\"spread\",le=\"1\"} 0"));
+        assert!(lines.contains(&"kraken_latency_seconds_bucket{channel=\"spread\",le=\"2.5\"} 1"));
+        assert!(lines.contains(&"kraken_latency_seconds_bucket{channel=\"trade\",le=\"0.025\"} 0"));
+        assert!(lines.contains(&"kraken_latency_seconds_bucket{channel=\"trade\",le=\"0.05\"} 1"));
+        assert!(lines.contains(&"kraken_latency_seconds_count{channel=\"trade\"} 1"));
+        // Tickers have no time from Kraken.
+        assert!(!text.contains("channel=\"ticker\",le"));
+        Ok(())
+    }
+
+    #[test]
+    fn serves_metrics() -> Result<()> {
+        let metrics = Metrics::new();
+        let addr = metrics.serve("127.0.0.1:0")?;
+        metrics.record_reconnect();
+
+        let get = |path: &str| -> Result<String> {
+            let mut stream = TcpStream::connect(addr)?;
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        let response = get("/metrics")?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&metrics.render()));
+        assert!(response.contains("\nkraken_reconnects_total 1\n"));
+        assert!(get("/")?.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,923 @@
+use crate::req::{Subscription, WsReq};
+use crate::resp::private::OrderSide;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use websocket::sync::server::upgrade::IntoWs;
+use websocket::sync::Writer;
+use websocket::{Message, OwnedMessage};
+
+const VERSION: &str = "1.9.0";
+
+/// A stand in for Kraken's websocket API running on a local
+/// port, for testing clients without a network. It answers
+/// pings, subscriptions and order requests as Kraken does,
+/// sends heartbeats while a connection has subscriptions and
+/// can be scripted with data frames to send on subscribing or
+/// straight away. Every request received is kept so tests
+/// can check what was sent.
+///
+/// Faults can be injected to check how clients cope when
+/// things go wrong: dropped connections, sockets which go
+/// quiet without closing, delayed or reordered frames,
+/// malformed json, corrupt book checksums, rejected
+/// subscriptions and maintenance.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    accept: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    stop: AtomicBool,
+    heartbeat: Mutex<Option<Duration>>,
+    // Frames sent after subscribing, by channel name and pair.
+    scripts: Mutex<HashMap<(String, String), Vec<String>>>,
+    requests: Mutex<Vec<Value>>,
+    conns: Mutex<Vec<Arc<MockConn>>>,
+    next_connection: AtomicU64,
+    next_id: AtomicU32,
+    faults: Arc<Mutex<Faults>>,
+}
+
+// Faults applied to every connection's frames.
+#[derive(Default)]
+struct Faults {
+    stalled: bool,
+    delay: Option<Duration>,
+    reorder: bool,
+    corrupt_checksums: bool,
+    // Error messages by channel name.
+    rejections: HashMap<String, String>,
+    status: Option<String>,
+}
+
+struct MockConn {
+    writer: Mutex<Writer<TcpStream>>,
+    stream: TcpStream,
+    subscriptions: Mutex<Vec<Subscribed>>,
+    closed: AtomicBool,
+    faults: Arc<Mutex<Faults>>,
+    // A frame held back to be sent after the next one.
+    held: Mutex<Option<String>>,
+}
+
+#[derive(Clone)]
+struct Subscribed {
+    channel_name: String,
+    pair: Option<String>,
+}
+
+impl MockServer {
+    /// Listen on a free local port, heartbeats are sent every
+    /// second as Kraken does.
+    pub fn start() -> Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(State::default());
+        *state.heartbeat.lock().unwrap() = Some(Duration::from_secs(1));
+        let accepting = state.clone();
+        let accept = thread::spawn(move || accept(listener, accepting));
+        Ok(MockServer {
+            addr,
+            state,
+            accept: Some(accept),
+        })
+    }
+
+    /// Address to pass to [`crate::Kraken::connect`].
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Change how often heartbeats are sent, or stop them.
+    pub fn heartbeat(&self, interval: Option<Duration>) {
+        *self.state.heartbeat.lock().unwrap() = interval;
+    }
+
+    /// Frames to send to any connection subscribing to the
+    /// channel, e.g. "ohlc-5", for the pair. Private channels
+    /// are scripted with an empty pair.
+    pub fn script(&self, channel_name: &str, pair: &str, frames: Vec<String>) {
+        self.state
+            .scripts
+            .lock()
+            .unwrap()
+            .insert((channel_name.to_owned(), pair.to_owned()), frames);
+    }
+
+    /// Send a frame to every open connection now.
+    pub fn send(&self, frame: &str) -> Result<()> {
+        for conn in self.state.conns.lock().unwrap().iter() {
+            conn.send(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Send a ping frame with the given payload to every open
+    /// connection now.
+    pub fn ping(&self, data: &[u8]) -> Result<()> {
+        for conn in self.state.conns.lock().unwrap().iter() {
+            conn.send_message(&Message::ping(data.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    /// Drop every open connection without a close frame, as
+    /// when the network fails.
+    pub fn disconnect(&self) {
+        for conn in self.state.conns.lock().unwrap().iter() {
+            conn.close();
+        }
+    }
+
+    /// Stop sending anything, heartbeats included, while
+    /// keeping connections open and reading requests, like a
+    /// half-open socket. Frames are discarded until unstalled.
+    pub fn stall(&self, stalled: bool) {
+        self.state.faults.lock().unwrap().stalled = stalled;
+    }
+
+    /// Hold back every frame sent for a while first.
+    pub fn delay(&self, delay: Option<Duration>) {
+        self.state.faults.lock().unwrap().delay = delay;
+    }
+
+    /// Swap frames in pairs so each is sent after the one
+    /// following it. A frame is held until the next is sent,
+    /// which may be a heartbeat.
+    pub fn reorder(&self, reorder: bool) {
+        self.state.faults.lock().unwrap().reorder = reorder;
+    }
+
+    /// Send a frame which is not valid json to every open
+    /// connection.
+    pub fn send_malformed(&self) -> Result<()> {
+        self.send(r#"[42,{"a":["5525.40000",1,"1.000"],"#)
+    }
+
+    /// Change the checksum of every book update sent so that
+    /// it no longer matches the book.
+    pub fn corrupt_checksums(&self, corrupt: bool) {
+        self.state.faults.lock().unwrap().corrupt_checksums = corrupt;
+    }
+
+    /// Reject subscriptions to the channel, e.g. "book-10",
+    /// with the error message.
+    pub fn reject(&self, channel_name: &str, message: &str) {
+        self.state
+            .faults
+            .lock()
+            .unwrap()
+            .rejections
+            .insert(channel_name.to_owned(), message.to_owned());
+    }
+
+    /// Announce a system status such as "maintenance" to open
+    /// connections and to those opened later.
+    pub fn system_status(&self, status: &str) -> Result<()> {
+        self.state.faults.lock().unwrap().status = Some(status.to_owned());
+        for conn in self.state.conns.lock().unwrap().iter() {
+            conn.send_json(system_status(0, status))?;
+        }
+        Ok(())
+    }
+
+    /// Number of currently open connections.
+    pub fn connections(&self) -> usize {
+        self.state.conns.lock().unwrap().len()
+    }
+
+    /// Every request received so far, on any connection.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Wait for a request with the given event to be received,
+    /// returning the first one found.
+    pub fn wait_for(&self, event: &str, timeout: Duration) -> Option<Value> {
+        let started = Instant::now();
+        loop {
+            let found = self.requests().into_iter().find(|r| r["event"] == event);
+            if found.is_some() || started.elapsed() > timeout {
+                return found;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::SeqCst);
+        for conn in self.state.conns.lock().unwrap().iter() {
+            conn.close();
+        }
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+    }
+}
+
+fn accept(listener: TcpListener, state: Arc<State>) {
+    while !state.stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                thread::spawn(move || serve(state, stream));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+// Handle one connection until it is closed by either side.
+fn serve(state: Arc<State>, stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    let client = stream
+        .into_ws()
+        .map_err(|(_, _, _, e)| anyhow!("Invalid upgrade: {:?}", e))?
+        .accept()
+        .map_err(|(_, e)| e)?;
+    let stream = client.stream_ref().try_clone()?;
+    let (mut reader, writer) = client.split()?;
+    let conn = Arc::new(MockConn {
+        writer: Mutex::new(writer),
+        stream,
+        subscriptions: Mutex::new(vec![]),
+        closed: AtomicBool::new(false),
+        faults: state.faults.clone(),
+        held: Mutex::new(None),
+    });
+    state.conns.lock().unwrap().push(conn.clone());
+    let connection_id = state.next_connection.fetch_add(1, Ordering::SeqCst);
+    let status = state.faults.lock().unwrap().status.clone();
+    conn.send_json(system_status(
+        connection_id,
+        status.as_deref().unwrap_or("online"),
+    ))?;
+    let beating = (state.clone(), conn.clone());
+    thread::spawn(move || heartbeat(beating.0, beating.1));
+
+    let served = (|| {
+        for message in reader.incoming_messages() {
+            match message? {
+                OwnedMessage::Text(text) => handle(&state, &conn, &text)?,
+                OwnedMessage::Ping(data) => conn.send_message(&Message::pong(data))?,
+                OwnedMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    })();
+    conn.close();
+    state
+        .conns
+        .lock()
+        .unwrap()
+        .retain(|other| !Arc::ptr_eq(other, &conn));
+    served
+}
+
+fn heartbeat(state: Arc<State>, conn: Arc<MockConn>) {
+    loop {
+        let interval = *state.heartbeat.lock().unwrap();
+        thread::sleep(interval.unwrap_or(Duration::from_millis(10)));
+        if conn.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        let subscribed = !conn.subscriptions.lock().unwrap().is_empty();
+        if interval.is_some() && subscribed && conn.send(r#"{"event":"heartbeat"}"#).is_err() {
+            return;
+        }
+    }
+}
+
+// Reply to a request as Kraken would.
+fn handle(state: &State, conn: &MockConn, text: &str) -> Result<()> {
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(_) => return conn.send_json(error("Malformed request", &Value::Null)),
+    };
+    state.requests.lock().unwrap().push(request.clone());
+    let reqid = &request["reqid"];
+    let parsed = match WsReq::deserialize(&request) {
+        Ok(parsed) => parsed,
+        Err(_) => return conn.send_json(invalid(&request)),
+    };
+    match parsed {
+        WsReq::Ping { .. } => conn.send_json(with_reqid(json!({"event": "pong"}), reqid)),
+        WsReq::Subscribe {
+            pair, subscription, ..
+        } => subscribe(state, conn, &request, pairs(pair), &subscription),
+        WsReq::Unsubscribe {
+            pair, subscription, ..
+        } => unsubscribe(conn, &request, pairs(pair), &subscription),
+        WsReq::AddOrder {
+            pair,
+            side,
+            order_type,
+            price,
+            volume,
+            ..
+        } => {
+            let txid = format!("OMOCK{}", state.next_id.fetch_add(1, Ordering::SeqCst));
+            let side = match side {
+                OrderSide::Buy => "buy",
+                OrderSide::Sell => "sell",
+            };
+            let description = format!(
+                "{} {} {} @ {} {}",
+                side,
+                volume,
+                pair,
+                order_type,
+                price.unwrap_or_default(),
+            );
+            conn.send_json(with_reqid(
+                json!({
+                    "event": "addOrderStatus",
+                    "status": "ok",
+                    "txid": txid,
+                    "descr": description,
+                }),
+                reqid,
+            ))
+        }
+        WsReq::CancelOrder { .. } => conn.send_json(with_reqid(
+            json!({"event": "cancelOrderStatus", "status": "ok"}),
+            reqid,
+        )),
+    }
+}
+
+/// The reply to a request which isn't a valid [`WsReq`].
+fn invalid(request: &Value) -> Value {
+    let message = match (
+        request["event"].as_str(),
+        request["subscription"]["name"].as_str(),
+    ) {
+        (Some("subscribe" | "unsubscribe"), Some("book")) => "Subscription depth not supported",
+        (Some("subscribe" | "unsubscribe"), Some("ohlc")) => {
+            "Subscription ohlc interval not supported"
+        }
+        (Some("subscribe" | "unsubscribe"), _) => "Subscription name invalid",
+        _ => return error("Unsupported event", &request["reqid"]),
+    };
+    subscription_status(
+        request,
+        None,
+        json!({"status": "error", "errorMessage": message}),
+    )
+}
+
+/// The pairs of a subscription, private channels are not per
+/// pair so have none.
+fn pairs(pairs: Vec<String>) -> Vec<Option<String>> {
+    match pairs.is_empty() {
+        true => vec![None],
+        false => pairs.into_iter().map(Some).collect(),
+    }
+}
+
+fn subscribe(
+    state: &State,
+    conn: &MockConn,
+    request: &Value,
+    pairs: Vec<Option<String>>,
+    subscription: &Subscription,
+) -> Result<()> {
+    let channel_name = channel_name(subscription);
+    let rejection = state
+        .faults
+        .lock()
+        .unwrap()
+        .rejections
+        .get(&channel_name)
+        .cloned();
+    for pair in pairs {
+        if let Some(message) = &rejection {
+            conn.send_json(subscription_status(
+                request,
+                pair.as_deref(),
+                json!({"status": "error", "errorMessage": message}),
+            ))?;
+            continue;
+        }
+        let already = conn
+            .subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| s.channel_name == channel_name && s.pair == pair);
+        if already {
+            conn.send_json(subscription_status(
+                request,
+                pair.as_deref(),
+                json!({"status": "error", "errorMessage": "Already subscribed"}),
+            ))?;
+            continue;
+        }
+        conn.subscriptions.lock().unwrap().push(Subscribed {
+            channel_name: channel_name.clone(),
+            pair: pair.clone(),
+        });
+        conn.send_json(subscription_status(
+            request,
+            pair.as_deref(),
+            json!({
+                "status": "subscribed",
+                "channelID": state.next_id.fetch_add(1, Ordering::SeqCst),
+                "channelName": channel_name,
+            }),
+        ))?;
+        let key = (channel_name.clone(), pair.unwrap_or_default());
+        let frames = state.scripts.lock().unwrap().get(&key).cloned();
+        for frame in frames.into_iter().flatten() {
+            conn.send(&frame)?;
+        }
+    }
+    Ok(())
+}
+
+fn unsubscribe(
+    conn: &MockConn,
+    request: &Value,
+    pairs: Vec<Option<String>>,
+    subscription: &Subscription,
+) -> Result<()> {
+    let channel_name = channel_name(subscription);
+    for pair in pairs {
+        let removed = {
+            let mut subscriptions = conn.subscriptions.lock().unwrap();
+            let before = subscriptions.len();
+            subscriptions.retain(|s| !(s.channel_name == channel_name && s.pair == pair));
+            subscriptions.len() < before
+        };
+        let status = match removed {
+            true => json!({"status": "unsubscribed", "channelName": channel_name}),
+            false => json!({"status": "error", "errorMessage": "Subscription Not Found"}),
+        };
+        conn.send_json(subscription_status(request, pair.as_deref(), status))?;
+    }
+    Ok(())
+}
+
+/// The name of the channel a subscription is sent on.
+fn channel_name(subscription: &Subscription) -> String {
+    match subscription {
+        Subscription::Ticker => "ticker".to_owned(),
+        Subscription::Trade => "trade".to_owned(),
+        Subscription::Spread => "spread".to_owned(),
+        Subscription::Book { depth } => format!("book-{}", depth.levels()),
+        Subscription::Ohlc { interval } => format!("ohlc-{}", interval.minutes()),
+        Subscription::OpenOrders { .. } => "openOrders".to_owned(),
+        Subscription::OwnTrades { .. } => "ownTrades".to_owned(),
+    }
+}
+
+fn system_status(connection_id: u64, status: &str) -> Value {
+    json!({
+        "connectionID": connection_id,
+        "event": "systemStatus",
+        "status": status,
+        "version": VERSION,
+    })
+}
+
+// Change the checksum of a book update, if it is one.
+fn corrupt_checksum(text: &str) -> Option<String> {
+    let mut frame: Value = serde_json::from_str(text).ok()?;
+    let mut corrupted = false;
+    for part in frame.as_array_mut()? {
+        if let Some(checksum) = part.get_mut("c") {
+            let value: u32 = checksum.as_str()?.parse().ok()?;
+            *checksum = json!(value.wrapping_add(1).to_string());
+            corrupted = true;
+        }
+    }
+    match corrupted {
+        true => Some(frame.to_string()),
+        false => None,
+    }
+}
+
+fn subscription_status(request: &Value, pair: Option<&str>, fields: Value) -> Value {
+    let mut status = json!({
+        "event": "subscriptionStatus",
+        "subscription": request["subscription"],
+    });
+    if let Some(pair) = pair {
+        status["pair"] = json!(pair);
+    }
+    for (key, value) in fields.as_object().into_iter().flatten() {
+        status[key] = value.clone();
+    }
+    with_reqid(status, &request["reqid"])
+}
+
+fn error(message: &str, reqid: &Value) -> Value {
+    with_reqid(json!({"event": "error", "errorMessage": message}), reqid)
+}
+
+fn with_reqid(mut reply: Value, reqid: &Value) -> Value {
+    if !reqid.is_null() {
+        reply["reqid"] = reqid.clone();
+    }
+    reply
+}
+
+impl MockConn {
+    fn send(&self, text: &str) -> Result<()> {
+        let (delay, reorder, corrupt) = {
+            let faults = self.faults.lock().unwrap();
+            (faults.delay, faults.reorder, faults.corrupt_checksums)
+        };
+        let text = match corrupt {
+            true => corrupt_checksum(text).unwrap_or_else(|| text.to_owned()),
+            false => text.to_owned(),
+        };
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+        let held = self.held.lock().unwrap().take();
+        match held {
+            Some(held) => {
+                self.send_message(&Message::text(text))?;
+                self.send_message(&Message::text(held))
+            }
+            None if reorder => {
+                *self.held.lock().unwrap() = Some(text);
+                Ok(())
+            }
+            None => self.send_message(&Message::text(text)),
+        }
+    }
+
+    fn send_json(&self, value: Value) -> Result<()> {
+        self.send(&value.to_string())
+    }
+
+    fn send_message(&self, message: &Message) -> Result<()> {
+        if self.faults.lock().unwrap().stalled {
+            return Ok(());
+        }
+        self.writer
+            .lock()
+            .unwrap()
+            .send_message(message)
+            .map_err(anyhow::Error::from)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::{FrameKind, FrameReader, Recorder};
+    use crate::req::{OhlcInterval, Subscription, WsReq};
+    use crate::resp::event::{Pong, RequestError, RequestState, SubscriptionState, SystemState};
+    use crate::resp::private::OrderSide;
+    use crate::resp::Resp;
+    use crate::Kraken;
+
+    const BOOK: &str = r#"[1234,{"b":[["5540.10000","1.00000000","1534614335.345903"]],"c":"974942666"},"book-10","XBT/USD"]"#;
+    const OHLC: &str = r#"[42,["1542057314.748456","1542057360.435743","3586.70000","3586.70000","3586.60000","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+
+    fn connect(server: &MockServer) -> Result<Kraken> {
+        let mut client = Kraken::connect(&server.url())?;
+        assert!(matches!(client.recv()?, Resp::SystemStatus(_)));
+        Ok(client)
+    }
+
+    fn subscribe_ohlc(pair: &str) -> WsReq {
+        WsReq::Subscribe {
+            request_id: Some(3),
+            pair: vec![pair.to_string()],
+            subscription: Subscription::Ohlc {
+                interval: OhlcInterval::Mins5,
+            },
+        }
+    }
+
+    #[test]
+    fn ping() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        client.send_req(WsReq::Ping {
+            request_id: Some(10),
+        })?;
+        assert_eq!(
+            Resp::Pong(Pong {
+                request_id: Some(10)
+            }),
+            client.recv()?
+        );
+        assert_eq!(1, server.connections());
+        Ok(())
+    }
+
+    #[test]
+    fn records_every_frame() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?;
+        client.record(recorder.clone());
+        server.ping(b"hi")?;
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+        recorder.flush()?;
+        let frames: Vec<_> = FrameReader::open(dir.path())?
+            .map(|frame| frame.map(|frame| (frame.kind, frame.text)))
+            .collect::<Result<_>>()?;
+        assert_eq!(
+            vec![
+                (FrameKind::Ping, "6869".to_string()),
+                (FrameKind::Text, r#"{"event":"heartbeat"}"#.to_string()),
+            ],
+            frames
+        );
+
+        // A recorder which fails is dropped, the client carries on.
+        let gone = tempfile::tempdir()?;
+        client.record(Recorder::new(gone.path())?);
+        gone.close()?;
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+        client.send_req(WsReq::Ping { request_id: None })?;
+        assert!(matches!(client.recv()?, Resp::Pong(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe_sends_scripted_frames() -> Result<()> {
+        let server = MockServer::start()?;
+        server.heartbeat(Some(Duration::from_millis(20)));
+        server.script("ohlc-5", "XBT/USD", vec![OHLC.to_string()]);
+        let mut client = connect(&server)?;
+        client.send_req(subscribe_ohlc("XBT/USD"))?;
+        match client.recv()? {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Subscribed, status.status);
+                assert_eq!(Some(3), status.request_id);
+                assert_eq!(Some("ohlc-5".to_string()), status.channel_name);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(client.recv()?, Resp::Ohlc(_)));
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+
+        client.send_req(subscribe_ohlc("XBT/USD"))?;
+        let reply = client
+            .incoming()
+            .find(|resp| *resp != Resp::Heartbeat)
+            .unwrap();
+        match reply {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Error, status.status)
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!("subscribe", server.requests()[0]["event"]);
+        Ok(())
+    }
+
+    #[test]
+    fn unsubscribe() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        let unsubscribe = WsReq::Unsubscribe {
+            request_id: None,
+            pair: vec!["XBT/USD".to_string()],
+            subscription: Subscription::Ticker,
+        };
+        client.send_req(unsubscribe.clone())?;
+        match client.recv()? {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Error, status.status)
+            }
+            other => panic!("{:?}", other),
+        }
+        client.send_req(WsReq::Subscribe {
+            request_id: None,
+            pair: vec!["XBT/USD".to_string()],
+            subscription: Subscription::Ticker,
+        })?;
+        client.recv()?;
+        client.send_req(unsubscribe)?;
+        match client.recv()? {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Unsubscribed, status.status)
+            }
+            other => panic!("{:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn private_subscription_has_no_pair() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        client.send_req(WsReq::Subscribe {
+            request_id: None,
+            pair: vec![],
+            subscription: Subscription::OwnTrades {
+                snapshot: None,
+                token: "abc".to_string(),
+            },
+        })?;
+        match client.recv()? {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Subscribed, status.status);
+                assert_eq!(Some("ownTrades".to_string()), status.channel_name);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(None, server.requests()[0].get("pair"));
+        Ok(())
+    }
+
+    #[test]
+    fn orders() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        client.send_req(WsReq::AddOrder {
+            request_id: Some(1),
+            token: "abc".to_string(),
+            pair: "XBT/USD".to_string(),
+            side: OrderSide::Buy,
+            order_type: "limit".to_string(),
+            price: Some("9000.0".to_string()),
+            volume: "0.01".to_string(),
+            validate: None,
+        })?;
+        let txid = match client.recv()? {
+            Resp::AddOrderStatus(status) => {
+                assert_eq!(RequestState::Ok, status.status);
+                assert_eq!(
+                    Some("buy 0.01 XBT/USD @ limit 9000.0".to_string()),
+                    status.description
+                );
+                status.txid.unwrap()
+            }
+            other => panic!("{:?}", other),
+        };
+        client.send_req(WsReq::CancelOrder {
+            request_id: Some(2),
+            token: "abc".to_string(),
+            txid: vec![txid],
+        })?;
+        assert!(matches!(client.recv()?, Resp::CancelOrderStatus(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn dropping_server_disconnects() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+        drop(server);
+        assert!(client.recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn disconnect() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.disconnect();
+        assert!(client.recv().is_err());
+        // The server carries on accepting connections.
+        connect(&server)?;
+        Ok(())
+    }
+
+    #[test]
+    fn stall() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.stall(true);
+        client.send_req(WsReq::Ping { request_id: None })?;
+        assert!(server.wait_for("ping", Duration::from_secs(1)).is_some());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || sender.send(client.recv().is_ok()));
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        server.stall(false);
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        assert!(receiver.recv_timeout(Duration::from_secs(1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn delay_and_reorder() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.delay(Some(Duration::from_millis(50)));
+        let started = Instant::now();
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        server.delay(None);
+        server.reorder(true);
+        for request_id in 1..=2 {
+            client.send_req(WsReq::Ping {
+                request_id: Some(request_id),
+            })?;
+        }
+        let pong = |request_id| Resp::Pong(Pong { request_id });
+        assert_eq!(pong(Some(2)), client.recv()?);
+        assert_eq!(pong(Some(1)), client.recv()?);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_for_malformed_requests() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = websocket::ClientBuilder::new(&server.url())?.connect_insecure()?;
+        client.recv_message()?;
+        client.send_message(&Message::text("not json"))?;
+        let reply = match client.recv_message()? {
+            OwnedMessage::Text(text) => serde_json::from_str::<Resp>(&text)?,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            Resp::RequestError(RequestError {
+                request_id: None,
+                error_message: "Malformed request".to_string(),
+            }),
+            reply
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_and_corrupt_frames() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.send_malformed()?;
+        assert!(client.recv().is_err());
+
+        server.corrupt_checksums(true);
+        server.send(BOOK)?;
+        server.send(r#"{"event":"heartbeat"}"#)?;
+        match client.recv()? {
+            Resp::Book(book) => assert_eq!(Some(974942667), book.checksum),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(Resp::Heartbeat, client.recv()?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_subscription() -> Result<()> {
+        let server = MockServer::start()?;
+        server.reject("ohlc-5", "Currency pair not supported");
+        let mut client = connect(&server)?;
+        client.send_req(subscribe_ohlc("XBT/USD"))?;
+        match client.recv()? {
+            Resp::SubscriptionStatus(status) => {
+                assert_eq!(SubscriptionState::Error, status.status);
+                assert_eq!(
+                    Some("Currency pair not supported".to_string()),
+                    status.error_message
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn maintenance() -> Result<()> {
+        let server = MockServer::start()?;
+        let mut client = connect(&server)?;
+        server.system_status("maintenance")?;
+        let maintenance = |resp| match resp {
+            Resp::SystemStatus(status) => status.status == SystemState::Maintenance,
+            _ => false,
+        };
+        assert!(maintenance(client.recv()?));
+        let mut client = Kraken::connect(&server.url())?;
+        assert!(maintenance(client.recv()?));
+        Ok(())
+    }
+}
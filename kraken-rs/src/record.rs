@@ -0,0 +1,496 @@
+use crate::resp::Resp;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Error, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+const EXTENSION: &str = "jsonl";
+const ZSTD_EXTENSION: &str = "jsonl.zst";
+
+/// Whether a frame was received from or sent to Kraken.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "in")]
+    In,
+    #[serde(rename = "out")]
+    Out,
+}
+
+/// The type of a websocket frame. Only text frames hold
+/// messages, the rest show how the connection was kept alive
+/// and how it ended.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum FrameKind {
+    #[default]
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "binary")]
+    Binary,
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "pong")]
+    Pong,
+    #[serde(rename = "close")]
+    Close,
+}
+
+impl FrameKind {
+    fn is_text(&self) -> bool {
+        *self == FrameKind::Text
+    }
+}
+
+/// A frame exactly as it was sent or received, with the local
+/// time it was read or written.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Frame {
+    pub time: Timestamp,
+    /// Distinguishes the connections recorded into one directory.
+    pub connection: u64,
+    pub direction: Direction,
+    /// Left out for text frames, as in recordings from before
+    /// other frames were recorded.
+    #[serde(default, skip_serializing_if = "FrameKind::is_text")]
+    pub kind: FrameKind,
+    /// The message of a text frame, the payload of a binary,
+    /// ping or pong frame in hex, or the status code and reason
+    /// of a close frame.
+    pub text: String,
+}
+
+impl Frame {
+    /// Whether this is a message received from Kraken.
+    pub fn is_received(&self) -> bool {
+        self.direction == Direction::In && self.kind == FrameKind::Text
+    }
+
+    pub fn parse(&self) -> Result<Resp> {
+        serde_json::from_str(&self.text).map_err(Error::from)
+    }
+}
+
+/// Appends frames as json lines to files in a directory,
+/// starting a new file once the current one reaches the size
+/// limit. Files are named by the time they were started so
+/// that sorting them by name puts them in order. Clones write
+/// to the same file so one recorder can be shared between
+/// several connections.
+///
+/// Frames are written by a thread of the recorder's own so that
+/// the disk never holds up reading from a connection, unless
+/// it falls 4096 frames behind. An error writing stops the
+/// recorder and is returned by the next call to it.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    writer: Arc<Writer>,
+}
+
+/// Frames waiting to be written before recording blocks.
+const QUEUE: usize = 4096;
+
+#[derive(Debug)]
+enum Command {
+    Line(Vec<u8>),
+    MaxBytes(u64),
+    #[cfg(feature = "zstd")]
+    Compress(bool),
+    Flush(SyncSender<io::Result<()>>),
+}
+
+/// The sending half of the writer thread, which is joined once
+/// every clone of the recorder has been dropped.
+#[derive(Debug)]
+struct Writer {
+    sender: Option<SyncSender<Command>>,
+    thread: Option<JoinHandle<()>>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    max_bytes: u64,
+    compress: bool,
+    file: Option<Output>,
+    written: u64,
+    // The time the current file is named after, a new file
+    // is always named after a later one.
+    named: u64,
+}
+
+enum Output {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::AutoFinishEncoder<'static, BufWriter<File>>),
+}
+
+impl std::fmt::Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+impl Output {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(w) => w,
+            #[cfg(feature = "zstd")]
+            Output::Zstd(w) => w,
+        }
+    }
+}
+
+impl Recorder {
+    /// Record into the given directory, which is created if
+    /// needed. Files are rotated every 256MiB by default.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Recorder> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut inner = Inner {
+            dir,
+            max_bytes: 256 * 1024 * 1024,
+            compress: false,
+            file: None,
+            written: 0,
+            named: 0,
+        };
+        let (sender, receiver) = sync_channel(QUEUE);
+        let error = Arc::new(Mutex::new(None));
+        let failed = error.clone();
+        let thread = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || {
+                if let Err(e) = inner.run(receiver) {
+                    *failed.lock().unwrap() = Some(e.to_string());
+                }
+            })?;
+        Ok(Recorder {
+            writer: Arc::new(Writer {
+                sender: Some(sender),
+                thread: Some(thread),
+                error,
+            }),
+        })
+    }
+
+    /// Start a new file once this many bytes, before any
+    /// compression, have been written to the current one.
+    pub fn max_bytes(self, max_bytes: u64) -> Recorder {
+        // Any error is returned by the next record.
+        let _ = self.writer.send(Command::MaxBytes(max_bytes));
+        self
+    }
+
+    /// Compress new files with zstd.
+    #[cfg(feature = "zstd")]
+    pub fn compress(self, compress: bool) -> Recorder {
+        let _ = self.writer.send(Command::Compress(compress));
+        self
+    }
+
+    pub fn record(&self, frame: &Frame) -> Result<()> {
+        let mut line = serde_json::to_vec(frame)?;
+        line.push(b'\n');
+        self.writer.send(Command::Line(line))
+    }
+
+    /// Wait for every frame recorded so far to be written out,
+    /// compressed files are only complete once they have been
+    /// rotated or the recorder dropped.
+    pub fn flush(&self) -> Result<()> {
+        let (sender, receiver) = sync_channel(1);
+        self.writer.send(Command::Flush(sender))?;
+        match receiver.recv() {
+            Ok(flushed) => flushed.map_err(Error::from),
+            Err(_) => Err(self.writer.failed()),
+        }
+    }
+}
+
+impl Writer {
+    fn send(&self, command: Command) -> Result<()> {
+        let sender = self.sender.as_ref().unwrap();
+        sender.send(command).map_err(|_| self.failed())
+    }
+
+    /// The error which stopped the writer thread.
+    fn failed(&self) -> Error {
+        match self.error.lock().unwrap().as_ref() {
+            Some(e) => anyhow!("Recording failed: {}", e),
+            None => anyhow!("Recording failed"),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        // Closing the channel ends the thread, which finishes
+        // the current file.
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Inner {
+    fn run(&mut self, commands: Receiver<Command>) -> Result<()> {
+        for command in commands {
+            match command {
+                Command::Line(line) => self.write(&line)?,
+                Command::MaxBytes(max_bytes) => self.max_bytes = max_bytes,
+                #[cfg(feature = "zstd")]
+                Command::Compress(compress) => self.compress = compress,
+                Command::Flush(flushed) => {
+                    let result = match self.file.as_mut() {
+                        Some(file) => file.writer().flush(),
+                        None => Ok(()),
+                    };
+                    let failed = result.as_ref().err().map(|e| e.to_string());
+                    let _ = flushed.send(result);
+                    if let Some(e) = failed {
+                        return Err(anyhow!(e));
+                    }
+                }
+            }
+        }
+        match self.file.as_mut() {
+            Some(file) => file.writer().flush().map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        if self.file.is_none() || self.written >= self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.as_mut().unwrap().writer().write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if let Some(mut old) = self.file.take() {
+            old.writer().flush()?;
+        }
+        let extension = match self.compress {
+            true => ZSTD_EXTENSION,
+            false => EXTENSION,
+        };
+        // Never reuse a name, even if the clock goes back or
+        // another recorder is writing to the directory.
+        let mut named = Timestamp::now().as_micros().max(self.named + 1);
+        let file = loop {
+            let path = self
+                .dir
+                .join(format!("frames-{}", named))
+                .with_extension(extension);
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => named += 1,
+                file => break BufWriter::new(file?),
+            }
+        };
+        self.named = named;
+        self.file = Some(match self.compress {
+            #[cfg(feature = "zstd")]
+            true => Output::Zstd(zstd::Encoder::new(file, 0)?.auto_finish()),
+            _ => Output::Plain(file),
+        });
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Reads back recorded frames from a single file or, in
+/// order, from every file in a directory.
+pub struct FrameReader {
+    files: std::vec::IntoIter<PathBuf>,
+    lines: Option<Box<dyn BufRead + Send>>,
+    line: String,
+}
+
+impl FrameReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FrameReader> {
+        let path = path.as_ref();
+        let files = if path.is_dir() {
+            let mut files = vec![];
+            for entry in fs::read_dir(path)? {
+                let file = entry?.path();
+                if is_recording(&file) {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            files
+        } else {
+            vec![path.to_owned()]
+        };
+        Ok(FrameReader {
+            files: files.into_iter(),
+            lines: None,
+            line: String::new(),
+        })
+    }
+
+    /// The responses received, frames which fail to parse
+    /// are errors so the caller can decide whether to skip them.
+    pub fn responses(self) -> impl Iterator<Item = Result<Resp>> {
+        self.filter_map(|frame| match frame {
+            Ok(frame) if frame.is_received() => Some(frame.parse()),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            let lines = match self.lines.as_mut() {
+                Some(lines) => lines,
+                None => match self.files.next() {
+                    Some(path) => self.lines.insert(open_lines(&path)?),
+                    None => return Ok(None),
+                },
+            };
+            self.line.clear();
+            if lines.read_line(&mut self.line)? == 0 {
+                self.lines = None;
+            } else if !self.line.trim().is_empty() {
+                return serde_json::from_str(&self.line)
+                    .map(Some)
+                    .map_err(|e| anyhow!("Invalid frame {}: {}", self.line.trim(), e));
+            }
+        }
+    }
+}
+
+impl Iterator for FrameReader {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Result<Frame>> {
+        self.next_frame().transpose()
+    }
+}
+
+fn is_recording(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    name.ends_with(".jsonl") || name.ends_with(".jsonl.zst")
+}
+
+fn open_lines(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    if path.to_string_lossy().ends_with(".zst") {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(BufReader::new(zstd::Decoder::new(file)?)));
+        #[cfg(not(feature = "zstd"))]
+        return Err(anyhow!("{}: built without zstd", path.display()));
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HEARTBEAT: &str = r#"{"event":"heartbeat"}"#;
+    const PING: &str = r#"{"event":"ping","reqid":1}"#;
+
+    fn frame(direction: Direction, text: &str) -> Frame {
+        Frame {
+            time: Timestamp::from_micros(1542057314748456),
+            connection: 7,
+            direction,
+            kind: FrameKind::Text,
+            text: text.to_string(),
+        }
+    }
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?;
+        let pong = Frame {
+            kind: FrameKind::Pong,
+            ..frame(Direction::In, "6869")
+        };
+        let frames = vec![
+            frame(Direction::Out, PING),
+            frame(Direction::In, HEARTBEAT),
+            pong,
+            frame(Direction::In, "not json"),
+        ];
+        for frame in &frames {
+            recorder.record(frame)?;
+        }
+        recorder.flush()?;
+
+        let read: Vec<Frame> = FrameReader::open(dir.path())?.collect::<Result<_>>()?;
+        assert_eq!(frames, read);
+        let responses: Vec<_> = FrameReader::open(dir.path())?.responses().collect();
+        assert_eq!(2, responses.len());
+        assert_eq!(Resp::Heartbeat, *responses[0].as_ref().unwrap());
+        assert!(responses[1].is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_by_size() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?.max_bytes(1);
+        for i in 0..3 {
+            recorder.record(&frame(Direction::In, HEARTBEAT))?;
+            if i == 0 {
+                recorder.flush()?;
+                assert_eq!(1, files(dir.path()).len());
+            }
+        }
+        recorder.flush()?;
+        assert_eq!(3, files(dir.path()).len());
+        assert_eq!(3, FrameReader::open(dir.path())?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_within_a_microsecond() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?.max_bytes(1);
+        for _ in 0..100 {
+            recorder.record(&frame(Direction::In, HEARTBEAT))?;
+        }
+        recorder.flush()?;
+        assert_eq!(100, files(dir.path()).len());
+        assert_eq!(100, FrameReader::open(dir.path())?.count());
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?.compress(true);
+        recorder.record(&frame(Direction::In, HEARTBEAT))?;
+        recorder.record(&frame(Direction::Out, PING))?;
+        drop(recorder);
+
+        let files = files(dir.path());
+        assert!(files[0].to_string_lossy().ends_with(".jsonl.zst"));
+        let read: Vec<Frame> = FrameReader::open(&files[0])?.collect::<Result<_>>()?;
+        assert_eq!(
+            vec![frame(Direction::In, HEARTBEAT), frame(Direction::Out, PING)],
+            read
+        );
+        Ok(())
+    }
+}
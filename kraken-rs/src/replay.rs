@@ -0,0 +1,200 @@
+use crate::record::{Frame, FrameReader};
+use crate::resp::{Resp, RespRef};
+use crate::time::Timestamp;
+use crate::RespSource;
+use anyhow::{anyhow, bail, Error, Result};
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+/// How quickly recorded frames are replayed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+    /// Every frame straight away.
+    Max,
+    /// Keep the recorded gaps between frames, divided by the
+    /// factor so 10.0 replays ten times faster than recorded.
+    Factor(f64),
+}
+
+/// Plays back the frames received in a recording as if they
+/// were arriving on a live connection, so anything reading a
+/// [`RespSource`] can be rerun against a past session. Frames
+/// sent to Kraken are skipped.
+pub struct ReplaySource {
+    frames: FrameReader,
+    speed: Speed,
+    connection: Option<u64>,
+    // Wall clock and recorded time of the first frame.
+    start: Option<(Instant, Timestamp)>,
+}
+
+impl ReplaySource {
+    /// Replay a recorded file or directory as fast as possible.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ReplaySource> {
+        Ok(ReplaySource {
+            frames: FrameReader::open(path)?,
+            speed: Speed::Max,
+            connection: None,
+            start: None,
+        })
+    }
+
+    /// Errors unless a factor is finite and above zero.
+    pub fn speed(mut self, speed: Speed) -> Result<ReplaySource> {
+        if let Speed::Factor(factor) = speed {
+            if !factor.is_finite() || factor <= 0.0 {
+                bail!("Invalid replay speed {}", factor);
+            }
+        }
+        self.speed = speed;
+        Ok(self)
+    }
+
+    /// Only replay frames from one recorded connection.
+    pub fn connection(mut self, connection: u64) -> ReplaySource {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// The next frame received, once it is due, erroring at
+    /// the end of the recording.
+    fn next_text(&mut self) -> Result<String> {
+        loop {
+            let frame = self
+                .frames
+                .next()
+                .unwrap_or_else(|| Err(anyhow!("End of recording")))?;
+            if frame.is_received() && self.connection.is_none_or(|c| c == frame.connection) {
+                self.wait_for(&frame);
+                return Ok(frame.text);
+            }
+        }
+    }
+
+    fn wait_for(&mut self, frame: &Frame) {
+        let factor = match self.speed {
+            Speed::Max => return,
+            Speed::Factor(factor) => factor,
+        };
+        let (started, first) = *self.start.get_or_insert((Instant::now(), frame.time));
+        let due = frame.time.saturating_duration_since(first).div_f64(factor);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Wait for and parse the next frame, see [`crate::Kraken::recv`].
+    pub fn recv(&mut self) -> Result<Resp> {
+        let text = self.next_text()?;
+        serde_json::from_str(text.as_str()).map_err(Error::from)
+    }
+
+    /// See [`crate::Kraken::recv_with`].
+    pub fn recv_with<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(RespRef) -> T,
+    {
+        let text = self.next_text()?;
+        Ok(f(serde_json::from_str(text.as_str())?))
+    }
+
+    /// The successfully parsed messages, ending with the recording.
+    pub fn incoming(&mut self) -> impl Iterator<Item = Resp> + '_ {
+        std::iter::from_fn(move || loop {
+            match self.next_text() {
+                Ok(text) => {
+                    if let Ok(resp) = serde_json::from_str(text.as_str()) {
+                        return Some(resp);
+                    }
+                }
+                Err(_) => return None,
+            }
+        })
+    }
+}
+
+impl RespSource for ReplaySource {
+    fn recv(&mut self) -> Result<Resp> {
+        ReplaySource::recv(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::{Direction, FrameKind, Recorder};
+    use crate::resp::event::Pong;
+    use std::time::Duration;
+
+    const HEARTBEAT: &str = r#"{"event":"heartbeat"}"#;
+    const PONG: &str = r#"{"event":"pong","reqid":1}"#;
+    const PING: &str = r#"{"event":"ping","reqid":1}"#;
+
+    fn record(frames: &[(u64, u64, Direction, &str)]) -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir()?;
+        let recorder = Recorder::new(dir.path())?;
+        for &(millis, connection, direction, text) in frames {
+            recorder.record(&Frame {
+                time: Timestamp::from_micros(1542057314000000 + millis * 1000),
+                connection,
+                direction,
+                kind: FrameKind::Text,
+                text: text.to_string(),
+            })?;
+        }
+        recorder.flush()?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn replays_received_frames() -> Result<()> {
+        let dir = record(&[
+            (0, 1, Direction::Out, PING),
+            (10, 1, Direction::In, PONG),
+            (20, 2, Direction::In, "garbage"),
+            (30, 1, Direction::In, HEARTBEAT),
+        ])?;
+        let mut replay = ReplaySource::open(dir.path())?;
+        assert_eq!(
+            vec![
+                Resp::Pong(Pong {
+                    request_id: Some(1)
+                }),
+                Resp::Heartbeat
+            ],
+            replay.incoming().collect::<Vec<_>>()
+        );
+
+        let mut replay = ReplaySource::open(dir.path())?.connection(2);
+        assert!(replay.recv().is_err());
+        assert_eq!("End of recording", replay.recv().unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn paced_by_recorded_time() -> Result<()> {
+        let dir = record(&[
+            (0, 1, Direction::In, HEARTBEAT),
+            (500, 1, Direction::In, HEARTBEAT),
+            (1000, 1, Direction::In, HEARTBEAT),
+        ])?;
+        let started = Instant::now();
+        let mut replay = ReplaySource::open(dir.path())?.speed(Speed::Factor(20.0))?;
+        for _ in 0..3 {
+            assert_eq!(Resp::Heartbeat, replay.recv()?);
+        }
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let started = Instant::now();
+        let mut replay = ReplaySource::open(dir.path())?;
+        assert_eq!(3, replay.incoming().count());
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        for factor in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let replay = ReplaySource::open(dir.path())?;
+            assert!(replay.speed(Speed::Factor(factor)).is_err(), "{}", factor);
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,15 @@
-use serde::{Serialize, Serializer};
-use serde_derive::Serialize;
+use crate::resp::private::OrderSide;
+use anyhow::{anyhow, Error, Result};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
 
-/// Kraken Websocket request
-#[derive(Clone, Eq, PartialEq, Serialize)]
+/// Kraken Websocket request, these can also be deserialized
+/// to handle requests server side.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "event")]
 pub enum WsReq {
     #[serde(rename = "ping")]
@@ -16,6 +23,8 @@ pub enum WsReq {
         #[serde(rename = "reqid")]
         #[serde(skip_serializing_if = "Option::is_none")]
         request_id: Option<u32>,
+        /// Empty for private channels, and then left out.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pair: Vec<String>,
         subscription: Subscription,
     },
@@ -24,12 +33,43 @@ pub enum WsReq {
         #[serde(rename = "reqid")]
         #[serde(skip_serializing_if = "Option::is_none")]
         request_id: Option<u32>,
+        /// Empty for private channels, and then left out.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pair: Vec<String>,
         subscription: Subscription,
     },
+    /// Place an order, only accepted on the authenticated endpoint.
+    #[serde(rename = "addOrder")]
+    AddOrder {
+        #[serde(rename = "reqid")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<u32>,
+        token: String,
+        pair: String,
+        #[serde(rename = "type")]
+        side: OrderSide,
+        #[serde(rename = "ordertype")]
+        order_type: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        price: Option<String>,
+        volume: String,
+        /// Only check the order, without placing it.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        validate: Option<bool>,
+    },
+    /// Cancel orders by their ids, only accepted on the
+    /// authenticated endpoint.
+    #[serde(rename = "cancelOrder")]
+    CancelOrder {
+        #[serde(rename = "reqid")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<u32>,
+        token: String,
+        txid: Vec<String>,
+    },
 }
 
-#[derive(Clone, Eq, PartialEq, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "name")]
 pub enum Subscription {
     #[serde(rename = "ticker")]
@@ -57,7 +97,7 @@ pub enum Subscription {
     },
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub enum BookDepth {
     N10,
     N25,
@@ -66,7 +106,7 @@ pub enum BookDepth {
     N1000,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub enum OhlcInterval {
     Mins1,
     Mins5,
@@ -79,27 +119,84 @@ pub enum OhlcInterval {
     Days15,
 }
 
-impl Serialize for BookDepth {
-    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_i32(match self {
+impl BookDepth {
+    pub const ALL: [BookDepth; 5] = [
+        BookDepth::N10,
+        BookDepth::N25,
+        BookDepth::N100,
+        BookDepth::N500,
+        BookDepth::N1000,
+    ];
+
+    /// Number of price levels on each side of the book.
+    pub fn levels(&self) -> u32 {
+        match self {
             BookDepth::N10 => 10,
             BookDepth::N25 => 25,
             BookDepth::N100 => 100,
             BookDepth::N500 => 500,
             BookDepth::N1000 => 1000,
-        })
+        }
     }
 }
 
-impl Serialize for OhlcInterval {
-    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+impl TryFrom<u32> for BookDepth {
+    type Error = Error;
+
+    fn try_from(levels: u32) -> Result<Self> {
+        BookDepth::ALL
+            .iter()
+            .find(|d| d.levels() == levels)
+            .copied()
+            .ok_or_else(|| anyhow!("No book depth of {} levels", levels))
+    }
+}
+
+impl FromStr for BookDepth {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<u32>()
+            .map_err(|_| anyhow!("Invalid book depth {}", s))
+            .and_then(BookDepth::try_from)
+    }
+}
+
+impl Serialize for BookDepth {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_i32(match self {
+        serializer.serialize_i32(self.levels() as i32)
+    }
+}
+
+impl<'de> Deserialize<'de> for BookDepth {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        BookDepth::try_from(u32::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+impl OhlcInterval {
+    pub const ALL: [OhlcInterval; 9] = [
+        OhlcInterval::Mins1,
+        OhlcInterval::Mins5,
+        OhlcInterval::Mins15,
+        OhlcInterval::Mins30,
+        OhlcInterval::Hours1,
+        OhlcInterval::Hours4,
+        OhlcInterval::Days1,
+        OhlcInterval::Days7,
+        OhlcInterval::Days15,
+    ];
+
+    /// Length of the interval in minutes, this is how
+    /// Kraken identifies intervals on the wire.
+    pub fn minutes(&self) -> u32 {
+        match self {
             OhlcInterval::Mins1 => 1,
             OhlcInterval::Mins5 => 5,
             OhlcInterval::Mins15 => 15,
@@ -109,14 +206,98 @@ impl Serialize for OhlcInterval {
             OhlcInterval::Days1 => 1440,
             OhlcInterval::Days7 => 10080,
             OhlcInterval::Days15 => 21600,
-        })
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(60 * self.minutes() as u64)
+    }
+}
+
+impl TryFrom<u32> for OhlcInterval {
+    type Error = Error;
+
+    fn try_from(minutes: u32) -> Result<Self> {
+        OhlcInterval::ALL
+            .iter()
+            .find(|i| i.minutes() == minutes)
+            .copied()
+            .ok_or_else(|| anyhow!("No ohlc interval of {} minutes", minutes))
+    }
+}
+
+impl FromStr for OhlcInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<u32>()
+            .map_err(|_| anyhow!("Invalid ohlc interval {}", s))
+            .and_then(OhlcInterval::try_from)
+    }
+}
+
+impl Serialize for OhlcInterval {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i32(self.minutes() as i32)
+    }
+}
+
+impl<'de> Deserialize<'de> for OhlcInterval {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        OhlcInterval::try_from(u32::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+// Both are sent as the integers listed in `ALL`.
+#[cfg(feature = "schema")]
+mod schema {
+    use super::{BookDepth, OhlcInterval};
+    use schemars::gen::SchemaGenerator;
+    use schemars::schema::{InstanceType, Schema, SchemaObject};
+    use schemars::JsonSchema;
+
+    fn integers<I: Iterator<Item = u32>>(values: I) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            enum_values: Some(values.map(Into::into).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    impl JsonSchema for BookDepth {
+        fn schema_name() -> String {
+            "BookDepth".to_owned()
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            integers(BookDepth::ALL.iter().map(BookDepth::levels))
+        }
+    }
+
+    impl JsonSchema for OhlcInterval {
+        fn schema_name() -> String {
+            "OhlcInterval".to_owned()
+        }
+
+        fn json_schema(_: &mut SchemaGenerator) -> Schema {
+            integers(OhlcInterval::ALL.iter().map(OhlcInterval::minutes))
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::req::{BookDepth, OhlcInterval, Subscription, WsReq};
+    use crate::resp::private::OrderSide;
     use anyhow::Result;
+    use std::convert::TryFrom;
 
     #[test]
     fn serialize_owntrades_subscription() -> Result<()> {
@@ -150,6 +331,24 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn serialize_add_order() -> Result<()> {
+        assert_eq!(
+            r#"{"event":"addOrder","reqid":5,"token":"abc","pair":"XBT/USD","type":"buy","ordertype":"limit","price":"9000.0","volume":"0.01"}"#,
+            serde_json::to_string(&WsReq::AddOrder {
+                request_id: Some(5),
+                token: "abc".to_string(),
+                pair: "XBT/USD".to_string(),
+                side: OrderSide::Buy,
+                order_type: "limit".to_string(),
+                price: Some("9000.0".to_string()),
+                volume: "0.01".to_string(),
+                validate: None
+            })?
+        );
+        Ok(())
+    }
+
     #[test]
     fn serialize_ohlc_subscription() -> Result<()> {
         assert_eq!(
@@ -283,4 +482,60 @@ mod test {
         assert_eq!("21600", serde_json::to_string(&OhlcInterval::Days15)?);
         Ok(())
     }
+
+    #[test]
+    fn parse_interval() -> Result<()> {
+        for interval in OhlcInterval::ALL.iter() {
+            assert_eq!(*interval, interval.minutes().to_string().parse()?);
+            assert_eq!(*interval, OhlcInterval::try_from(interval.minutes())?);
+        }
+        assert!("7".parse::<OhlcInterval>().is_err());
+        assert!("5m".parse::<OhlcInterval>().is_err());
+        assert!(OhlcInterval::try_from(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_depth() -> Result<()> {
+        for depth in BookDepth::ALL.iter() {
+            assert_eq!(*depth, depth.levels().to_string().parse()?);
+        }
+        assert!("50".parse::<BookDepth>().is_err());
+        assert!("ten".parse::<BookDepth>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deserialize_requests() -> Result<()> {
+        let requests = [
+            r#"{"event":"ping","reqid":1}"#,
+            r#"{"event":"subscribe","pair":["XBT/USD","XBT/EUR"],"subscription":{"name":"book","depth":25}}"#,
+            r#"{"event":"unsubscribe","reqid":2,"pair":["XBT/USD"],"subscription":{"name":"ohlc","interval":60}}"#,
+            r#"{"event":"addOrder","token":"abc","pair":"XBT/USD","type":"sell","ordertype":"market","volume":"1.0"}"#,
+            r#"{"event":"cancelOrder","reqid":3,"token":"abc","txid":["O1","O2"]}"#,
+            r#"{"event":"subscribe","subscription":{"name":"ownTrades","token":"abc"}}"#,
+        ];
+        for request in requests.iter() {
+            let parsed: WsReq = serde_json::from_str(request)?;
+            assert_eq!(*request, serde_json::to_string(&parsed)?);
+        }
+        assert_eq!(
+            WsReq::Subscribe {
+                request_id: None,
+                pair: vec![],
+                subscription: Subscription::OwnTrades {
+                    snapshot: None,
+                    token: "abc".to_string()
+                }
+            },
+            serde_json::from_str(
+                r#"{"event":"subscribe","subscription":{"name":"ownTrades","token":"abc"}}"#
+            )?
+        );
+        assert!(serde_json::from_str::<WsReq>(
+            r#"{"event":"subscribe","pair":["XBT/USD"],"subscription":{"name":"book","depth":7}}"#
+        )
+        .is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,417 @@
+use crate::req::BookDepth;
+use crate::resp::{channel_name, parse_part, Parts, Wire};
+use crate::time::Timestamp;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, SerializeTuple, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A snapshot of, or an update to, the order book for a pair.
+/// The first message after subscribing is a snapshot of the
+/// requested depth, after that only changed levels are sent.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Book"))]
+pub struct Book<S = String> {
+    #[serde(rename = "channelId")]
+    pub channel_id: u32,
+    pub pair: S,
+    pub depth: BookDepth,
+    pub snapshot: bool,
+    pub asks: Vec<PriceLevel<S>>,
+    pub bids: Vec<PriceLevel<S>>,
+    /// CRC32 of the top ten levels of the book once this
+    /// update is applied, only sent with updates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+}
+
+/// A price level in the book, a volume of zero in an
+/// update means the level should be removed.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(
+    feature = "schema",
+    derive(JsonSchema),
+    schemars(rename = "PriceLevel")
+)]
+pub struct PriceLevel<S = String> {
+    pub price: S,
+    pub volume: S,
+    pub time: Timestamp,
+    /// Set when Kraken resends a level to restore the depth
+    /// of the book after another level was removed.
+    pub republish: bool,
+}
+
+impl PriceLevel<&str> {
+    pub fn into_owned(self) -> PriceLevel {
+        PriceLevel {
+            price: self.price.to_owned(),
+            volume: self.volume.to_owned(),
+            time: self.time,
+            republish: self.republish,
+        }
+    }
+}
+
+impl Book<&str> {
+    /// Copy the borrowed fields into an owned book message.
+    pub fn into_owned(self) -> Book {
+        Book {
+            channel_id: self.channel_id,
+            pair: self.pair.to_owned(),
+            depth: self.depth,
+            snapshot: self.snapshot,
+            asks: self.asks.into_iter().map(PriceLevel::into_owned).collect(),
+            bids: self.bids.into_iter().map(PriceLevel::into_owned).collect(),
+            checksum: self.checksum,
+        }
+    }
+}
+
+impl<S> Book<S> {
+    /// Build from the raw elements of a book channel message,
+    /// updates to both sides arrive as two data elements.
+    pub(crate) fn from_parts<'de, E>(parts: &[&'de RawValue]) -> Result<Book<S>, E>
+    where
+        S: Deserialize<'de>,
+        E: de::Error,
+    {
+        let (channel_id, data, name, pair) = match parts {
+            [channel_id, _, name, pair] => Ok((channel_id, &parts[1..2], name, pair)),
+            [channel_id, _, _, name, pair] => Ok((channel_id, &parts[1..3], name, pair)),
+            _ => Err(de::Error::invalid_length(
+                parts.len(),
+                &"4 or 5 book elements",
+            )),
+        }?;
+        let mut book = Book {
+            channel_id: parse_part(channel_id, "channel id")?,
+            pair: parse_part(pair, "pair")?,
+            depth: parse_depth(parse_part(name, "channel name")?)?,
+            snapshot: false,
+            asks: vec![],
+            bids: vec![],
+            checksum: None,
+        };
+        for part in data {
+            let data: BookResponseData<S> = parse_part(part, "book data")?;
+            book.snapshot |= data.snapshot_asks.is_some() || data.snapshot_bids.is_some();
+            book.asks.extend(data.snapshot_asks.into_iter().flatten());
+            book.asks.extend(data.asks.into_iter().flatten());
+            book.bids.extend(data.snapshot_bids.into_iter().flatten());
+            book.bids.extend(data.bids.into_iter().flatten());
+            book.checksum = book.checksum.or(data.checksum);
+        }
+        Ok(book)
+    }
+}
+
+impl<'de, S> Deserialize<'de> for Book<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = Parts::deserialize(deserializer)?;
+        match channel_name(&parts)? {
+            name if name.starts_with("book-") => Book::from_parts(&parts),
+            name => Err(de::Error::invalid_value(
+                de::Unexpected::Str(name),
+                &"book channel",
+            )),
+        }
+    }
+}
+
+// Snapshots are sent as one data element. Updates with levels
+// on both sides are sent as two, the checksum in the last.
+impl<S: Serialize> Serialize for Wire<'_, Book<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let book = self.0;
+        let checksum = book.checksum.map(|c| c.to_string());
+        let mut data = vec![];
+        if book.snapshot {
+            data.push(BookWireData {
+                snapshot_asks: Some(Levels(&book.asks)),
+                snapshot_bids: Some(Levels(&book.bids)),
+                checksum,
+                ..Default::default()
+            });
+        } else if !book.asks.is_empty() && !book.bids.is_empty() {
+            data.push(BookWireData {
+                asks: Some(Levels(&book.asks)),
+                ..Default::default()
+            });
+            data.push(BookWireData {
+                bids: Some(Levels(&book.bids)),
+                checksum,
+                ..Default::default()
+            });
+        } else if book.bids.is_empty() {
+            data.push(BookWireData {
+                asks: Some(Levels(&book.asks)),
+                checksum,
+                ..Default::default()
+            });
+        } else {
+            data.push(BookWireData {
+                bids: Some(Levels(&book.bids)),
+                checksum,
+                ..Default::default()
+            });
+        }
+        let mut tuple = serializer.serialize_tuple(data.len() + 3)?;
+        tuple.serialize_element(&book.channel_id)?;
+        for data in &data {
+            tuple.serialize_element(data)?;
+        }
+        tuple.serialize_element(&format!("book-{}", book.depth.levels()))?;
+        tuple.serialize_element(&book.pair)?;
+        tuple.end()
+    }
+}
+
+impl<S: Serialize> Serialize for Wire<'_, PriceLevel<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let level = self.0;
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&level.price)?;
+        seq.serialize_element(&level.volume)?;
+        seq.serialize_element(&level.time)?;
+        if level.republish {
+            seq.serialize_element("r")?;
+        }
+        seq.end()
+    }
+}
+
+// Internal type used for serializing each data element
+// of a book message.
+#[derive(Serialize)]
+struct BookWireData<'a, S> {
+    #[serde(rename = "as", skip_serializing_if = "Option::is_none")]
+    snapshot_asks: Option<Levels<'a, S>>,
+    #[serde(rename = "bs", skip_serializing_if = "Option::is_none")]
+    snapshot_bids: Option<Levels<'a, S>>,
+    #[serde(rename = "a", skip_serializing_if = "Option::is_none")]
+    asks: Option<Levels<'a, S>>,
+    #[serde(rename = "b", skip_serializing_if = "Option::is_none")]
+    bids: Option<Levels<'a, S>>,
+    #[serde(rename = "c", skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+impl<S> Default for BookWireData<'_, S> {
+    fn default() -> Self {
+        BookWireData {
+            snapshot_asks: None,
+            snapshot_bids: None,
+            asks: None,
+            bids: None,
+            checksum: None,
+        }
+    }
+}
+
+struct Levels<'a, S>(&'a [PriceLevel<S>]);
+
+impl<S: Serialize> Serialize for Levels<'_, S> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(Wire))
+    }
+}
+
+/// Read the depth from a book channel name e.g. "book-10".
+fn parse_depth<E>(channel_name: &str) -> Result<BookDepth, E>
+where
+    E: de::Error,
+{
+    channel_name
+        .strip_prefix("book-")
+        .and_then(|levels| levels.parse::<u32>().ok())
+        .and_then(|levels| BookDepth::try_from(levels).ok())
+        .ok_or_else(|| {
+            de::Error::invalid_value(de::Unexpected::Str(channel_name), &"book-<levels>")
+        })
+}
+
+// Internal type used for deserializing each data element
+// of a book message, snapshots use different keys.
+#[derive(Debug, Deserialize)]
+struct BookResponseData<S> {
+    #[serde(rename = "as")]
+    snapshot_asks: Option<Vec<PriceLevel<S>>>,
+    #[serde(rename = "bs")]
+    snapshot_bids: Option<Vec<PriceLevel<S>>>,
+    #[serde(rename = "a")]
+    asks: Option<Vec<PriceLevel<S>>>,
+    #[serde(rename = "b")]
+    bids: Option<Vec<PriceLevel<S>>>,
+    #[serde(rename = "c")]
+    #[serde(default, deserialize_with = "deserialize_checksum")]
+    checksum: Option<u32>,
+}
+
+fn deserialize_checksum<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let checksum = <&str>::deserialize(deserializer)?;
+    checksum.parse().map(Some).map_err(de::Error::custom)
+}
+
+// Levels are arrays of price, volume and time with a
+// trailing "r" in updates which republish a level.
+impl<'de, S> Deserialize<'de> for PriceLevel<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LevelVisitor<S>(PhantomData<S>);
+
+        impl<'de, S> Visitor<'de> for LevelVisitor<S>
+        where
+            S: Deserialize<'de>,
+        {
+            type Value = PriceLevel<S>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a price level array")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<PriceLevel<S>, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut next = |i| {
+                    seq.next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &"3 or 4 level elements"))
+                };
+                let level = PriceLevel {
+                    price: next(0)?,
+                    volume: next(1)?,
+                    time: seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(2, &"3 or 4 level elements"))?,
+                    republish: seq.next_element::<IgnoredAny>()?.is_some(),
+                };
+                Ok(level)
+            }
+        }
+
+        deserializer.deserialize_seq(LevelVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    const VALID_SNAPSHOT_RESPONSE: &str = r#"[
+      0,
+      {
+        "as": [
+          ["5541.30000", "2.50700000", "1534614248.123678"],
+          ["5541.80000", "0.33000000", "1534614098.345543"]
+        ],
+        "bs": [
+          ["5541.20000", "1.52900000", "1534614248.765567"]
+        ]
+      },
+      "book-10",
+      "XBT/USD"
+    ]"#;
+
+    const VALID_UPDATE_RESPONSE: &str = r#"[
+      1234,
+      {"a": [["5541.30000", "2.50700000", "1534614248.456738"]]},
+      {
+        "b": [
+          ["5541.30000", "0.00000000", "1534614335.345903"],
+          ["5540.10000", "1.00000000", "1534614335.345903", "r"]
+        ],
+        "c": "974942666"
+      },
+      "book-10",
+      "XBT/USD"
+    ]"#;
+
+    fn level(price: &str, volume: &str, micros: u64, republish: bool) -> PriceLevel {
+        PriceLevel {
+            price: price.to_string(),
+            volume: volume.to_string(),
+            time: Timestamp::from_micros(micros),
+            republish,
+        }
+    }
+
+    #[test]
+    fn snapshot_deserialization() -> Result<()> {
+        assert_eq!(
+            Book {
+                channel_id: 0,
+                pair: "XBT/USD".to_string(),
+                depth: BookDepth::N10,
+                snapshot: true,
+                asks: vec![
+                    level("5541.30000", "2.50700000", 1534614248123678, false),
+                    level("5541.80000", "0.33000000", 1534614098345543, false)
+                ],
+                bids: vec![level("5541.20000", "1.52900000", 1534614248765567, false)],
+                checksum: None
+            },
+            serde_json::from_str::<Book>(VALID_SNAPSHOT_RESPONSE)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn update_deserialization() -> Result<()> {
+        assert_eq!(
+            Book {
+                channel_id: 1234,
+                pair: "XBT/USD".to_string(),
+                depth: BookDepth::N10,
+                snapshot: false,
+                asks: vec![level("5541.30000", "2.50700000", 1534614248456738, false)],
+                bids: vec![
+                    level("5541.30000", "0.00000000", 1534614335345903, false),
+                    level("5540.10000", "1.00000000", 1534614335345903, true)
+                ],
+                checksum: Some(974942666)
+            },
+            serde_json::from_str::<Book>(VALID_UPDATE_RESPONSE)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let borrowed = serde_json::from_str::<Book<&str>>(VALID_UPDATE_RESPONSE)?;
+        assert_eq!(
+            serde_json::from_str::<Book>(VALID_UPDATE_RESPONSE)?,
+            borrowed.into_owned()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::resp::{event_object, Wire};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::ser::{Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::hash::{Hash, Hasher};
+
+/// Reply to a ping request.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Pong {
+    #[serde(rename = "reqid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u32>,
+}
+
+/// Sent on connection and whenever the status of the
+/// exchange changes.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct SystemStatus {
+    #[serde(rename = "connectionID")]
+    pub connection_id: u64,
+    pub status: SystemState,
+    pub version: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub enum SystemState {
+    #[serde(rename = "online")]
+    Online,
+    #[serde(rename = "maintenance")]
+    Maintenance,
+    #[serde(rename = "cancel_only")]
+    CancelOnly,
+    #[serde(rename = "limit_only")]
+    LimitOnly,
+    #[serde(rename = "post_only")]
+    PostOnly,
+}
+
+/// Reply to a subscribe or unsubscribe request, one is
+/// sent for each pair in the request.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct SubscriptionStatus {
+    #[serde(rename = "reqid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u32>,
+    #[serde(rename = "channelID")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<u32>,
+    #[serde(rename = "channelName")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pair: Option<String>,
+    pub status: SubscriptionState,
+    #[serde(rename = "errorMessage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<EchoedSubscription>,
+}
+
+/// The subscription from the request a status replies to, as
+/// Kraken echoes it back with any options such as a token.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(transparent)]
+pub struct EchoedSubscription(pub Value);
+
+impl Hash for EchoedSubscription {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_string().hash(state);
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub enum SubscriptionState {
+    #[serde(rename = "subscribed")]
+    Subscribed,
+    #[serde(rename = "unsubscribed")]
+    Unsubscribed,
+    #[serde(rename = "error")]
+    Error,
+}
+
+/// Reply to an addOrder request.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct AddOrderStatus {
+    #[serde(rename = "reqid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u32>,
+    pub status: RequestState,
+    /// Id of the order placed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(rename = "descr")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "errorMessage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+/// Reply to a cancelOrder request.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct CancelOrderStatus {
+    #[serde(rename = "reqid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u32>,
+    pub status: RequestState,
+    #[serde(rename = "errorMessage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+/// Sent instead of a status when a request can't be handled at
+/// all, such as one which isn't json or has an unknown event.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct RequestError {
+    #[serde(rename = "reqid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u32>,
+    #[serde(rename = "errorMessage")]
+    pub error_message: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub enum RequestState {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "error")]
+    Error,
+}
+
+macro_rules! wire_event {
+    ($type:ty, $event:expr) => {
+        impl Serialize for Wire<'_, $type> {
+            fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+            where
+                Z: Serializer,
+            {
+                event_object($event, self.0)?.serialize(serializer)
+            }
+        }
+    };
+}
+
+wire_event!(Pong, "pong");
+wire_event!(SystemStatus, "systemStatus");
+wire_event!(SubscriptionStatus, "subscriptionStatus");
+wire_event!(AddOrderStatus, "addOrderStatus");
+wire_event!(CancelOrderStatus, "cancelOrderStatus");
+wire_event!(RequestError, "error");
@@ -0,0 +1,63 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Deduplicates pair names so that long lived state keyed by
+/// pair holds one shared allocation per pair rather than one
+/// per message. Borrowed responses can be interned directly
+/// without first copying the pair out of the frame.
+#[derive(Debug, Clone, Default)]
+pub struct PairInterner {
+    pairs: HashSet<Arc<str>>,
+}
+
+impl PairInterner {
+    pub fn new() -> PairInterner {
+        Default::default()
+    }
+
+    /// Get the shared name for this pair, allocating it
+    /// only the first time it is seen.
+    pub fn intern(&mut self, pair: &str) -> Arc<str> {
+        match self.pairs.get(pair) {
+            Some(existing) => existing.clone(),
+            None => {
+                let interned: Arc<str> = Arc::from(pair);
+                self.pairs.insert(interned.clone());
+                interned
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repeated_pair_is_shared() {
+        let mut interner = PairInterner::new();
+        let first = interner.intern("XBT/USD");
+        let owned = String::from("XBT/USD");
+        let second = interner.intern(owned.as_str());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(1, interner.len());
+    }
+
+    #[test]
+    fn distinct_pairs_are_not_shared() {
+        let mut interner = PairInterner::new();
+        let xbt = interner.intern("XBT/USD");
+        let eth = interner.intern("ETH/USD");
+        assert_eq!("XBT/USD", &*xbt);
+        assert_eq!("ETH/USD", &*eth);
+        assert_eq!(2, interner.len());
+    }
+}
@@ -1,31 +1,371 @@
+pub mod book;
+pub mod event;
+pub mod intern;
 pub mod ohlc;
+pub mod private;
+pub mod spread;
 pub mod ticker;
+pub mod trade;
 
+use crate::resp::book::Book;
+use crate::resp::event::{
+    AddOrderStatus, CancelOrderStatus, Pong, RequestError, SubscriptionStatus, SystemStatus,
+};
 use crate::resp::ohlc::Ohlc;
+use crate::resp::private::{OpenOrders, OwnTrades};
+use crate::resp::spread::Spread;
 use crate::resp::ticker::TickerState;
-use serde_derive::{Deserialize, Serialize};
+use crate::resp::trade::Trades;
+use serde::de::value::MapDeserializer;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, Serializer};
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum Resp {
-    Ticker(TickerState),
-    Ohlc(Ohlc),
+/// Kraken Websocket response. Channel messages are arrays
+/// and are dispatched on the channel name they carry, event
+/// messages are objects and are dispatched on their `event`
+/// field. Neither is buffered into an intermediate form so
+/// this can only be deserialized from json.
+///
+/// The string fields of market data are generic so that they
+/// can borrow from the frame they were parsed from, see
+/// [`RespRef`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Resp<S = String> {
+    Ticker(TickerState<S>),
+    Ohlc(Ohlc<S>),
+    Trade(Trades<S>),
+    Spread(Spread<S>),
+    Book(Book<S>),
+    OwnTrades(OwnTrades<S>),
+    OpenOrders(OpenOrders<S>),
+    Heartbeat,
+    Pong(Pong),
+    SystemStatus(SystemStatus),
+    SubscriptionStatus(SubscriptionStatus),
+    AddOrderStatus(AddOrderStatus),
+    CancelOrderStatus(CancelOrderStatus),
+    RequestError(RequestError),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-#[serde(untagged)]
-enum IntOrDecimal {
-    Int(u64),
-    Dec(String),
+/// Response borrowing its market data from the source frame,
+/// parsing one of these does not allocate for channel messages.
+/// A string with escapes, such as `"XBT\/USD"`, is not in the
+/// frame as it reads so can't be borrowed and parsing fails,
+/// where it does the owned [`Resp`] should be parsed instead.
+pub type RespRef<'a> = Resp<&'a str>;
+
+impl Resp<&str> {
+    /// Copy any borrowed data into an owned response.
+    pub fn into_owned(self) -> Resp {
+        match self {
+            Resp::Ticker(t) => Resp::Ticker(t.into_owned()),
+            Resp::Ohlc(o) => Resp::Ohlc(o.into_owned()),
+            Resp::Trade(t) => Resp::Trade(t.into_owned()),
+            Resp::Spread(s) => Resp::Spread(s.into_owned()),
+            Resp::Book(b) => Resp::Book(b.into_owned()),
+            Resp::OwnTrades(t) => Resp::OwnTrades(t.into_owned()),
+            Resp::OpenOrders(o) => Resp::OpenOrders(o.into_owned()),
+            Resp::Heartbeat => Resp::Heartbeat,
+            Resp::Pong(p) => Resp::Pong(p),
+            Resp::SystemStatus(s) => Resp::SystemStatus(s),
+            Resp::SubscriptionStatus(s) => Resp::SubscriptionStatus(s),
+            Resp::AddOrderStatus(s) => Resp::AddOrderStatus(s),
+            Resp::CancelOrderStatus(s) => Resp::CancelOrderStatus(s),
+            Resp::RequestError(e) => Resp::RequestError(e),
+        }
+    }
+}
+
+/// Each response serializes as the message it holds, a
+/// heartbeat holds nothing so is written as Kraken sends it.
+impl<S: Serialize> Serialize for Resp<S> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        match self {
+            Resp::Ticker(t) => t.serialize(serializer),
+            Resp::Ohlc(o) => o.serialize(serializer),
+            Resp::Trade(t) => t.serialize(serializer),
+            Resp::Spread(s) => s.serialize(serializer),
+            Resp::Book(b) => b.serialize(serializer),
+            Resp::OwnTrades(t) => t.serialize(serializer),
+            Resp::OpenOrders(o) => o.serialize(serializer),
+            Resp::Heartbeat => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("event", "heartbeat")?;
+                map.end()
+            }
+            Resp::Pong(p) => p.serialize(serializer),
+            Resp::SystemStatus(s) => s.serialize(serializer),
+            Resp::SubscriptionStatus(s) => s.serialize(serializer),
+            Resp::AddOrderStatus(s) => s.serialize(serializer),
+            Resp::CancelOrderStatus(s) => s.serialize(serializer),
+            Resp::RequestError(e) => e.serialize(serializer),
+        }
+    }
+}
+
+impl<S: Serialize> Resp<S> {
+    /// The frame Kraken would send for this response.
+    pub fn to_wire(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&Wire(self))
+    }
+}
+
+/// Serializes a response, or any message it holds, in Kraken's
+/// wire format rather than with the field names the types use
+/// for their own `Serialize`. Parsing the output gives back an
+/// equal value, and frames as Kraken sends them come out byte
+/// for byte the same.
+pub struct Wire<'a, T>(pub &'a T);
+
+impl<S: Serialize> Serialize for Wire<'_, Resp<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        match self.0 {
+            Resp::Ticker(t) => Wire(t).serialize(serializer),
+            Resp::Ohlc(o) => Wire(o).serialize(serializer),
+            Resp::Trade(t) => Wire(t).serialize(serializer),
+            Resp::Spread(s) => Wire(s).serialize(serializer),
+            Resp::Book(b) => Wire(b).serialize(serializer),
+            Resp::OwnTrades(t) => Wire(t).serialize(serializer),
+            Resp::OpenOrders(o) => Wire(o).serialize(serializer),
+            Resp::Heartbeat => {
+                event_object("heartbeat", &Value::Object(Default::default()))?.serialize(serializer)
+            }
+            Resp::Pong(p) => Wire(p).serialize(serializer),
+            Resp::SystemStatus(s) => Wire(s).serialize(serializer),
+            Resp::SubscriptionStatus(s) => Wire(s).serialize(serializer),
+            Resp::AddOrderStatus(s) => Wire(s).serialize(serializer),
+            Resp::CancelOrderStatus(s) => Wire(s).serialize(serializer),
+            Resp::RequestError(e) => Wire(e).serialize(serializer),
+        }
+    }
+}
+
+/// Add the event name to the fields of an event message. Kraken
+/// sends the keys sorted, as serde_json orders those of a `Value`.
+pub(crate) fn event_object<T, E>(event: &str, fields: &T) -> Result<Value, E>
+where
+    T: Serialize,
+    E: ser::Error,
+{
+    let mut object = serde_json::to_value(fields).map_err(E::custom)?;
+    object["event"] = Value::from(event);
+    Ok(object)
+}
+
+const CHANNELS: &[&str] = &[
+    "ticker",
+    "ohlc-*",
+    "trade",
+    "spread",
+    "book-*",
+    "ownTrades",
+    "openOrders",
+];
+const EVENTS: &[&str] = &[
+    "heartbeat",
+    "pong",
+    "systemStatus",
+    "subscriptionStatus",
+    "addOrderStatus",
+    "cancelOrderStatus",
+    "error",
+];
+
+impl<'de, S> Deserialize<'de> for Resp<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(RespVisitor(PhantomData))
+    }
+}
+
+struct RespVisitor<S>(PhantomData<S>);
+
+impl<'de, S> Visitor<'de> for RespVisitor<S>
+where
+    S: Deserialize<'de>,
+{
+    type Value = Resp<S>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a channel message array or an event object")
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Resp<S>, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let parts = Parts::read(seq)?;
+        match channel_name(&parts)? {
+            "ticker" => TickerState::from_parts(&parts).map(Resp::Ticker),
+            name if name.starts_with("ohlc-") => Ohlc::from_parts(&parts).map(Resp::Ohlc),
+            "trade" => Trades::from_parts(&parts).map(Resp::Trade),
+            "spread" => Spread::from_parts(&parts).map(Resp::Spread),
+            name if name.starts_with("book-") => Book::from_parts(&parts).map(Resp::Book),
+            "ownTrades" => OwnTrades::from_parts(&parts).map(Resp::OwnTrades),
+            "openOrders" => OpenOrders::from_parts(&parts).map(Resp::OpenOrders),
+            name => Err(de::Error::unknown_variant(name, CHANNELS)),
+        }
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Resp<S>, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut event = None;
+        let mut fields: Vec<(&'de str, &'de RawValue)> = Vec::with_capacity(8);
+        while let Some((key, value)) = map.next_entry::<&'de str, &'de RawValue>()? {
+            if key == "event" {
+                event = Some(parse_part::<&str, _>(value, "event")?);
+            } else {
+                fields.push((key, value));
+            }
+        }
+        match event.ok_or_else(|| de::Error::missing_field("event"))? {
+            "heartbeat" => Ok(Resp::Heartbeat),
+            "pong" => parse_fields(fields, "pong").map(Resp::Pong),
+            "systemStatus" => parse_fields(fields, "systemStatus").map(Resp::SystemStatus),
+            "subscriptionStatus" => {
+                parse_fields(fields, "subscriptionStatus").map(Resp::SubscriptionStatus)
+            }
+            "addOrderStatus" => parse_fields(fields, "addOrderStatus").map(Resp::AddOrderStatus),
+            "cancelOrderStatus" => {
+                parse_fields(fields, "cancelOrderStatus").map(Resp::CancelOrderStatus)
+            }
+            "error" => parse_fields(fields, "error").map(Resp::RequestError),
+            event => Err(de::Error::unknown_variant(event, EVENTS)),
+        }
+    }
+}
+
+/// The most elements in any channel message.
+const MAX_PARTS: usize = 5;
+
+/// The raw elements of a channel message. The channel name is
+/// the penultimate element so the array is split up before any
+/// element is parsed, the elements are just slices of the input
+/// and are kept on the stack.
+pub(crate) struct Parts<'de> {
+    buf: [&'de RawValue; MAX_PARTS],
+    len: usize,
+}
+
+impl<'de> Parts<'de> {
+    fn read<A>(mut seq: A) -> Result<Parts<'de>, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut parts = Parts {
+            buf: [RawValue::NULL; MAX_PARTS],
+            len: 0,
+        };
+        while let Some(part) = seq.next_element()? {
+            if parts.len == MAX_PARTS {
+                return Err(de::Error::invalid_length(
+                    parts.len + 1,
+                    &"at most 5 elements",
+                ));
+            }
+            parts.buf[parts.len] = part;
+            parts.len += 1;
+        }
+        Ok(parts)
+    }
+}
+
+impl<'de> Deref for Parts<'de> {
+    type Target = [&'de RawValue];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf[..self.len]
+    }
+}
+
+impl<'de> Deserialize<'de> for Parts<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PartsVisitor;
+
+        impl<'de> Visitor<'de> for PartsVisitor {
+            type Value = Parts<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a channel message array")
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Parts<'de>, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Parts::read(seq)
+            }
+        }
+
+        deserializer.deserialize_seq(PartsVisitor)
+    }
+}
+
+/// Read the name from the raw elements of a channel message,
+/// private channels have no channel id or pair so are shorter.
+fn channel_name<'de, E>(parts: &[&'de RawValue]) -> Result<&'de str, E>
+where
+    E: de::Error,
+{
+    match parts.len() {
+        n if n < 3 => Err(de::Error::invalid_length(n, &"at least 3 elements")),
+        n => parse_part(parts[n - 2], "channel name"),
+    }
+}
+
+/// Parse one raw element of a message, the description
+/// of the element is included in any error.
+fn parse_part<'de, T, E>(raw: &'de RawValue, what: &str) -> Result<T, E>
+where
+    T: Deserialize<'de>,
+    E: de::Error,
+{
+    serde_json::from_str(raw.get()).map_err(|e| de::Error::custom(format!("{}: {}", what, e)))
+}
+
+/// Parse the raw fields of an event object into its type.
+fn parse_fields<'de, T, E>(fields: Vec<(&'de str, &'de RawValue)>, event: &str) -> Result<T, E>
+where
+    T: Deserialize<'de>,
+    E: de::Error,
+{
+    T::deserialize(MapDeserializer::new(fields.into_iter()))
+        .map_err(|e: serde_json::Error| de::Error::custom(format!("{}: {}", event, e)))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::req::OhlcInterval;
+    use crate::resp::event::{EchoedSubscription, RequestState, SubscriptionState, SystemState};
     use crate::resp::ticker::{BidAskData, ValueMarker};
+    use crate::time::Timestamp;
     use anyhow::Result;
+    use serde_json::json;
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn ticker_deserialization() -> Result<()> {
         assert_eq!(
             Resp::Ticker(TickerState {
@@ -97,8 +437,8 @@ mod test {
         assert_eq!(
             Resp::Ohlc(Ohlc {
                 channel_id: 42,
-                time: "1542057314.748456".to_owned(),
-                etime: "1542057360.435743".to_string(),
+                time: Timestamp::from_micros(1542057314748456),
+                etime: Timestamp::from_micros(1542057360435743),
                 open: "3586.70001".to_string(),
                 high: "3586.70000".to_string(),
                 low: "3586.60001".to_string(),
@@ -106,7 +446,7 @@ mod test {
                 vwap: "3586.68894".to_string(),
                 volume: "0.03373000".to_string(),
                 count: 2,
-                channel_name: "ohlc-5".to_string(),
+                interval: OhlcInterval::Mins5,
                 pair: "XBT/USD".to_string()
             }),
             serde_json::from_str::<Resp>(
@@ -131,4 +471,225 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn heartbeat_deserialization() -> Result<()> {
+        assert_eq!(
+            Resp::Heartbeat,
+            serde_json::from_str::<Resp>(r#"{"event":"heartbeat"}"#)?
+        );
+        assert_eq!(
+            r#"{"event":"heartbeat"}"#,
+            serde_json::to_string(&Resp::<String>::Heartbeat)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pong_deserialization() -> Result<()> {
+        assert_eq!(
+            Resp::Pong(Pong {
+                request_id: Some(42)
+            }),
+            serde_json::from_str::<Resp>(r#"{"event":"pong","reqid":42}"#)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn system_status_deserialization() -> Result<()> {
+        assert_eq!(
+            Resp::SystemStatus(SystemStatus {
+                connection_id: 8628615390848610000,
+                status: SystemState::Online,
+                version: "1.0.0".to_string()
+            }),
+            serde_json::from_str::<Resp>(
+                r#"{
+                  "connectionID": 8628615390848610000,
+                  "event": "systemStatus",
+                  "status": "online",
+                  "version": "1.0.0"
+                }"#
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subscription_status_deserialization() -> Result<()> {
+        assert_eq!(
+            Resp::SubscriptionStatus(SubscriptionStatus {
+                request_id: None,
+                channel_id: Some(10001),
+                channel_name: Some("ohlc-5".to_string()),
+                pair: Some("XBT/EUR".to_string()),
+                status: SubscriptionState::Subscribed,
+                error_message: None,
+                subscription: Some(EchoedSubscription(json!({ "interval": 5, "name": "ohlc" })))
+            }),
+            serde_json::from_str::<Resp>(
+                r#"{
+                  "channelID": 10001,
+                  "channelName": "ohlc-5",
+                  "event": "subscriptionStatus",
+                  "pair": "XBT/EUR",
+                  "status": "subscribed",
+                  "subscription": {
+                    "interval": 5,
+                    "name": "ohlc"
+                  }
+                }"#
+            )?
+        );
+        assert_eq!(
+            Resp::SubscriptionStatus(SubscriptionStatus {
+                request_id: Some(3),
+                channel_id: None,
+                channel_name: None,
+                pair: Some("XBT/USD".to_string()),
+                status: SubscriptionState::Error,
+                error_message: Some("Subscription depth not supported".to_string()),
+                subscription: Some(EchoedSubscription(json!({ "depth": 42, "name": "book" })))
+            }),
+            serde_json::from_str::<Resp>(
+                r#"{
+                  "errorMessage": "Subscription depth not supported",
+                  "event": "subscriptionStatus",
+                  "pair": "XBT/USD",
+                  "reqid": 3,
+                  "status": "error",
+                  "subscription": {
+                    "depth": 42,
+                    "name": "book"
+                  }
+                }"#
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn order_status_deserialization() -> Result<()> {
+        assert_eq!(
+            Resp::AddOrderStatus(AddOrderStatus {
+                request_id: Some(7),
+                status: RequestState::Ok,
+                txid: Some("ONPNXH-KMKMU-F4MR5V".to_string()),
+                description: Some("buy 0.01770000 XBTEUR @ limit 4000".to_string()),
+                error_message: None
+            }),
+            serde_json::from_str::<Resp>(
+                r#"{
+                  "descr": "buy 0.01770000 XBTEUR @ limit 4000",
+                  "event": "addOrderStatus",
+                  "reqid": 7,
+                  "status": "ok",
+                  "txid": "ONPNXH-KMKMU-F4MR5V"
+                }"#
+            )?
+        );
+        assert_eq!(
+            Resp::CancelOrderStatus(CancelOrderStatus {
+                request_id: None,
+                status: RequestState::Error,
+                error_message: Some("EOrder:Unknown order".to_string())
+            }),
+            serde_json::from_str::<Resp>(
+                r#"{"event":"cancelOrderStatus","status":"error","errorMessage":"EOrder:Unknown order"}"#
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_channel_is_named_in_error() {
+        let err = serde_json::from_str::<Resp>(r#"[1, {}, "nonsense", "XBT/USD"]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("nonsense"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_event_is_named_in_error() {
+        let err = serde_json::from_str::<Resp>(r#"{"event":"nonsense"}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("nonsense"), "{}", err);
+    }
+
+    #[test]
+    fn bad_data_is_named_in_error() {
+        let err = serde_json::from_str::<Resp>(r#"[1, [], "ohlc-5", "XBT/USD"]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("ohlc data"), "{}", err);
+    }
+
+    #[test]
+    fn wire_round_trip() -> Result<()> {
+        let frames = [
+            r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#,
+            r#"[42,["1542057314.748456","1542057360.435743","3586.70001","3586.70000","3586.60001","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#,
+            r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["6060.00000","0.02455000","1534614057.324998","b","l",""]],"trade","XBT/USD"]"#,
+            r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#,
+            r#"[0,{"as":[["5541.30000","2.50700000","1534614248.123678"]],"bs":[["5541.20000","1.52900000","1534614248.765567"]]},"book-10","XBT/USD"]"#,
+            r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738"]]},{"b":[["5541.30000","0.00000000","1534614335.345903"],["5540.10000","1.00000000","1534614335.345903","r"]],"c":"974942666"},"book-10","XBT/USD"]"#,
+            r#"[1234,{"b":[["5541.30000","0.00000000","1534614335.345903"]],"c":"974942666"},"book-25","XBT/USD"]"#,
+            r#"[[{"TDLH43-DVQXD-2KHVYY":{"cost":"1000000.00000","fee":"1600.00000","margin":"0.00000","ordertxid":"TDLH43-DVQXD-2KHVYY","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070651","type":"sell","vol":"1000000000.00000000"}}],"ownTrades",{"sequence":2}]"#,
+            r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"avg_price":"34.50000","cost":"0.00000","descr":{"close":null,"leverage":"0:1","order":"sell 10.00345345 XBT/EUR @ limit 34.50000 with 0:1 leverage","ordertype":"limit","pair":"XBT/EUR","price":"34.50000","price2":"0.00000","type":"sell"},"fee":"0.00000","opentm":"0.000000","status":"open","userref":0,"vol":"10.00345345","vol_exec":"0.00000000"}},{"OGTT3Y-C6I3P-XRI6HY":{"status":"closed"}}],"openOrders",{"sequence":3}]"#,
+            r#"{"event":"heartbeat"}"#,
+            r#"{"event":"pong","reqid":42}"#,
+            r#"{"connectionID":8628615390848610000,"event":"systemStatus","status":"online","version":"1.0.0"}"#,
+            r#"{"channelID":10001,"channelName":"ohlc-5","event":"subscriptionStatus","pair":"XBT/EUR","status":"subscribed","subscription":{"interval":5,"name":"ohlc"}}"#,
+            r#"{"channelName":"ownTrades","event":"subscriptionStatus","status":"subscribed","subscription":{"name":"ownTrades"}}"#,
+            r#"{"channelID":10002,"channelName":"book-10","event":"subscriptionStatus","pair":"XBT/EUR","status":"subscribed","subscription":{"depth":10,"name":"book","ratecounter":true}}"#,
+            r#"{"errorMessage":"Subscription depth not supported","event":"subscriptionStatus","pair":"XBT/USD","reqid":3,"status":"error","subscription":{"depth":42,"name":"book"}}"#,
+            r#"{"descr":"buy 0.01770000 XBTEUR @ limit 4000","event":"addOrderStatus","reqid":7,"status":"ok","txid":"ONPNXH-KMKMU-F4MR5V"}"#,
+            r#"{"errorMessage":"EOrder:Unknown order","event":"cancelOrderStatus","status":"error"}"#,
+            r#"{"errorMessage":"Unsupported event","event":"error","reqid":4}"#,
+        ];
+        for frame in frames.iter() {
+            let resp = serde_json::from_str::<Resp>(frame)?;
+            assert_eq!(*frame, resp.to_wire()?);
+            let borrowed = serde_json::from_str::<RespRef>(frame)?;
+            assert_eq!(*frame, serde_json::to_string(&Wire(&borrowed))?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn wire_round_trip_without_subscription() -> Result<()> {
+        let status: Resp = Resp::SubscriptionStatus(SubscriptionStatus {
+            request_id: Some(3),
+            channel_id: None,
+            channel_name: None,
+            pair: Some("XBT/USD".to_string()),
+            status: SubscriptionState::Error,
+            error_message: Some("Subscription depth not supported".to_string()),
+            subscription: None,
+        });
+        assert_eq!(status, serde_json::from_str(&status.to_wire()?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let frame = r#"[42,["1542057314.748456","1542057360.435743","3586.70001","3586.70000","3586.60001","3586.60000","3586.68894","0.03373000",2],"ohlc-5","XBT/USD"]"#;
+        let borrowed = serde_json::from_str::<RespRef>(frame)?;
+        match &borrowed {
+            Resp::Ohlc(ohlc) => assert_eq!("XBT/USD", ohlc.pair),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(serde_json::from_str::<Resp>(frame)?, borrowed.into_owned());
+
+        // Escaped strings can only be parsed owned.
+        let escaped = frame.replace("XBT/USD", r"XBT\/USD");
+        assert!(serde_json::from_str::<RespRef>(&escaped).is_err());
+        assert_eq!(
+            serde_json::from_str::<Resp>(frame)?,
+            serde_json::from_str::<Resp>(&escaped)?
+        );
+        Ok(())
+    }
 }
@@ -1,101 +1,160 @@
-use crate::resp::IntOrDecimal;
-use anyhow::Result;
-use serde::{de, Deserialize, Deserializer};
-use serde_derive::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
+use crate::req::OhlcInterval;
+use crate::resp::{channel_name, parse_part, Parts, Wire};
+use crate::time::Timestamp;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+use serde_derive::Serialize;
+use serde_json::value::RawValue;
+use std::convert::TryFrom;
 
+/// An update to the candle for the current interval, Kraken
+/// sends one of these each time a trade changes the candle.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
-pub struct Ohlc {
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Ohlc"))]
+pub struct Ohlc<S = String> {
     #[serde(rename = "channelId")]
     pub channel_id: u32,
-    #[serde(rename = "channelName")]
-    pub channel_name: String,
-    pub pair: String,
-    pub time: String,
-    pub etime: String,
-    pub open: String,
-    pub high: String,
-    pub low: String,
-    pub close: String,
-    pub vwap: String,
-    pub volume: String,
+    pub interval: OhlcInterval,
+    pub pair: S,
+    /// Time of the last update to the candle.
+    pub time: Timestamp,
+    /// End time of the candle interval.
+    pub etime: Timestamp,
+    pub open: S,
+    pub high: S,
+    pub low: S,
+    pub close: S,
+    pub vwap: S,
+    pub volume: S,
     pub count: u32,
 }
 
-impl<'de> Deserialize<'de> for Ohlc {
-    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+impl Ohlc<&str> {
+    /// Copy the borrowed fields into an owned candle.
+    pub fn into_owned(self) -> Ohlc {
+        Ohlc {
+            channel_id: self.channel_id,
+            interval: self.interval,
+            pair: self.pair.to_owned(),
+            time: self.time,
+            etime: self.etime,
+            open: self.open.to_owned(),
+            high: self.high.to_owned(),
+            low: self.low.to_owned(),
+            close: self.close.to_owned(),
+            vwap: self.vwap.to_owned(),
+            volume: self.volume.to_owned(),
+            count: self.count,
+        }
+    }
+}
+
+impl<S> Ohlc<S> {
+    /// Start time of the candle interval.
+    pub fn start(&self) -> Timestamp {
+        self.etime - self.interval.duration()
+    }
+
+    /// Build from the raw elements of an ohlc channel message.
+    pub(crate) fn from_parts<'de, E>(parts: &[&'de RawValue]) -> Result<Ohlc<S>, E>
     where
-        D: Deserializer<'de>,
+        S: Deserialize<'de>,
+        E: de::Error,
     {
-        // Delegate the heavy lifting to the internal representation
-        let internal = InternalOhlcResponse::deserialize(deserializer)?;
-
-        let data = match &internal.0[1] {
-            OhlcResponsePart::Data(d) => Ok(d),
-            _ => Err(de::Error::custom("Second component must be ticker data")),
+        let (channel_id, data, channel_name, pair) = match parts {
+            [channel_id, data, channel_name, pair] => Ok((channel_id, data, channel_name, pair)),
+            _ => Err(de::Error::invalid_length(parts.len(), &"4 ohlc elements")),
         }?;
+        let (time, etime, open, high, low, close, vwap, volume, count): OhlcResponseData<S> =
+            parse_part(data, "ohlc data")?;
 
         Ok(Ohlc {
-            channel_id: match internal.0[0] {
-                OhlcResponsePart::UInt(n) => Ok(n),
-                _ => Err(de::Error::custom("First component must be channel id")),
-            }?,
-            time: force_dec::<D>(&data.0[0], "Time component must be decimal")?,
-            etime: force_dec::<D>(&data.0[1], "Etime component must be decimal")?,
-            open: force_dec::<D>(&data.0[2], "open component must be decimal")?,
-            high: force_dec::<D>(&data.0[3], "high component must be decimal")?,
-            low: force_dec::<D>(&data.0[4], "low component must be decimal")?,
-            close: force_dec::<D>(&data.0[5], "close component must be decimal")?,
-            vwap: force_dec::<D>(&data.0[6], "vwap component must be decimal")?,
-            volume: force_dec::<D>(&data.0[7], "volume component must be decimal")?,
-            count: match &data.0[8] {
-                IntOrDecimal::Int(n) => Ok(*n as u32),
-                _ => Err(de::Error::custom("count component must be integer")),
-            }?,
-            channel_name: match &internal.0[2] {
-                OhlcResponsePart::Str(s) => Ok(s.clone()),
-                _ => Err(de::Error::custom("")),
-            }?,
-            pair: match &internal.0[3] {
-                OhlcResponsePart::Str(s) => Ok(s.clone()),
-                _ => Err(de::Error::custom("")),
-            }?,
+            channel_id: parse_part(channel_id, "channel id")?,
+            time,
+            etime,
+            open,
+            high,
+            low,
+            close,
+            vwap,
+            volume,
+            count,
+            interval: parse_interval(parse_part(channel_name, "channel name")?)?,
+            pair: parse_part(pair, "pair")?,
         })
     }
 }
 
-fn force_dec<'de, D>(x: &IntOrDecimal, e: &str) -> Result<String, <D as Deserializer<'de>>::Error>
+impl<'de, S> Deserialize<'de> for Ohlc<S>
 where
-    D: Deserializer<'de>,
+    S: Deserialize<'de>,
 {
-    match x {
-        IntOrDecimal::Dec(s) => Ok(s.clone()),
-        _ => Err(de::Error::custom(e)),
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = Parts::deserialize(deserializer)?;
+        match channel_name(&parts)? {
+            name if name.starts_with("ohlc-") => Ohlc::from_parts(&parts),
+            name => Err(de::Error::invalid_value(
+                de::Unexpected::Str(name),
+                &"ohlc channel",
+            )),
+        }
     }
 }
 
-// Internal type used for deserializing the ticker
-// update which is an array of different types.
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-struct InternalOhlcResponse([OhlcResponsePart; 4]);
+impl<S: Serialize> Serialize for Wire<'_, Ohlc<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let ohlc = self.0;
+        let data: OhlcResponseData<&S> = (
+            ohlc.time,
+            ohlc.etime,
+            &ohlc.open,
+            &ohlc.high,
+            &ohlc.low,
+            &ohlc.close,
+            &ohlc.vwap,
+            &ohlc.volume,
+            ohlc.count,
+        );
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&ohlc.channel_id)?;
+        tuple.serialize_element(&data)?;
+        tuple.serialize_element(&format!("ohlc-{}", ohlc.interval.minutes()))?;
+        tuple.serialize_element(&ohlc.pair)?;
+        tuple.end()
+    }
+}
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-#[serde(untagged)]
-enum OhlcResponsePart {
-    UInt(u32),
-    Data(OhlcResponseData),
-    Str(String),
+/// Read the interval from an ohlc channel name e.g. "ohlc-5".
+fn parse_interval<E>(channel_name: &str) -> Result<OhlcInterval, E>
+where
+    E: de::Error,
+{
+    channel_name
+        .strip_prefix("ohlc-")
+        .and_then(|minutes| minutes.parse::<u32>().ok())
+        .and_then(|minutes| OhlcInterval::try_from(minutes).ok())
+        .ok_or_else(|| {
+            de::Error::invalid_value(de::Unexpected::Str(channel_name), &"ohlc-<minutes>")
+        })
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-struct OhlcResponseData([IntOrDecimal; 9]);
+// Internal type used for the data element of the ohlc update.
+type OhlcResponseData<S> = (Timestamp, Timestamp, S, S, S, S, S, S, u32);
 
 #[cfg(test)]
 mod test {
     use super::*;
     use anyhow::Result;
 
-    const VALID_OHLC_RESPONSE: &'static str = r#"
+    const VALID_OHLC_RESPONSE: &str = r#"
     [
       42,
       [
@@ -118,8 +177,8 @@ mod test {
         assert_eq!(
             Ohlc {
                 channel_id: 42,
-                time: "1542057314.748456".to_owned(),
-                etime: "1542057360.435743".to_string(),
+                time: Timestamp::from_micros(1542057314748456),
+                etime: Timestamp::from_micros(1542057360435743),
                 open: "3586.70001".to_string(),
                 high: "3586.70000".to_string(),
                 low: "3586.60001".to_string(),
@@ -127,11 +186,37 @@ mod test {
                 vwap: "3586.68894".to_string(),
                 volume: "0.03373000".to_string(),
                 count: 2,
-                channel_name: "ohlc-5".to_string(),
+                interval: OhlcInterval::Mins5,
                 pair: "XBT/USD".to_string()
             },
             serde_json::from_str::<Ohlc>(VALID_OHLC_RESPONSE)?
         );
         Ok(())
     }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let borrowed = serde_json::from_str::<Ohlc<&str>>(VALID_OHLC_RESPONSE)?;
+        assert_eq!("3586.68894", borrowed.vwap);
+        assert_eq!(
+            serde_json::from_str::<Ohlc>(VALID_OHLC_RESPONSE)?,
+            borrowed.into_owned()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn candle_start() -> Result<()> {
+        let ohlc = serde_json::from_str::<Ohlc>(VALID_OHLC_RESPONSE)?;
+        assert_eq!(Timestamp::from_micros(1542057060435743), ohlc.start());
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_interval() {
+        let err = serde_json::from_str::<Ohlc>(&VALID_OHLC_RESPONSE.replace("ohlc-5", "ohlc-7"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("ohlc-7"), "{}", err);
+    }
 }
@@ -0,0 +1,149 @@
+use crate::resp::{channel_name, parse_part, Parts, Wire};
+use crate::time::Timestamp;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+use serde_derive::Serialize;
+use serde_json::value::RawValue;
+
+/// The best bid and ask for a pair, sent whenever either changes.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Spread"))]
+pub struct Spread<S = String> {
+    #[serde(rename = "channelId")]
+    pub channel_id: u32,
+    pub pair: S,
+    pub bid: S,
+    pub ask: S,
+    pub time: Timestamp,
+    #[serde(rename = "bidVolume")]
+    pub bid_volume: S,
+    #[serde(rename = "askVolume")]
+    pub ask_volume: S,
+}
+
+impl Spread<&str> {
+    /// Copy the borrowed fields into an owned spread.
+    pub fn into_owned(self) -> Spread {
+        Spread {
+            channel_id: self.channel_id,
+            pair: self.pair.to_owned(),
+            bid: self.bid.to_owned(),
+            ask: self.ask.to_owned(),
+            time: self.time,
+            bid_volume: self.bid_volume.to_owned(),
+            ask_volume: self.ask_volume.to_owned(),
+        }
+    }
+}
+
+impl<S> Spread<S> {
+    /// Build from the raw elements of a spread channel message.
+    pub(crate) fn from_parts<'de, E>(parts: &[&'de RawValue]) -> Result<Spread<S>, E>
+    where
+        S: Deserialize<'de>,
+        E: de::Error,
+    {
+        let (channel_id, data, pair) = match parts {
+            [channel_id, data, _, pair] => Ok((channel_id, data, pair)),
+            _ => Err(de::Error::invalid_length(parts.len(), &"4 spread elements")),
+        }?;
+        let (bid, ask, time, bid_volume, ask_volume): SpreadResponseData<S> =
+            parse_part(data, "spread data")?;
+        Ok(Spread {
+            channel_id: parse_part(channel_id, "channel id")?,
+            pair: parse_part(pair, "pair")?,
+            bid,
+            ask,
+            time,
+            bid_volume,
+            ask_volume,
+        })
+    }
+}
+
+impl<'de, S> Deserialize<'de> for Spread<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = Parts::deserialize(deserializer)?;
+        match channel_name(&parts)? {
+            "spread" => Spread::from_parts(&parts),
+            name => Err(de::Error::invalid_value(
+                de::Unexpected::Str(name),
+                &"spread channel",
+            )),
+        }
+    }
+}
+
+impl<S: Serialize> Serialize for Wire<'_, Spread<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let spread = self.0;
+        let data: SpreadResponseData<&S> = (
+            &spread.bid,
+            &spread.ask,
+            spread.time,
+            &spread.bid_volume,
+            &spread.ask_volume,
+        );
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&spread.channel_id)?;
+        tuple.serialize_element(&data)?;
+        tuple.serialize_element("spread")?;
+        tuple.serialize_element(&spread.pair)?;
+        tuple.end()
+    }
+}
+
+// Internal type used for the data element of the
+// spread update.
+type SpreadResponseData<S> = (S, S, Timestamp, S, S);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    const VALID_SPREAD_RESPONSE: &str = r#"[
+      0,
+      ["5698.40000", "5700.00000", "1542057299.545897", "1.01234567", "0.98765432"],
+      "spread",
+      "XBT/USD"
+    ]"#;
+
+    #[test]
+    fn external_success_deserialization() -> Result<()> {
+        assert_eq!(
+            Spread {
+                channel_id: 0,
+                pair: "XBT/USD".to_string(),
+                bid: "5698.40000".to_string(),
+                ask: "5700.00000".to_string(),
+                time: Timestamp::from_micros(1542057299545897),
+                bid_volume: "1.01234567".to_string(),
+                ask_volume: "0.98765432".to_string()
+            },
+            serde_json::from_str::<Spread>(VALID_SPREAD_RESPONSE)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let borrowed = serde_json::from_str::<Spread<&str>>(VALID_SPREAD_RESPONSE)?;
+        assert_eq!(
+            serde_json::from_str::<Spread>(VALID_SPREAD_RESPONSE)?,
+            borrowed.into_owned()
+        );
+        Ok(())
+    }
+}
@@ -1,154 +1,225 @@
-use crate::resp::IntOrDecimal;
-use anyhow::{anyhow, Result};
-use serde::{de, Deserialize, Deserializer, Serialize};
+use crate::resp::{channel_name, parse_part, Parts, Wire};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
 use serde_derive::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
+use serde_json::value::RawValue;
 
 /// Information about the ticker for a particular
 /// pair at a given point in time.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
-pub struct TickerState {
+#[cfg_attr(
+    feature = "schema",
+    derive(JsonSchema),
+    schemars(rename = "TickerState")
+)]
+pub struct TickerState<S = String> {
     #[serde(rename = "channelId")]
     pub channel_id: u32,
-    pub pair: String,
-    pub ask: BidAskData,
-    pub bid: BidAskData,
-    pub close: ValueMarker<String>,
-    pub volume: ValueMarker<String>,
+    pub pair: S,
+    pub ask: BidAskData<S>,
+    pub bid: BidAskData<S>,
+    pub close: ValueMarker<S>,
+    pub volume: ValueMarker<S>,
     #[serde(rename = "volumeWeightedAvgPrice")]
-    pub volume_weighted_avg_price: ValueMarker<String>,
+    pub volume_weighted_avg_price: ValueMarker<S>,
     #[serde(rename = "tradeCount")]
     pub trade_count: ValueMarker<u32>,
     #[serde(rename = "lowPrice")]
-    pub low_price: ValueMarker<String>,
+    pub low_price: ValueMarker<S>,
     #[serde(rename = "highPrice")]
-    pub high_price: ValueMarker<String>,
+    pub high_price: ValueMarker<S>,
     #[serde(rename = "openPrice")]
-    pub open_price: ValueMarker<String>,
+    pub open_price: ValueMarker<S>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
-pub struct BidAskData {
-    pub price: String,
+#[cfg_attr(
+    feature = "schema",
+    derive(JsonSchema),
+    schemars(rename = "BidAskData")
+)]
+pub struct BidAskData<S = String> {
+    pub price: S,
     #[serde(rename = "wholeLotVolume")]
     pub whole_lot_volume: u64,
     #[serde(rename = "lotVolume")]
-    pub lot_volume: String,
+    pub lot_volume: S,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
-pub struct ValueMarker<T>
-where
-    T: Debug + Clone + Eq + PartialEq + Hash + Serialize,
-{
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct ValueMarker<T> {
     pub today: T,
     #[serde(rename = "last24h")]
     pub last_24h: T,
 }
 
-impl BidAskData {
-    fn try_from(src: &[IntOrDecimal; 3]) -> Result<BidAskData> {
-        Ok(BidAskData {
-            price: match &src[0] {
-                IntOrDecimal::Dec(s) => Ok(s.clone()),
-                _ => Err(anyhow!("First element in bid/ask must be decimal price")),
-            }?,
-            whole_lot_volume: match &src[1] {
-                IntOrDecimal::Int(n) => Ok(*n),
-                _ => Err(anyhow!(
-                    "Second element in bid/ask must be int whole lot vol"
-                )),
-            }?,
-            lot_volume: match &src[2] {
-                IntOrDecimal::Dec(s) => Ok(s.clone()),
-                _ => Err(anyhow!("Second element in bid/ask must be decimal lot vol")),
-            }?,
-        })
+impl<S> From<(S, u64, S)> for BidAskData<S> {
+    fn from((price, whole_lot_volume, lot_volume): (S, u64, S)) -> BidAskData<S> {
+        BidAskData {
+            price,
+            whole_lot_volume,
+            lot_volume,
+        }
     }
 }
 
-impl<T> ValueMarker<T>
-where
-    T: Debug + Clone + Eq + PartialEq + Hash + Serialize,
-{
-    fn from(src: &[T; 2]) -> ValueMarker<T> {
+impl<T> From<[T; 2]> for ValueMarker<T> {
+    fn from([today, last_24h]: [T; 2]) -> ValueMarker<T> {
+        ValueMarker { today, last_24h }
+    }
+}
+
+impl<T> ValueMarker<T> {
+    pub fn map<U, F>(self, mut f: F) -> ValueMarker<U>
+    where
+        F: FnMut(T) -> U,
+    {
         ValueMarker {
-            today: src[0].clone(),
-            last_24h: src[1].clone(),
+            today: f(self.today),
+            last_24h: f(self.last_24h),
         }
     }
 }
 
-impl<'de> Deserialize<'de> for TickerState {
-    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+impl BidAskData<&str> {
+    pub fn into_owned(self) -> BidAskData {
+        BidAskData {
+            price: self.price.to_owned(),
+            whole_lot_volume: self.whole_lot_volume,
+            lot_volume: self.lot_volume.to_owned(),
+        }
+    }
+}
+
+impl TickerState<&str> {
+    /// Copy the borrowed fields into an owned ticker.
+    pub fn into_owned(self) -> TickerState {
+        TickerState {
+            channel_id: self.channel_id,
+            pair: self.pair.to_owned(),
+            ask: self.ask.into_owned(),
+            bid: self.bid.into_owned(),
+            close: self.close.map(str::to_owned),
+            volume: self.volume.map(str::to_owned),
+            volume_weighted_avg_price: self.volume_weighted_avg_price.map(str::to_owned),
+            trade_count: self.trade_count,
+            low_price: self.low_price.map(str::to_owned),
+            high_price: self.high_price.map(str::to_owned),
+            open_price: self.open_price.map(str::to_owned),
+        }
+    }
+}
+
+impl<S> TickerState<S> {
+    /// Build from the raw elements of a ticker channel message.
+    pub(crate) fn from_parts<'de, E>(parts: &[&'de RawValue]) -> Result<TickerState<S>, E>
     where
-        D: Deserializer<'de>,
+        S: Deserialize<'de>,
+        E: de::Error,
     {
-        // Delegate the heavy lifting to the internal representation
-        let internal = InternalTickerResponse::deserialize(deserializer)?;
-
-        let data = match &internal.0[1] {
-            TickerResponsePart::Data(d) => Ok(d),
-            _ => Err(de::Error::custom("Second component must be ticker data")),
+        let (channel_id, data, pair) = match parts {
+            [channel_id, data, _, pair] => Ok((channel_id, data, pair)),
+            _ => Err(de::Error::invalid_length(parts.len(), &"4 ticker elements")),
         }?;
-
+        let data: TickerResponseData<S> = parse_part(data, "ticker data")?;
         Ok(TickerState {
-            channel_id: match internal.0[0] {
-                TickerResponsePart::UInt(n) => Ok(n),
-                _ => Err(de::Error::custom("First component must be channel id")),
-            }?,
-            pair: match &internal.0[3] {
-                TickerResponsePart::Str(s) => Ok(s.clone()),
-                _ => Err(de::Error::custom("Last component must be the pair")),
-            }?,
-            ask: BidAskData::try_from(&data.ask)
-                .map_err(|e| de::Error::custom(format!("{}", e)))?,
-            bid: BidAskData::try_from(&data.bid)
-                .map_err(|e| de::Error::custom(format!("{}", e)))?,
-            close: ValueMarker::from(&data.close),
-            volume: ValueMarker::from(&data.volume),
-            volume_weighted_avg_price: ValueMarker::from(&data.volume_weighted_avg_price),
-            trade_count: ValueMarker::from(&data.trade_count),
-            low_price: ValueMarker::from(&data.low_price),
-            high_price: ValueMarker::from(&data.high_price),
-            open_price: ValueMarker::from(&data.open_price),
+            channel_id: parse_part(channel_id, "channel id")?,
+            pair: parse_part(pair, "pair")?,
+            ask: data.ask.into(),
+            bid: data.bid.into(),
+            close: data.close.into(),
+            volume: data.volume.into(),
+            volume_weighted_avg_price: data.volume_weighted_avg_price.into(),
+            trade_count: data.trade_count.into(),
+            low_price: data.low_price.into(),
+            high_price: data.high_price.into(),
+            open_price: data.open_price.into(),
         })
     }
 }
 
-// Internal type used for deserializing the ticker
-// update which is an array of different types.
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-struct InternalTickerResponse([TickerResponsePart; 4]);
+impl<'de, S> Deserialize<'de> for TickerState<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = Parts::deserialize(deserializer)?;
+        match channel_name(&parts)? {
+            "ticker" => TickerState::from_parts(&parts),
+            name => Err(de::Error::invalid_value(
+                de::Unexpected::Str(name),
+                &"ticker channel",
+            )),
+        }
+    }
+}
+
+impl<S: Serialize> Serialize for Wire<'_, TickerState<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let ticker = self.0;
+        let data = TickerResponseData {
+            ask: (
+                &ticker.ask.price,
+                ticker.ask.whole_lot_volume,
+                &ticker.ask.lot_volume,
+            ),
+            bid: (
+                &ticker.bid.price,
+                ticker.bid.whole_lot_volume,
+                &ticker.bid.lot_volume,
+            ),
+            close: both(&ticker.close),
+            volume: both(&ticker.volume),
+            volume_weighted_avg_price: both(&ticker.volume_weighted_avg_price),
+            trade_count: [ticker.trade_count.today, ticker.trade_count.last_24h],
+            low_price: both(&ticker.low_price),
+            high_price: both(&ticker.high_price),
+            open_price: both(&ticker.open_price),
+        };
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&ticker.channel_id)?;
+        tuple.serialize_element(&data)?;
+        tuple.serialize_element("ticker")?;
+        tuple.serialize_element(&ticker.pair)?;
+        tuple.end()
+    }
+}
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-#[serde(untagged)]
-enum TickerResponsePart {
-    UInt(u32),
-    Data(TickerResponseData),
-    Str(String),
+fn both<T>(marker: &ValueMarker<T>) -> [&T; 2] {
+    [&marker.today, &marker.last_24h]
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
-struct TickerResponseData {
+// Internal type used for the data element of the ticker
+// update, the keys are in the order Kraken sends them.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+struct TickerResponseData<S> {
     #[serde(rename = "a")]
-    ask: [IntOrDecimal; 3],
+    ask: (S, u64, S),
     #[serde(rename = "b")]
-    bid: [IntOrDecimal; 3],
+    bid: (S, u64, S),
     #[serde(rename = "c")]
-    close: [String; 2],
+    close: [S; 2],
     #[serde(rename = "v")]
-    volume: [String; 2],
+    volume: [S; 2],
     #[serde(rename = "p")]
-    volume_weighted_avg_price: [String; 2],
+    volume_weighted_avg_price: [S; 2],
     #[serde(rename = "t")]
     trade_count: [u32; 2],
     #[serde(rename = "l")]
-    low_price: [String; 2],
+    low_price: [S; 2],
     #[serde(rename = "h")]
-    high_price: [String; 2],
+    high_price: [S; 2],
     #[serde(rename = "o")]
-    open_price: [String; 2],
+    open_price: [S; 2],
 }
 
 #[cfg(test)]
@@ -156,7 +227,7 @@ mod test {
     use super::*;
     use anyhow::Result;
 
-    const VALID_TICKER_RESPONSE: &'static str = r#"[
+    const VALID_TICKER_RESPONSE: &str = r#"[
       0,
       {
         "a": ["5525.40000", 1,  "1.000"],
@@ -174,6 +245,7 @@ mod test {
     ]"#;
 
     #[test]
+    #[allow(clippy::useless_format)]
     fn external_success_deserialization() -> Result<()> {
         assert_eq!(
             TickerState {
@@ -224,33 +296,33 @@ mod test {
     }
 
     #[test]
-    fn internal_success_deserialization() -> Result<()> {
+    fn internal_data_deserialization() -> Result<()> {
+        let parts = serde_json::from_str::<Vec<&RawValue>>(VALID_TICKER_RESPONSE)?;
+        assert_eq!(
+            TickerResponseData {
+                ask: ("5525.40000", 1, "1.000"),
+                bid: ("5525.10000", 1, "1.000"),
+                close: ["5525.10000", "0.00398963"],
+                high_price: ["5783.00000", "5783.00000"],
+                low_price: ["5505.00000", "5505.00000"],
+                open_price: ["5760.70000", "5763.40000"],
+                volume_weighted_avg_price: ["5631.44067", "5653.78939"],
+                trade_count: [11493, 16267],
+                volume: ["2634.11501494", "3591.17907851"]
+            },
+            serde_json::from_str::<TickerResponseData<&str>>(parts[1].get())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let borrowed = serde_json::from_str::<TickerState<&str>>(VALID_TICKER_RESPONSE)?;
+        assert_eq!("XBT/USD", borrowed.pair);
+        assert_eq!("5525.40000", borrowed.ask.price);
         assert_eq!(
-            InternalTickerResponse([
-                TickerResponsePart::UInt(0),
-                TickerResponsePart::Data(TickerResponseData {
-                    ask: [
-                        IntOrDecimal::Dec(format!("5525.40000")),
-                        IntOrDecimal::Int(1),
-                        IntOrDecimal::Dec(format!("1.000"))
-                    ],
-                    bid: [
-                        IntOrDecimal::Dec(format!("5525.10000")),
-                        IntOrDecimal::Int(1),
-                        IntOrDecimal::Dec(format!("1.000"))
-                    ],
-                    close: [format!("5525.10000"), format!("0.00398963"),],
-                    high_price: [format!("5783.00000"), format!("5783.00000"),],
-                    low_price: [format!("5505.00000"), format!("5505.00000"),],
-                    open_price: [format!("5760.70000"), format!("5763.40000"),],
-                    volume_weighted_avg_price: [format!("5631.44067"), format!("5653.78939"),],
-                    trade_count: [11493, 16267],
-                    volume: [format!("2634.11501494"), format!("3591.17907851"),]
-                }),
-                TickerResponsePart::Str(format!("ticker")),
-                TickerResponsePart::Str(format!("XBT/USD")),
-            ]),
-            serde_json::from_str::<InternalTickerResponse>(VALID_TICKER_RESPONSE)?
+            serde_json::from_str::<TickerState>(VALID_TICKER_RESPONSE)?,
+            borrowed.into_owned()
         );
         Ok(())
     }
@@ -0,0 +1,204 @@
+use crate::resp::{channel_name, parse_part, Parts, Wire};
+use crate::time::Timestamp;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+/// One or more trades on a pair, Kraken batches trades
+/// which happen close together into a single message.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Trades"))]
+pub struct Trades<S = String> {
+    #[serde(rename = "channelId")]
+    pub channel_id: u32,
+    pub pair: S,
+    pub trades: Vec<Trade<S>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema), schemars(rename = "Trade"))]
+pub struct Trade<S = String> {
+    pub price: S,
+    pub volume: S,
+    pub time: Timestamp,
+    pub side: Side,
+    #[serde(rename = "orderType")]
+    pub order_type: OrderType,
+    pub misc: S,
+}
+
+/// Side of the taker in a trade.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub enum Side {
+    #[serde(rename = "b")]
+    Buy,
+    #[serde(rename = "s")]
+    Sell,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub enum OrderType {
+    #[serde(rename = "m")]
+    Market,
+    #[serde(rename = "l")]
+    Limit,
+}
+
+impl Trade<&str> {
+    pub fn into_owned(self) -> Trade {
+        Trade {
+            price: self.price.to_owned(),
+            volume: self.volume.to_owned(),
+            time: self.time,
+            side: self.side,
+            order_type: self.order_type,
+            misc: self.misc.to_owned(),
+        }
+    }
+}
+
+impl Trades<&str> {
+    /// Copy the borrowed fields into owned trades.
+    pub fn into_owned(self) -> Trades {
+        Trades {
+            channel_id: self.channel_id,
+            pair: self.pair.to_owned(),
+            trades: self.trades.into_iter().map(Trade::into_owned).collect(),
+        }
+    }
+}
+
+impl<S> Trades<S> {
+    /// Build from the raw elements of a trade channel message.
+    pub(crate) fn from_parts<'de, E>(parts: &[&'de RawValue]) -> Result<Trades<S>, E>
+    where
+        S: Deserialize<'de>,
+        E: de::Error,
+    {
+        let (channel_id, data, pair) = match parts {
+            [channel_id, data, _, pair] => Ok((channel_id, data, pair)),
+            _ => Err(de::Error::invalid_length(parts.len(), &"4 trade elements")),
+        }?;
+        let data: Vec<TradeResponseData<S>> = parse_part(data, "trade data")?;
+        Ok(Trades {
+            channel_id: parse_part(channel_id, "channel id")?,
+            pair: parse_part(pair, "pair")?,
+            trades: data
+                .into_iter()
+                .map(|(price, volume, time, side, order_type, misc)| Trade {
+                    price,
+                    volume,
+                    time,
+                    side,
+                    order_type,
+                    misc,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl<'de, S> Deserialize<'de> for Trades<S>
+where
+    S: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let parts = Parts::deserialize(deserializer)?;
+        match channel_name(&parts)? {
+            "trade" => Trades::from_parts(&parts),
+            name => Err(de::Error::invalid_value(
+                de::Unexpected::Str(name),
+                &"trade channel",
+            )),
+        }
+    }
+}
+
+impl<S: Serialize> Serialize for Wire<'_, Trades<S>> {
+    fn serialize<Z>(&self, serializer: Z) -> Result<Z::Ok, Z::Error>
+    where
+        Z: Serializer,
+    {
+        let trades = self.0;
+        let data: Vec<TradeResponseData<&S>> = trades
+            .trades
+            .iter()
+            .map(|t| (&t.price, &t.volume, t.time, t.side, t.order_type, &t.misc))
+            .collect();
+        let mut tuple = serializer.serialize_tuple(4)?;
+        tuple.serialize_element(&trades.channel_id)?;
+        tuple.serialize_element(&data)?;
+        tuple.serialize_element("trade")?;
+        tuple.serialize_element(&trades.pair)?;
+        tuple.end()
+    }
+}
+
+// Internal type used for each trade in the data
+// element of the update.
+type TradeResponseData<S> = (S, S, Timestamp, Side, OrderType, S);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    const VALID_TRADE_RESPONSE: &str = r#"[
+      0,
+      [
+        ["5541.20000", "0.15850568", "1534614057.321597", "s", "l", ""],
+        ["6060.00000", "0.02455000", "1534614057.324998", "b", "l", ""]
+      ],
+      "trade",
+      "XBT/USD"
+    ]"#;
+
+    #[test]
+    fn external_success_deserialization() -> Result<()> {
+        assert_eq!(
+            Trades {
+                channel_id: 0,
+                pair: "XBT/USD".to_string(),
+                trades: vec![
+                    Trade {
+                        price: "5541.20000".to_string(),
+                        volume: "0.15850568".to_string(),
+                        time: Timestamp::from_micros(1534614057321597),
+                        side: Side::Sell,
+                        order_type: OrderType::Limit,
+                        misc: "".to_string()
+                    },
+                    Trade {
+                        price: "6060.00000".to_string(),
+                        volume: "0.02455000".to_string(),
+                        time: Timestamp::from_micros(1534614057324998),
+                        side: Side::Buy,
+                        order_type: OrderType::Limit,
+                        misc: "".to_string()
+                    }
+                ]
+            },
+            serde_json::from_str::<Trades>(VALID_TRADE_RESPONSE)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_deserialization() -> Result<()> {
+        let borrowed = serde_json::from_str::<Trades<&str>>(VALID_TRADE_RESPONSE)?;
+        assert_eq!("5541.20000", borrowed.trades[0].price);
+        assert_eq!(
+            serde_json::from_str::<Trades>(VALID_TRADE_RESPONSE)?,
+            borrowed.into_owned()
+        );
+        Ok(())
+    }
+}
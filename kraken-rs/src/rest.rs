@@ -0,0 +1,165 @@
+use crate::candle::Candle;
+use crate::req::OhlcInterval;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const REST_ENDPOINT: &str = "https://api.kraken.com";
+
+/// Client for the public Kraken REST api, this only covers
+/// what the websocket api has no equivalent for.
+#[derive(Debug, Clone)]
+pub struct KrakenRest {
+    base_url: String,
+    agent: ureq::Agent,
+}
+
+impl Default for KrakenRest {
+    fn default() -> Self {
+        KrakenRest::with_base_url(REST_ENDPOINT)
+    }
+}
+
+impl KrakenRest {
+    pub fn new() -> KrakenRest {
+        Default::default()
+    }
+
+    pub fn with_base_url(base_url: &str) -> KrakenRest {
+        KrakenRest {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            agent: ureq::agent(),
+        }
+    }
+
+    /// Fetch candles for a pair, given in the websocket form
+    /// e.g. "XBT/USD", starting after the given time. Kraken
+    /// only keeps the most recent 720 candles for each interval
+    /// and the last of these is usually still in progress.
+    pub fn ohlc(
+        &self,
+        pair: &str,
+        interval: OhlcInterval,
+        since: Option<Timestamp>,
+    ) -> Result<Vec<Candle>> {
+        let mut req = self
+            .agent
+            .get(&format!("{}/0/public/OHLC", self.base_url))
+            .query("pair", &pair.replace('/', ""))
+            .query("interval", &interval.minutes().to_string());
+        if let Some(since) = since {
+            req = req.query("since", &since.as_secs().to_string());
+        }
+        parse_ohlc(&req.call()?.into_string()?, pair, interval)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RestResponse<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+impl<T> RestResponse<T> {
+    fn into_result(self) -> Result<T> {
+        match (self.error.is_empty(), self.result) {
+            (true, Some(result)) => Ok(result),
+            (_, _) => Err(anyhow!("Kraken rest error: {}", self.error.join(", "))),
+        }
+    }
+}
+
+// Each row is the start time in whole seconds followed by
+// open, high, low, close, vwap, volume and count.
+type RestCandle = (u64, String, String, String, String, String, String, u32);
+
+#[derive(Debug, Deserialize)]
+struct OhlcResult {
+    #[serde(flatten)]
+    pairs: HashMap<String, serde_json::Value>,
+}
+
+fn parse_ohlc(body: &str, pair: &str, interval: OhlcInterval) -> Result<Vec<Candle>> {
+    let result = serde_json::from_str::<RestResponse<OhlcResult>>(body)?.into_result()?;
+    // The rows are keyed by Kraken's internal name for the pair
+    // which we don't know, the only other key is "last".
+    let rows = result
+        .pairs
+        .into_iter()
+        .find(|(k, _)| k != "last")
+        .map(|(_, rows)| serde_json::from_value::<Vec<RestCandle>>(rows))
+        .ok_or_else(|| anyhow!("No ohlc data for {}", pair))??;
+    let dec = |s: &str| Decimal::from_str(s).map_err(Error::from);
+    rows.into_iter()
+        .map(|(time, open, high, low, close, vwap, volume, count)| {
+            Ok(Candle {
+                pair: pair.to_owned(),
+                start: Timestamp::from_secs(time),
+                period: interval.duration(),
+                open: dec(&open)?,
+                high: dec(&high)?,
+                low: dec(&low)?,
+                close: dec(&close)?,
+                vwap: dec(&vwap)?,
+                volume: dec(&volume)?,
+                count,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_ohlc_success() -> Result<()> {
+        let candles = parse_ohlc(
+            r#"{
+              "error": [],
+              "result": {
+                "XXBTZUSD": [
+                  [1688671200, "30306.1", "30306.2", "30305.7", "30305.7", "30306.1", "3.39243896", 23],
+                  [1688671260, "30305.7", "30305.7", "30305.7", "30305.7", "0.0", "0.00000000", 0]
+                ],
+                "last": 1688672160
+              }
+            }"#,
+            "XBT/USD",
+            OhlcInterval::Mins1,
+        )?;
+        assert_eq!(
+            Candle {
+                pair: "XBT/USD".to_string(),
+                start: Timestamp::from_secs(1688671200),
+                period: Duration::from_secs(60),
+                open: Decimal::from_str("30306.1")?,
+                high: Decimal::from_str("30306.2")?,
+                low: Decimal::from_str("30305.7")?,
+                close: Decimal::from_str("30305.7")?,
+                vwap: Decimal::from_str("30306.1")?,
+                volume: Decimal::from_str("3.39243896")?,
+                count: 23
+            },
+            candles[0]
+        );
+        assert_eq!(2, candles.len());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ohlc_error() {
+        let err = parse_ohlc(
+            r#"{"error": ["EQuery:Unknown asset pair"]}"#,
+            "XBT/XYZ",
+            OhlcInterval::Mins1,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("EQuery:Unknown asset pair"), "{}", err);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::candle::{CandleAggregator, CandleClosed};
+use crate::resp::ohlc::Ohlc;
+use crate::resp::ticker::TickerState;
+use crate::resp::Resp;
+use crate::sink::{Sink, TickerThrottle, CANDLE_GRACE};
+use crate::time::Timestamp;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CANDLE_HEADER: &[&str] = &[
+    "pair", "interval", "start", "end", "open", "high", "low", "close", "vwap", "volume", "count",
+];
+const TICKER_HEADER: &[&str] = &[
+    "time",
+    "pair",
+    "ask",
+    "ask_volume",
+    "bid",
+    "bid_volume",
+    "last",
+    "last_volume",
+    "volume_today",
+    "volume_24h",
+    "vwap_today",
+    "vwap_24h",
+    "trades_today",
+    "trades_24h",
+    "low_today",
+    "low_24h",
+    "high_today",
+    "high_24h",
+    "open_today",
+    "open_24h",
+];
+
+/// Writes closed candles and periodic ticker snapshots to CSV
+/// files in a directory, one file per day for each pair and
+/// candle interval e.g. "ohlc-5-XBT-USD-2018-11-12.csv" and
+/// "ticker-XBT-USD-2018-11-12.csv". Days are UTC, candles are
+/// filed by their start time and tickers by when they were
+/// received. Files start with a header and are appended to if
+/// they already exist. Times are seconds since the epoch e.g.
+/// "1542057314.748456".
+///
+/// Candles are only written once they can no longer change,
+/// see [`CandleAggregator`], so the one in progress is lost if
+/// the sink is dropped.
+pub struct CsvSink {
+    dir: PathBuf,
+    candles: CandleAggregator,
+    tickers: TickerThrottle,
+    // Open files by name without the date.
+    files: HashMap<String, CsvFile>,
+}
+
+struct CsvFile {
+    date: String,
+    writer: BufWriter<File>,
+}
+
+impl CsvSink {
+    /// Write into the given directory, which is created if
+    /// needed. Tickers are written at most once a minute for
+    /// each pair by default.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<CsvSink> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(CsvSink {
+            dir,
+            candles: CandleAggregator::new(),
+            tickers: TickerThrottle::new(Duration::from_secs(60)),
+            files: HashMap::new(),
+        })
+    }
+
+    /// The least time between the tickers written for a pair.
+    pub fn ticker_interval(mut self, interval: Duration) -> CsvSink {
+        self.tickers = TickerThrottle::new(interval);
+        self
+    }
+
+    fn write_candle(&mut self, closed: CandleClosed) -> Result<()> {
+        let ohlc: Ohlc = closed.candle;
+        let start = ohlc.start();
+        let name = format!("ohlc-{}-{}", ohlc.interval.minutes(), file_pair(&ohlc.pair));
+        let row = [
+            ohlc.pair.clone(),
+            ohlc.interval.minutes().to_string(),
+            start.to_string(),
+            ohlc.etime.to_string(),
+            ohlc.open,
+            ohlc.high,
+            ohlc.low,
+            ohlc.close,
+            ohlc.vwap,
+            ohlc.volume,
+            ohlc.count.to_string(),
+        ];
+        self.write_row(name, start, CANDLE_HEADER, &row)
+    }
+
+    fn write_ticker(&mut self, ticker: &TickerState, received: Timestamp) -> Result<()> {
+        if !self.tickers.due(&ticker.pair, received) {
+            return Ok(());
+        }
+        let row = [
+            received.to_string(),
+            ticker.pair.clone(),
+            ticker.ask.price.clone(),
+            ticker.ask.lot_volume.clone(),
+            ticker.bid.price.clone(),
+            ticker.bid.lot_volume.clone(),
+            ticker.close.today.clone(),
+            ticker.close.last_24h.clone(),
+            ticker.volume.today.clone(),
+            ticker.volume.last_24h.clone(),
+            ticker.volume_weighted_avg_price.today.clone(),
+            ticker.volume_weighted_avg_price.last_24h.clone(),
+            ticker.trade_count.today.to_string(),
+            ticker.trade_count.last_24h.to_string(),
+            ticker.low_price.today.clone(),
+            ticker.low_price.last_24h.clone(),
+            ticker.high_price.today.clone(),
+            ticker.high_price.last_24h.clone(),
+            ticker.open_price.today.clone(),
+            ticker.open_price.last_24h.clone(),
+        ];
+        let name = format!("ticker-{}", file_pair(&ticker.pair));
+        self.write_row(name, received, TICKER_HEADER, &row)
+    }
+
+    fn write_row(
+        &mut self,
+        name: String,
+        time: Timestamp,
+        header: &[&str],
+        row: &[String],
+    ) -> Result<()> {
+        let date = time.utc_date();
+        if self.files.get(&name).is_none_or(|file| file.date != date) {
+            let path = self.dir.join(format!("{}-{}.csv", name, date));
+            let file = CsvFile {
+                writer: open(&path, header)?,
+                date,
+            };
+            // Replacing the previous day's file flushes it.
+            self.files.insert(name.clone(), file);
+        }
+        let file = self.files.get_mut(&name).unwrap();
+        write_line(&mut file.writer, row.iter().map(String::as_str))
+    }
+}
+
+impl Sink for CsvSink {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        match resp {
+            Resp::Ohlc(ohlc) => {
+                if let Some(closed) = self.candles.update(ohlc.clone()) {
+                    self.write_candle(closed)?;
+                }
+            }
+            Resp::Ticker(ticker) => self.write_ticker(ticker, received)?,
+            _ => {}
+        }
+        for closed in self.candles.close_expired(received - CANDLE_GRACE) {
+            self.write_candle(closed)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for file in self.files.values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Pairs without the slash, which can't be in a file name.
+fn file_pair(pair: &str) -> String {
+    pair.replace('/', "-")
+}
+
+fn open(path: &Path, header: &[&str]) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if empty {
+        write_line(&mut writer, header.iter().copied())?;
+    }
+    Ok(writer)
+}
+
+fn write_line<'a, W, I>(writer: &mut W, fields: I) -> Result<()>
+where
+    W: Write,
+    I: Iterator<Item = &'a str>,
+{
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::req::OhlcInterval;
+
+    // 2018-11-13 00:00:00
+    const MIDNIGHT: u64 = 1542067200;
+
+    fn ohlc(etime: u64, close: &str) -> Resp {
+        Resp::Ohlc(Ohlc {
+            channel_id: 42,
+            interval: OhlcInterval::Mins1,
+            pair: "XBT/USD".to_string(),
+            time: Timestamp::from_secs(etime - 30),
+            etime: Timestamp::from_secs(etime),
+            open: "100.0".to_string(),
+            high: "110.0".to_string(),
+            low: "90.0".to_string(),
+            close: close.to_string(),
+            vwap: "100.0".to_string(),
+            volume: "1.0".to_string(),
+            count: 1,
+        })
+    }
+
+    fn ticker() -> Resp {
+        serde_json::from_str(r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#).unwrap()
+    }
+
+    fn read(dir: &Path, name: &str) -> Vec<String> {
+        fs::read_to_string(dir.join(name))
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn closed_candles_rotate_daily() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sink = CsvSink::new(dir.path())?;
+        let received = Timestamp::from_secs(MIDNIGHT - 30);
+        sink.write(&ohlc(MIDNIGHT, "101.0"), received)?;
+        sink.write(&ohlc(MIDNIGHT, "102.0"), received)?;
+        sink.write(
+            &ohlc(MIDNIGHT + 60, "103.0"),
+            received + Duration::from_secs(60),
+        )?;
+        // Closed by time passing rather than by a later update.
+        sink.write(&Resp::Heartbeat, Timestamp::from_secs(MIDNIGHT + 65))?;
+        sink.flush()?;
+
+        assert_eq!(
+            vec![
+                CANDLE_HEADER.join(","),
+                "XBT/USD,1,1542067140.000000,1542067200.000000,100.0,110.0,90.0,102.0,100.0,1.0,1"
+                    .to_string()
+            ],
+            read(dir.path(), "ohlc-1-XBT-USD-2018-11-12.csv")
+        );
+        let next_day = read(dir.path(), "ohlc-1-XBT-USD-2018-11-13.csv");
+        assert_eq!(2, next_day.len());
+        assert!(next_day[1].contains(",103.0,"));
+        Ok(())
+    }
+
+    #[test]
+    fn tickers_are_throttled_and_appended() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let start = Timestamp::from_secs(MIDNIGHT - 600);
+        let mut sink = CsvSink::new(dir.path())?.ticker_interval(Duration::from_secs(60));
+        for secs in [0, 30, 60, 90].iter() {
+            sink.write(&ticker(), start + Duration::from_secs(*secs))?;
+        }
+        drop(sink);
+        // A new sink appends without repeating the header.
+        let mut sink = CsvSink::new(dir.path())?;
+        sink.write(&ticker(), start + Duration::from_secs(300))?;
+        sink.flush()?;
+
+        let lines = read(dir.path(), "ticker-XBT-USD-2018-11-12.csv");
+        assert_eq!(TICKER_HEADER.join(","), lines[0]);
+        assert_eq!(4, lines.len());
+        assert_eq!(
+            "1542066600.000000,XBT/USD,5525.40000,1.000,5525.10000,1.000,5525.10000,0.00398963,2634.11501494,3591.17907851,5631.44067,5653.78939,11493,16267,5505.00000,5505.00000,5783.00000,5783.00000,5760.70000,5763.40000",
+            lines[1]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn quotes_fields() -> Result<()> {
+        let mut line = vec![];
+        write_line(&mut line, ["a", "b,c", "d\"e"].iter().copied())?;
+        assert_eq!("a,\"b,c\",\"d\"\"e\"\n", String::from_utf8(line)?);
+        Ok(())
+    }
+}
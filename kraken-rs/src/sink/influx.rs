@@ -0,0 +1,490 @@
+use crate::resp::ohlc::Ohlc;
+use crate::resp::spread::Spread;
+use crate::resp::ticker::TickerState;
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Lines sent in one UDP datagram are kept under this size so
+/// that they aren't fragmented.
+const MAX_DATAGRAM: usize = 1400;
+
+/// How long to wait for an HTTP write endpoint.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Buffers of lines waiting to be sent to an HTTP write
+/// endpoint, further buffers are dropped.
+const HTTP_QUEUE: usize = 16;
+
+/// Writes tickers, candles and spreads as InfluxDB line
+/// protocol to a file, a UDP listener or an HTTP write
+/// endpoint. The measurements are
+///
+/// - `ticker`, tagged with the pair, with the columns of the
+///   CSV ticker files as fields, at the time received
+/// - `ohlc`, tagged with the pair and interval in minutes, at
+///   the candle's start time so each update to a candle
+///   replaces the last
+/// - `spread`, tagged with the pair, with the best bid and ask,
+///   their volumes and the difference between them, at
+///   Kraken's time for the spread
+///
+/// Prices and volumes are float fields written with the digits
+/// Kraken sent, counts are integer fields. Lines are buffered
+/// and sent when the buffer is full or the sink is flushed.
+/// Sending to UDP or HTTP is best effort, when it fails the
+/// lines are logged as lost and dropped rather than retried.
+/// HTTP requests are made on a thread of their own so a slow
+/// endpoint doesn't hold up the caller, the lines are dropped
+/// instead if too many are waiting to be sent.
+pub struct InfluxSink {
+    target: Target,
+    buffer: String,
+    buffer_bytes: usize,
+    // Shared with the HTTP thread.
+    failed_sends: Arc<AtomicU64>,
+}
+
+enum Target {
+    File(BufWriter<File>),
+    Udp(UdpSocket, SocketAddr),
+    Http(SyncSender<String>),
+}
+
+impl InfluxSink {
+    /// Append lines to a file, created if needed.
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<InfluxSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(InfluxSink::new(Target::File(BufWriter::new(file))))
+    }
+
+    /// Send lines to a UDP listener, such as InfluxDB's UDP
+    /// service or Telegraf's socket listener.
+    pub fn udp<A: ToSocketAddrs>(addr: A) -> Result<InfluxSink> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("No address to send to"))?;
+        let bind: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(bind)?;
+        Ok(InfluxSink::new(Target::Udp(socket, addr)))
+    }
+
+    /// POST lines to a write endpoint with any database, bucket
+    /// and precision in the url, e.g.
+    /// "http://localhost:8086/write?db=kraken&precision=ns".
+    /// InfluxDB 2 needs an API token, sent as
+    /// "Authorization: Token <token>".
+    pub fn http(url: &str, token: Option<&str>) -> Result<InfluxSink> {
+        let (sender, receiver) = sync_channel(HTTP_QUEUE);
+        let sink = InfluxSink::new(Target::Http(sender));
+        let url = url.to_owned();
+        let authorization = token.map(|token| format!("Token {}", token));
+        let failed_sends = sink.failed_sends.clone();
+        thread::Builder::new()
+            .name("influx-http".to_string())
+            .spawn(move || post_lines(&url, authorization.as_deref(), receiver, &failed_sends))?;
+        Ok(sink)
+    }
+
+    fn new(target: Target) -> InfluxSink {
+        InfluxSink {
+            target,
+            buffer: String::new(),
+            buffer_bytes: 64 * 1024,
+            failed_sends: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The size in bytes the buffered lines reach before they
+    /// are sent without waiting for a flush, 64KiB by default.
+    pub fn buffer_bytes(mut self, bytes: usize) -> InfluxSink {
+        self.buffer_bytes = bytes;
+        self
+    }
+
+    /// Sends to UDP or HTTP which failed, losing their lines.
+    /// HTTP sends are counted once they have been tried.
+    pub fn failed_sends(&self) -> u64 {
+        self.failed_sends.load(Ordering::Relaxed)
+    }
+
+    fn send(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let sent = match &mut self.target {
+            Target::File(writer) => {
+                writer.write_all(self.buffer.as_bytes())?;
+                writer.flush()?;
+                Ok(())
+            }
+            Target::Udp(socket, addr) => datagrams(&self.buffer)
+                .into_iter()
+                .try_for_each(|datagram| socket.send_to(datagram.as_bytes(), *addr).map(|_| ()))
+                .map_err(Error::from),
+            Target::Http(queue) => match queue.try_send(self.buffer.clone()) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(anyhow!("Too many sends waiting")),
+                Err(TrySendError::Disconnected(_)) => Err(anyhow!("HTTP thread stopped")),
+            },
+        };
+        if let Err(e) = sent {
+            dropped(&e, &self.buffer, &self.failed_sends);
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Run on the HTTP thread until the sink is dropped.
+fn post_lines(
+    url: &str,
+    authorization: Option<&str>,
+    queue: Receiver<String>,
+    failed_sends: &AtomicU64,
+) {
+    for lines in queue {
+        let mut request = ureq::post(url)
+            .timeout(HTTP_TIMEOUT)
+            .set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(authorization) = authorization {
+            request = request.set("Authorization", authorization);
+        }
+        if let Err(e) = request.send_string(&lines) {
+            dropped(&Error::from(e), &lines, failed_sends);
+        }
+    }
+}
+
+fn dropped(error: &Error, lines: &str, failed_sends: &AtomicU64) {
+    failed_sends.fetch_add(1, Ordering::Relaxed);
+    warn!(
+        error = %error,
+        lines = lines.lines().count(),
+        "influx send failed, lines dropped"
+    );
+}
+
+impl Sink for InfluxSink {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        match resp {
+            Resp::Ticker(ticker) => write_ticker(&mut self.buffer, ticker, received),
+            Resp::Ohlc(ohlc) => write_ohlc(&mut self.buffer, ohlc),
+            Resp::Spread(spread) => write_spread(&mut self.buffer, spread)?,
+            _ => {}
+        }
+        if self.buffer.len() >= self.buffer_bytes {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.send()
+    }
+}
+
+enum Field<'a> {
+    Float(&'a str),
+    Int(u64),
+}
+
+/// Add a line, the tags are sorted by key as InfluxDB prefers.
+fn write_line(
+    out: &mut String,
+    measurement: &str,
+    tags: &[(&str, &str)],
+    fields: &[(&str, Field)],
+    time: Timestamp,
+) {
+    out.push_str(measurement);
+    for (key, value) in tags {
+        out.push(',');
+        escape(out, key);
+        out.push('=');
+        escape(out, value);
+    }
+    for (i, (key, value)) in fields.iter().enumerate() {
+        out.push(if i == 0 { ' ' } else { ',' });
+        escape(out, key);
+        out.push('=');
+        match value {
+            Field::Float(s) => out.push_str(s),
+            Field::Int(n) => {
+                let _ = write!(out, "{}i", n);
+            }
+        }
+    }
+    let _ = writeln!(out, " {}", time.as_micros() * 1000);
+}
+
+// Tag keys, values and field keys escape commas, equals signs
+// and spaces.
+fn escape(out: &mut String, s: &str) {
+    for c in s.chars() {
+        if let ',' | '=' | ' ' = c {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+fn write_ticker(out: &mut String, ticker: &TickerState, received: Timestamp) {
+    use Field::{Float, Int};
+    let fields = [
+        ("ask", Float(&ticker.ask.price)),
+        ("ask_volume", Float(&ticker.ask.lot_volume)),
+        ("bid", Float(&ticker.bid.price)),
+        ("bid_volume", Float(&ticker.bid.lot_volume)),
+        ("last", Float(&ticker.close.today)),
+        ("last_volume", Float(&ticker.close.last_24h)),
+        ("volume_today", Float(&ticker.volume.today)),
+        ("volume_24h", Float(&ticker.volume.last_24h)),
+        ("vwap_today", Float(&ticker.volume_weighted_avg_price.today)),
+        (
+            "vwap_24h",
+            Float(&ticker.volume_weighted_avg_price.last_24h),
+        ),
+        ("trades_today", Int(ticker.trade_count.today.into())),
+        ("trades_24h", Int(ticker.trade_count.last_24h.into())),
+        ("low_today", Float(&ticker.low_price.today)),
+        ("low_24h", Float(&ticker.low_price.last_24h)),
+        ("high_today", Float(&ticker.high_price.today)),
+        ("high_24h", Float(&ticker.high_price.last_24h)),
+        ("open_today", Float(&ticker.open_price.today)),
+        ("open_24h", Float(&ticker.open_price.last_24h)),
+    ];
+    write_line(out, "ticker", &[("pair", &ticker.pair)], &fields, received);
+}
+
+fn write_ohlc(out: &mut String, ohlc: &Ohlc) {
+    use Field::{Float, Int};
+    let interval = ohlc.interval.minutes().to_string();
+    let fields = [
+        ("open", Float(&ohlc.open)),
+        ("high", Float(&ohlc.high)),
+        ("low", Float(&ohlc.low)),
+        ("close", Float(&ohlc.close)),
+        ("vwap", Float(&ohlc.vwap)),
+        ("volume", Float(&ohlc.volume)),
+        ("count", Int(ohlc.count.into())),
+    ];
+    let tags = [("interval", interval.as_str()), ("pair", &ohlc.pair)];
+    write_line(out, "ohlc", &tags, &fields, ohlc.start());
+}
+
+fn write_spread(out: &mut String, spread: &Spread) -> Result<()> {
+    use Field::Float;
+    let dec = |s: &str| Decimal::from_str(s).map_err(|e| anyhow!("Invalid price {}: {}", s, e));
+    let difference = (dec(&spread.ask)? - dec(&spread.bid)?).to_string();
+    let fields = [
+        ("ask", Float(&spread.ask)),
+        ("ask_volume", Float(&spread.ask_volume)),
+        ("bid", Float(&spread.bid)),
+        ("bid_volume", Float(&spread.bid_volume)),
+        ("spread", Float(&difference)),
+    ];
+    write_line(
+        out,
+        "spread",
+        &[("pair", &spread.pair)],
+        &fields,
+        spread.time,
+    );
+    Ok(())
+}
+
+/// Whole lines grouped into datagrams of at most
+/// [`MAX_DATAGRAM`] bytes, unless a line is longer.
+fn datagrams(lines: &str) -> Vec<&str> {
+    let mut datagrams = vec![];
+    let mut start = 0;
+    let mut end = 0;
+    for line in lines.split_inclusive('\n') {
+        if end > start && end + line.len() - start > MAX_DATAGRAM {
+            datagrams.push(&lines[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    if end > start {
+        datagrams.push(&lines[start..end]);
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::req::OhlcInterval;
+    use std::fs;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    const TICKER: &str = r#"[0,{"a":["5525.40000",1,"1.000"],"b":["5525.10000",1,"1.000"],"c":["5525.10000","0.00398963"],"v":["2634.11501494","3591.17907851"],"p":["5631.44067","5653.78939"],"t":[11493,16267],"l":["5505.00000","5505.00000"],"h":["5783.00000","5783.00000"],"o":["5760.70000","5763.40000"]},"ticker","XBT/USD"]"#;
+    const SPREAD: &str = r#"[0,["5698.40000","5700.00000","1542057299.545897","1.01234567","0.98765432"],"spread","XBT/USD"]"#;
+
+    fn ohlc() -> Resp {
+        Resp::Ohlc(Ohlc {
+            channel_id: 42,
+            interval: OhlcInterval::Mins5,
+            pair: "XBT/USD".to_string(),
+            time: Timestamp::from_secs(1542057314),
+            etime: Timestamp::from_secs(1542057600),
+            open: "3586.70000".to_string(),
+            high: "3586.70000".to_string(),
+            low: "3586.60000".to_string(),
+            close: "3586.60000".to_string(),
+            vwap: "3586.68894".to_string(),
+            volume: "0.03373000".to_string(),
+            count: 2,
+        })
+    }
+
+    #[test]
+    fn line_protocol() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kraken.lp");
+        let mut sink = InfluxSink::file(&path)?;
+        let received = Timestamp::from_micros(1542057314748456);
+        sink.write(&serde_json::from_str(TICKER)?, received)?;
+        sink.write(&ohlc(), received)?;
+        sink.write(&serde_json::from_str(SPREAD)?, received)?;
+        sink.write(&Resp::Heartbeat, received)?;
+        assert_eq!("", fs::read_to_string(&path)?);
+        sink.flush()?;
+
+        let contents = fs::read_to_string(&path)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(
+            vec![
+                "ticker,pair=XBT/USD ask=5525.40000,ask_volume=1.000,bid=5525.10000,bid_volume=1.000,last=5525.10000,last_volume=0.00398963,volume_today=2634.11501494,volume_24h=3591.17907851,vwap_today=5631.44067,vwap_24h=5653.78939,trades_today=11493i,trades_24h=16267i,low_today=5505.00000,low_24h=5505.00000,high_today=5783.00000,high_24h=5783.00000,open_today=5760.70000,open_24h=5763.40000 1542057314748456000",
+                "ohlc,interval=5,pair=XBT/USD open=3586.70000,high=3586.70000,low=3586.60000,close=3586.60000,vwap=3586.68894,volume=0.03373000,count=2i 1542057300000000000",
+                "spread,pair=XBT/USD ask=5700.00000,ask_volume=0.98765432,bid=5698.40000,bid_volume=1.01234567,spread=1.60000 1542057299545897000",
+            ],
+            lines
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_tags() {
+        let mut out = String::new();
+        let fields = [("last price", Field::Int(1))];
+        write_line(
+            &mut out,
+            "m",
+            &[("pair", "a,b=c d")],
+            &fields,
+            Timestamp::from_micros(1),
+        );
+        assert_eq!("m,pair=a\\,b\\=c\\ d last\\ price=1i 1000\n", out);
+    }
+
+    #[test]
+    fn udp_datagrams_hold_whole_lines() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut sink = InfluxSink::udp(listener.local_addr()?)?;
+        let received = Timestamp::from_secs(1542057314);
+        for _ in 0..10 {
+            sink.write(&serde_json::from_str(TICKER)?, received)?;
+        }
+        sink.flush()?;
+
+        let mut lines = 0;
+        let mut buf = [0; 65536];
+        while lines < 10 {
+            let n = listener.recv(&mut buf)?;
+            assert!(n <= MAX_DATAGRAM);
+            let datagram = std::str::from_utf8(&buf[..n])?;
+            assert!(datagram.ends_with('\n'));
+            lines += datagram.lines().count();
+        }
+        assert_eq!(10, lines);
+        Ok(())
+    }
+
+    #[test]
+    fn http_posts_lines() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/write?db=kraken", listener.local_addr()?);
+        let server = thread::spawn(move || -> Result<String> {
+            let (mut stream, _) = listener.accept()?;
+            let mut request = vec![];
+            let mut buf = [0; 4096];
+            // Read until the whole body has arrived.
+            while !String::from_utf8_lossy(&request).ends_with("1542057300000000000\n") {
+                let n = stream.read(&mut buf)?;
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")?;
+            Ok(String::from_utf8(request)?)
+        });
+        let mut sink = InfluxSink::http(&url, Some("secret"))?.buffer_bytes(1);
+        sink.write(&ohlc(), Timestamp::now())?;
+
+        let request = server.join().unwrap()?;
+        assert!(request.starts_with("POST /write?db=kraken HTTP/1.1"));
+        assert!(request
+            .to_lowercase()
+            .contains("\r\nauthorization: token secret\r\n"));
+        assert!(request.ends_with("\r\n\r\nohlc,interval=5,pair=XBT/USD open=3586.70000,high=3586.70000,low=3586.60000,close=3586.60000,vwap=3586.68894,volume=0.03373000,count=2i 1542057300000000000\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn drops_lines_when_http_fails() -> Result<()> {
+        // Nothing is listening once the listener is dropped.
+        let url = format!(
+            "http://{}/write?db=kraken",
+            TcpListener::bind("127.0.0.1:0")?.local_addr()?
+        );
+        let mut sink = InfluxSink::http(&url, None)?.buffer_bytes(1);
+        sink.write(&ohlc(), Timestamp::now())?;
+        assert_eq!("", sink.buffer);
+        sink.write(&ohlc(), Timestamp::now())?;
+        sink.flush()?;
+        let until = Instant::now() + Duration::from_secs(10);
+        while sink.failed_sends() < 2 && Instant::now() < until {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(2, sink.failed_sends());
+        Ok(())
+    }
+
+    #[test]
+    fn slow_http_does_not_block() -> Result<()> {
+        // Accepted by the OS but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/write?db=kraken", listener.local_addr()?);
+        let mut sink = InfluxSink::http(&url, None)?.buffer_bytes(1);
+        let started = Instant::now();
+        for _ in 0..HTTP_QUEUE + 2 {
+            sink.write(&ohlc(), Timestamp::now())?;
+        }
+        assert!(started.elapsed() < HTTP_TIMEOUT);
+        // The queue filled up behind the first send.
+        assert!(sink.failed_sends() >= 1);
+        Ok(())
+    }
+}
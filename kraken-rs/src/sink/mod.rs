@@ -0,0 +1,66 @@
+mod csv;
+mod influx;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use self::csv::CsvSink;
+pub use self::influx::InfluxSink;
+#[cfg(feature = "parquet")]
+pub use self::parquet::ParquetSink;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteSink;
+
+use crate::resp::Resp;
+use crate::time::Timestamp;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long after a candle's end time it is written if no
+/// update for the next candle has arrived, to allow for the
+/// difference between Kraken's clock and ours.
+const CANDLE_GRACE: Duration = Duration::from_secs(5);
+
+/// Somewhere responses are written to be kept, such as files
+/// or a database. Each sink picks out the messages it stores
+/// and ignores the rest.
+pub trait Sink {
+    /// Store what the sink keeps from a response received at
+    /// the given time.
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()>;
+
+    /// Write out anything buffered.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// Picks which tickers are kept, at most one for each pair in
+/// an interval.
+struct TickerThrottle {
+    interval: Duration,
+    // When the last ticker kept for each pair was received.
+    last: HashMap<String, Timestamp>,
+}
+
+impl TickerThrottle {
+    fn new(interval: Duration) -> TickerThrottle {
+        TickerThrottle {
+            interval,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether to keep a ticker for the pair received at the
+    /// given time, which is then the last kept.
+    fn due(&mut self, pair: &str, received: Timestamp) -> bool {
+        let due = match self.last.get(pair) {
+            Some(&last) => received.saturating_duration_since(last) >= self.interval,
+            None => true,
+        };
+        if due {
+            self.last.insert(pair.to_owned(), received);
+        }
+        due
+    }
+}
@@ -0,0 +1,588 @@
+use crate::candle::{CandleAggregator, CandleClosed};
+use crate::resp::book::Book;
+use crate::resp::ticker::TickerState;
+use crate::resp::trade::{OrderType, Side, Trades};
+use crate::resp::Resp;
+use crate::sink::{Sink, TickerThrottle, CANDLE_GRACE};
+use crate::time::Timestamp;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{anyhow, Result};
+use arrow_array::builder::{
+    make_builder, ArrayBuilder, BooleanBuilder, Decimal128Builder, StringBuilder,
+    TimestampMicrosecondBuilder, UInt32Builder,
+};
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Decimal places kept in decimal columns, more than Kraken
+/// gives for any price or volume.
+const SCALE: u32 = 10;
+const PRECISION: u8 = 38;
+
+/// Writes trades, closed candles, ticker snapshots and book
+/// updates to Parquet files for long term storage. Prices and
+/// volumes are exact decimal columns with ten decimal places
+/// and times are UTC microsecond timestamps.
+///
+/// Files are partitioned the way DuckDB and Polars expect, by
+/// table then UTC date and pair, e.g.
+/// "trades/date=2018-11-12/pair=XBT-USD/part-1542057314748456-0.parquet"
+/// with the slash in the pair replaced. Trades are dated by
+/// when they happened, candles by their start and tickers and
+/// book updates by when they were received. The tables are
+///
+/// - `trades`: pair, time, price, volume, side, order_type, misc
+/// - `candles`: pair, interval, start, end, open, high, low,
+///   close, vwap, volume, count
+/// - `tickers`: the columns of the CSV ticker files
+/// - `book`: received, pair, depth, snapshot, side, price,
+///   volume, time, republish, checksum, one row per level
+///
+/// Rows are buffered and each partition is written to a new
+/// file once it has a batch of rows, or when flushed, so flush
+/// rarely to avoid many small files. Rows which fail to be
+/// written are kept to be tried again with the next write.
+pub struct ParquetSink {
+    dir: PathBuf,
+    batch_rows: usize,
+    candles: CandleAggregator,
+    tickers: TickerThrottle,
+    batches: HashMap<Partition, Batch>,
+    files_written: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct Partition {
+    table: Table,
+    date: String,
+    pair: String,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Table {
+    Trades,
+    Candles,
+    Tickers,
+    Book,
+}
+
+impl ParquetSink {
+    /// Write into the given directory, which is created if
+    /// needed. Every ticker is kept and partitions are written
+    /// out every 100,000 rows by default.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<ParquetSink> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(ParquetSink {
+            dir,
+            batch_rows: 100_000,
+            candles: CandleAggregator::new(),
+            tickers: TickerThrottle::new(Duration::from_secs(0)),
+            batches: HashMap::new(),
+            files_written: 0,
+        })
+    }
+
+    /// Rows buffered for a partition before it is written.
+    pub fn batch_rows(mut self, rows: usize) -> ParquetSink {
+        self.batch_rows = rows.max(1);
+        self
+    }
+
+    /// The least time between the tickers kept for a pair.
+    pub fn ticker_interval(mut self, interval: Duration) -> ParquetSink {
+        self.tickers = TickerThrottle::new(interval);
+        self
+    }
+
+    fn write_trades(&mut self, trades: &Trades) -> Result<()> {
+        let mut rows = Vec::with_capacity(trades.trades.len());
+        for trade in &trades.trades {
+            let side = match trade.side {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            };
+            let order_type = match trade.order_type {
+                OrderType::Market => "market",
+                OrderType::Limit => "limit",
+            };
+            let row = [
+                Cell::Str(&trades.pair),
+                Cell::Time(trade.time),
+                decimal(&trade.price)?,
+                decimal(&trade.volume)?,
+                Cell::Str(side),
+                Cell::Str(order_type),
+                Cell::Str(&trade.misc),
+            ];
+            rows.push((trade.time, row));
+        }
+        self.push(Table::Trades, &trades.pair, &rows)
+    }
+
+    fn write_candle(&mut self, closed: CandleClosed) -> Result<()> {
+        let ohlc = closed.candle;
+        let row = [
+            Cell::Str(&ohlc.pair),
+            Cell::U32(Some(ohlc.interval.minutes())),
+            Cell::Time(ohlc.start()),
+            Cell::Time(ohlc.etime),
+            decimal(&ohlc.open)?,
+            decimal(&ohlc.high)?,
+            decimal(&ohlc.low)?,
+            decimal(&ohlc.close)?,
+            decimal(&ohlc.vwap)?,
+            decimal(&ohlc.volume)?,
+            Cell::U32(Some(ohlc.count)),
+        ];
+        self.push(Table::Candles, &ohlc.pair, &[(ohlc.start(), row)])
+    }
+
+    fn write_ticker(&mut self, ticker: &TickerState, received: Timestamp) -> Result<()> {
+        if !self.tickers.due(&ticker.pair, received) {
+            return Ok(());
+        }
+        let row = [
+            Cell::Time(received),
+            Cell::Str(&ticker.pair),
+            decimal(&ticker.ask.price)?,
+            decimal(&ticker.ask.lot_volume)?,
+            decimal(&ticker.bid.price)?,
+            decimal(&ticker.bid.lot_volume)?,
+            decimal(&ticker.close.today)?,
+            decimal(&ticker.close.last_24h)?,
+            decimal(&ticker.volume.today)?,
+            decimal(&ticker.volume.last_24h)?,
+            decimal(&ticker.volume_weighted_avg_price.today)?,
+            decimal(&ticker.volume_weighted_avg_price.last_24h)?,
+            Cell::U32(Some(ticker.trade_count.today)),
+            Cell::U32(Some(ticker.trade_count.last_24h)),
+            decimal(&ticker.low_price.today)?,
+            decimal(&ticker.low_price.last_24h)?,
+            decimal(&ticker.high_price.today)?,
+            decimal(&ticker.high_price.last_24h)?,
+            decimal(&ticker.open_price.today)?,
+            decimal(&ticker.open_price.last_24h)?,
+        ];
+        self.push(Table::Tickers, &ticker.pair, &[(received, row)])
+    }
+
+    fn write_book(&mut self, book: &Book, received: Timestamp) -> Result<()> {
+        let sides = [("ask", &book.asks), ("bid", &book.bids)];
+        let mut rows = vec![];
+        for (side, levels) in sides.iter() {
+            for level in levels.iter() {
+                let row = [
+                    Cell::Time(received),
+                    Cell::Str(&book.pair),
+                    Cell::U32(Some(book.depth.levels())),
+                    Cell::Bool(book.snapshot),
+                    Cell::Str(side),
+                    decimal(&level.price)?,
+                    decimal(&level.volume)?,
+                    Cell::Time(level.time),
+                    Cell::Bool(level.republish),
+                    Cell::U32(book.checksum),
+                ];
+                rows.push((received, row));
+            }
+        }
+        self.push(Table::Book, &book.pair, &rows)
+    }
+
+    /// Add rows, each with the time it is dated by, then write
+    /// out the partitions which are full. The rows are all
+    /// converted first so a message is kept whole or not at all.
+    fn push<'a, R>(&mut self, table: Table, pair: &str, rows: &[(Timestamp, R)]) -> Result<()>
+    where
+        R: AsRef<[Cell<'a>]>,
+    {
+        let batch_rows = self.batch_rows;
+        let mut full = vec![];
+        for (time, row) in rows {
+            let partition = Partition {
+                table,
+                date: time.utc_date(),
+                pair: pair.replace('/', "-"),
+            };
+            let batch = self
+                .batches
+                .entry(partition.clone())
+                .or_insert_with(|| Batch::new(table.schema(), batch_rows));
+            batch.push(row.as_ref());
+            if batch.rows >= batch_rows && !full.contains(&partition) {
+                full.push(partition);
+            }
+        }
+        for partition in full {
+            self.write_file(&partition)?;
+        }
+        Ok(())
+    }
+
+    /// Write a partition's rows to a new file, they are only
+    /// dropped once written.
+    fn write_file(&mut self, partition: &Partition) -> Result<()> {
+        let dir = self
+            .dir
+            .join(partition.table.name())
+            .join(format!("date={}", partition.date))
+            .join(format!("pair={}", partition.pair));
+        fs::create_dir_all(&dir)?;
+        let name = format!(
+            "part-{}-{}.parquet",
+            Timestamp::now().as_micros(),
+            self.files_written
+        );
+        self.files_written += 1;
+        let batch = &self.batches[partition];
+        let columns = batch.builders.iter().map(|b| b.finish_cloned()).collect();
+        let records = RecordBatch::try_new(batch.schema.clone(), columns)?;
+        let path = dir.join(name);
+        if let Err(e) = write_records(&path, &records) {
+            // Don't leave half a file to be read.
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        self.batches.remove(partition);
+        Ok(())
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        match resp {
+            Resp::Trade(trades) => self.write_trades(trades)?,
+            Resp::Ohlc(ohlc) => {
+                if let Some(closed) = self.candles.update(ohlc.clone()) {
+                    self.write_candle(closed)?;
+                }
+            }
+            Resp::Ticker(ticker) => self.write_ticker(ticker, received)?,
+            Resp::Book(book) => self.write_book(book, received)?,
+            _ => {}
+        }
+        for closed in self.candles.close_expired(received - CANDLE_GRACE) {
+            self.write_candle(closed)?;
+        }
+        Ok(())
+    }
+
+    /// Write every partition with buffered rows to a new file.
+    fn flush(&mut self) -> Result<()> {
+        let partitions: Vec<_> = self.batches.keys().cloned().collect();
+        for partition in partitions {
+            self.write_file(&partition)?;
+        }
+        Ok(())
+    }
+}
+
+impl Table {
+    fn name(&self) -> &'static str {
+        match self {
+            Table::Trades => "trades",
+            Table::Candles => "candles",
+            Table::Tickers => "tickers",
+            Table::Book => "book",
+        }
+    }
+
+    fn schema(&self) -> SchemaRef {
+        use DataType::{Boolean, Utf8};
+        let time = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+        let decimal = DataType::Decimal128(PRECISION, SCALE as i8);
+        let count = DataType::UInt32;
+        let columns: Vec<(&str, DataType)> = match self {
+            Table::Trades => vec![
+                ("pair", Utf8),
+                ("time", time),
+                ("price", decimal.clone()),
+                ("volume", decimal),
+                ("side", Utf8),
+                ("order_type", Utf8),
+                ("misc", Utf8),
+            ],
+            Table::Candles => {
+                let mut columns = vec![
+                    ("pair", Utf8),
+                    ("interval", count.clone()),
+                    ("start", time.clone()),
+                    ("end", time),
+                ];
+                for name in ["open", "high", "low", "close", "vwap", "volume"].iter() {
+                    columns.push((name, decimal.clone()));
+                }
+                columns.push(("count", count));
+                columns
+            }
+            Table::Tickers => {
+                let mut columns = vec![("time", time), ("pair", Utf8)];
+                for name in [
+                    "ask",
+                    "ask_volume",
+                    "bid",
+                    "bid_volume",
+                    "last",
+                    "last_volume",
+                    "volume_today",
+                    "volume_24h",
+                    "vwap_today",
+                    "vwap_24h",
+                ]
+                .iter()
+                {
+                    columns.push((name, decimal.clone()));
+                }
+                columns.push(("trades_today", count.clone()));
+                columns.push(("trades_24h", count));
+                for name in [
+                    "low_today",
+                    "low_24h",
+                    "high_today",
+                    "high_24h",
+                    "open_today",
+                    "open_24h",
+                ]
+                .iter()
+                {
+                    columns.push((name, decimal.clone()));
+                }
+                columns
+            }
+            Table::Book => vec![
+                ("received", time.clone()),
+                ("pair", Utf8),
+                ("depth", count.clone()),
+                ("snapshot", Boolean),
+                ("side", Utf8),
+                ("price", decimal.clone()),
+                ("volume", decimal),
+                ("time", time),
+                ("republish", Boolean),
+                ("checksum", count),
+            ],
+        };
+        let fields: Vec<Field> = columns
+            .into_iter()
+            .map(|(name, data_type)| Field::new(name, data_type, name == "checksum"))
+            .collect();
+        Arc::new(Schema::new(fields))
+    }
+}
+
+/// One value in a row, rows are converted in full before any
+/// is added to a batch so a bad value can't leave the columns
+/// with different lengths.
+enum Cell<'a> {
+    Str(&'a str),
+    Decimal(i128),
+    Time(Timestamp),
+    U32(Option<u32>),
+    Bool(bool),
+}
+
+fn write_records(path: &Path, records: &RecordBatch) -> Result<()> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let file = File::options().write(true).create_new(true).open(path)?;
+    let mut writer = ArrowWriter::try_new(file, records.schema(), Some(properties))?;
+    writer.write(records)?;
+    writer.close()?;
+    Ok(())
+}
+
+fn decimal(value: &str) -> Result<Cell<'static>> {
+    let mut decimal =
+        Decimal::from_str(value).map_err(|e| anyhow!("Invalid decimal {}: {}", value, e))?;
+    if decimal.scale() > SCALE {
+        return Err(anyhow!("{} has more than {} decimal places", value, SCALE));
+    }
+    decimal.rescale(SCALE);
+    Ok(Cell::Decimal(decimal.mantissa()))
+}
+
+/// Rows of one partition being built up as columns.
+struct Batch {
+    schema: SchemaRef,
+    builders: Vec<Box<dyn ArrayBuilder>>,
+    rows: usize,
+}
+
+impl Batch {
+    fn new(schema: SchemaRef, capacity: usize) -> Batch {
+        let builders = schema
+            .fields()
+            .iter()
+            .map(|field| make_builder(field.data_type(), capacity.min(1024)))
+            .collect();
+        Batch {
+            schema,
+            builders,
+            rows: 0,
+        }
+    }
+
+    // The cells are in the order of the schema's columns.
+    fn push(&mut self, row: &[Cell]) {
+        for (builder, cell) in self.builders.iter_mut().zip(row) {
+            let builder = builder.as_any_mut();
+            match cell {
+                Cell::Str(s) => downcast::<StringBuilder>(builder).append_value(s),
+                Cell::Decimal(d) => downcast::<Decimal128Builder>(builder).append_value(*d),
+                Cell::Time(t) => downcast::<TimestampMicrosecondBuilder>(builder)
+                    .append_value(t.as_micros() as i64),
+                Cell::U32(n) => downcast::<UInt32Builder>(builder).append_option(*n),
+                Cell::Bool(b) => downcast::<BooleanBuilder>(builder).append_value(*b),
+            }
+        }
+        self.rows += 1;
+    }
+}
+
+fn downcast<T: 'static>(builder: &mut dyn std::any::Any) -> &mut T {
+    builder.downcast_mut().expect("cell types match the schema")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, TimestampMicrosecondType};
+
+    const TRADES: &str = r#"[0,[["5541.20000","0.15850568","1534614057.321597","s","l",""],["6060.00000","0.02455000","1534614057.324998","b","m",""]],"trade","XBT/USD"]"#;
+    const BOOK: &str = r#"[1234,{"a":[["5541.30000","2.50700000","1534614248.456738"]]},{"b":[["5540.10000","1.00000000","1534614335.345903","r"]],"c":"974942666"},"book-10","XBT/USD"]"#;
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files = vec![];
+        let mut dirs = vec![dir.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                match path.is_dir() {
+                    true => dirs.push(path),
+                    false => files.push(path),
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn read(path: &Path) -> Result<RecordBatch> {
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+        Ok(reader.next().unwrap()?)
+    }
+
+    #[test]
+    fn trades_are_partitioned_with_exact_decimals() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sink = ParquetSink::new(dir.path())?;
+        let received = Timestamp::from_secs(1534614058);
+        sink.write(&serde_json::from_str(TRADES)?, received)?;
+        assert!(files(dir.path()).is_empty());
+        sink.flush()?;
+
+        let files = files(dir.path());
+        assert_eq!(1, files.len());
+        let relative = files[0].strip_prefix(dir.path())?;
+        assert!(relative.starts_with("trades/date=2018-08-18/pair=XBT-USD"));
+
+        let batch = read(&files[0])?;
+        assert_eq!(2, batch.num_rows());
+        let prices = batch
+            .column_by_name("price")
+            .unwrap()
+            .as_primitive::<Decimal128Type>();
+        assert_eq!("5541.2000000000", prices.value_as_string(0));
+        let volumes = batch
+            .column_by_name("volume")
+            .unwrap()
+            .as_primitive::<Decimal128Type>();
+        assert_eq!("0.0245500000", volumes.value_as_string(1));
+        let times = batch
+            .column_by_name("time")
+            .unwrap()
+            .as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(1534614057321597, times.value(0));
+        assert_eq!(
+            "market",
+            batch
+                .column_by_name("order_type")
+                .unwrap()
+                .as_string::<i32>()
+                .value(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn book_levels_written_per_batch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sink = ParquetSink::new(dir.path())?.batch_rows(2);
+        let received = Timestamp::from_secs(1534614336);
+        sink.write(&serde_json::from_str(BOOK)?, received)?;
+        // A full batch is written without flushing.
+        let files = files(dir.path());
+        assert_eq!(1, files.len());
+        let batch = read(&files[0])?;
+        assert_eq!(2, batch.num_rows());
+        let sides = batch.column_by_name("side").unwrap().as_string::<i32>();
+        assert_eq!(("ask", "bid"), (sides.value(0), sides.value(1)));
+        let checksums = batch
+            .column_by_name("checksum")
+            .unwrap()
+            .as_primitive::<arrow_array::types::UInt32Type>();
+        assert_eq!(974942666, checksums.value(1));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_book_is_not_half_written() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sink = ParquetSink::new(dir.path())?;
+        let received = Timestamp::from_secs(1534614336);
+        let bad = BOOK.replace("1.00000000", "1.00000000001");
+        assert!(sink.write(&serde_json::from_str(&bad)?, received).is_err());
+        sink.flush()?;
+        assert!(files(dir.path()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn rows_kept_when_write_fails() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut sink = ParquetSink::new(dir.path())?;
+        // The table's directory can't be created.
+        File::create(dir.path().join("trades"))?;
+        sink.write(
+            &serde_json::from_str(TRADES)?,
+            Timestamp::from_secs(1534614058),
+        )?;
+        assert!(sink.flush().is_err());
+        fs::remove_file(dir.path().join("trades"))?;
+        sink.flush()?;
+        let files = files(dir.path());
+        assert_eq!(1, files.len());
+        assert_eq!(2, read(&files[0])?.num_rows());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_inexact_decimals() {
+        assert!(decimal("0.12345678901").is_err());
+        assert!(decimal("abc").is_err());
+        assert!(matches!(decimal("1.5"), Ok(Cell::Decimal(15000000000))));
+    }
+}
@@ -0,0 +1,596 @@
+use crate::req::OhlcInterval;
+use crate::resp::ohlc::Ohlc;
+use crate::resp::private::{
+    OpenOrders, OrderDescription, OrderSide, OrderStatus, OrderUpdate, OtherFields, OwnTrade,
+    OwnTrades,
+};
+use crate::resp::Resp;
+use crate::sink::Sink;
+use crate::time::Timestamp;
+use anyhow::{anyhow, Result};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, Row};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS candles (
+        pair TEXT NOT NULL,
+        interval INTEGER NOT NULL,
+        time INTEGER NOT NULL,
+        updated INTEGER NOT NULL,
+        etime INTEGER NOT NULL,
+        open TEXT NOT NULL,
+        high TEXT NOT NULL,
+        low TEXT NOT NULL,
+        close TEXT NOT NULL,
+        vwap TEXT NOT NULL,
+        volume TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (pair, interval, time)
+    );
+    CREATE TABLE IF NOT EXISTS own_trades (
+        trade_id TEXT PRIMARY KEY,
+        order_id TEXT NOT NULL,
+        position_id TEXT,
+        pair TEXT NOT NULL,
+        time INTEGER NOT NULL,
+        side TEXT NOT NULL,
+        order_type TEXT NOT NULL,
+        price TEXT NOT NULL,
+        cost TEXT NOT NULL,
+        fee TEXT NOT NULL,
+        volume TEXT NOT NULL,
+        margin TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS own_trades_time ON own_trades (time);
+    CREATE TABLE IF NOT EXISTS order_updates (
+        id INTEGER PRIMARY KEY,
+        received INTEGER NOT NULL,
+        order_id TEXT NOT NULL,
+        status TEXT,
+        user_ref INTEGER,
+        open_time INTEGER,
+        volume TEXT,
+        volume_exec TEXT,
+        cost TEXT,
+        fee TEXT,
+        avg_price TEXT,
+        cancel_reason TEXT,
+        descr_pair TEXT,
+        descr_side TEXT,
+        descr_order_type TEXT,
+        descr_price TEXT,
+        descr_price2 TEXT,
+        descr_leverage TEXT,
+        descr_order TEXT,
+        descr_close TEXT
+    );
+    CREATE INDEX IF NOT EXISTS order_updates_received ON order_updates (received);
+";
+
+/// Stores candles, the account's trades and updates to its
+/// orders in a SQLite database, for keeping data locally
+/// without running a database server.
+///
+/// Candles are keyed by pair, interval and start time and each
+/// update to the candle in progress replaces the last, so the
+/// table always holds the latest value of each candle. Own
+/// trades are keyed by trade id, so the trades repeated when
+/// resubscribing are only stored once, and every order update
+/// is kept in the order received.
+///
+/// Prices and volumes are stored as text to keep them exact
+/// and times as microseconds since the epoch. Writes are made
+/// in a transaction which is committed on flush, or sooner once
+/// it has 1,000 rows or has been open a second by default, so
+/// readers see recent data and a crash loses little.
+pub struct SqliteSink {
+    conn: Connection,
+    commit_rows: usize,
+    commit_interval: Duration,
+    // When the open transaction began and the rows written in it.
+    transaction: Option<(Instant, usize)>,
+}
+
+impl SqliteSink {
+    /// Open the database at the given path, creating it and the
+    /// tables if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteSink> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        SqliteSink::with_connection(conn)
+    }
+
+    /// A database held in memory, lost when the sink is dropped.
+    pub fn in_memory() -> Result<SqliteSink> {
+        SqliteSink::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<SqliteSink> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteSink {
+            conn,
+            commit_rows: 1000,
+            commit_interval: Duration::from_secs(1),
+            transaction: None,
+        })
+    }
+
+    /// Rows written before the transaction is committed.
+    pub fn commit_rows(mut self, rows: usize) -> SqliteSink {
+        self.commit_rows = rows.max(1);
+        self
+    }
+
+    /// The longest a transaction is kept open, checked as rows
+    /// are written.
+    pub fn commit_interval(mut self, interval: Duration) -> SqliteSink {
+        self.commit_interval = interval;
+        self
+    }
+
+    /// The candles for a pair and interval starting in the range
+    /// [from, to), in time order. The channel ids aren't stored
+    /// so are zero.
+    pub fn candles(
+        &self,
+        pair: &str,
+        interval: OhlcInterval,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<Vec<Ohlc>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT updated, etime, open, high, low, close, vwap, volume, count FROM candles
+             WHERE pair = ?1 AND interval = ?2 AND time >= ?3 AND time < ?4 ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![pair, interval.minutes(), from, to], |row| {
+            Ok(Ohlc {
+                channel_id: 0,
+                interval,
+                pair: pair.to_owned(),
+                time: row.get(0)?,
+                etime: row.get(1)?,
+                open: row.get(2)?,
+                high: row.get(3)?,
+                low: row.get(4)?,
+                close: row.get(5)?,
+                vwap: row.get(6)?,
+                volume: row.get(7)?,
+                count: row.get(8)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The account's trades made in the range [from, to), in
+    /// time order.
+    pub fn own_trades(&self, from: Timestamp, to: Timestamp) -> Result<Vec<OwnTrade>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT trade_id, order_id, position_id, pair, time, side, order_type, price, cost,
+             fee, volume, margin FROM own_trades WHERE time >= ?1 AND time < ?2 ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![from, to], |row| {
+            Ok(OwnTrade {
+                trade_id: row.get(0)?,
+                order_id: row.get(1)?,
+                position_id: row.get(2)?,
+                pair: row.get(3)?,
+                time: row.get(4)?,
+                side: row.get(5)?,
+                order_type: row.get(6)?,
+                price: row.get(7)?,
+                cost: row.get(8)?,
+                fee: row.get(9)?,
+                volume: row.get(10)?,
+                margin: row.get(11)?,
+                // Only the fields with a column are stored.
+                other: OtherFields::default(),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The order updates received in the range [from, to) with
+    /// when each was received, in the order received.
+    pub fn order_updates(
+        &self,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<Vec<(Timestamp, OrderUpdate)>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT received, order_id, status, user_ref, open_time, volume, volume_exec, cost,
+             fee, avg_price, cancel_reason, descr_order, descr_order_type, descr_pair,
+             descr_price, descr_price2, descr_side, descr_leverage, descr_close
+             FROM order_updates WHERE received >= ?1 AND received < ?2 ORDER BY id",
+        )?;
+        let rows = statement.query_map(params![from, to], |row| {
+            let update = OrderUpdate {
+                order_id: row.get(1)?,
+                status: row.get(2)?,
+                user_ref: row.get(3)?,
+                description: description(row)?,
+                open_time: row.get(4)?,
+                volume: row.get(5)?,
+                volume_exec: row.get(6)?,
+                cost: row.get(7)?,
+                fee: row.get(8)?,
+                avg_price: row.get(9)?,
+                cancel_reason: row.get(10)?,
+                other: OtherFields::default(),
+            };
+            Ok((row.get(0)?, update))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        if self.transaction.is_none() {
+            self.conn.execute_batch("BEGIN")?;
+            self.transaction = Some((Instant::now(), 0));
+        }
+        Ok(())
+    }
+
+    /// Count rows written in the transaction, committing it if
+    /// it is big or old enough.
+    fn written(&mut self, rows: usize) -> Result<()> {
+        if let Some((began, written)) = &mut self.transaction {
+            *written += rows;
+            if *written >= self.commit_rows || began.elapsed() >= self.commit_interval {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_candle(&mut self, ohlc: &Ohlc) -> Result<()> {
+        // Older updates arriving late don't replace newer ones.
+        let mut statement = self.conn.prepare_cached(
+            "INSERT INTO candles
+             (pair, interval, time, updated, etime, open, high, low, close, vwap, volume, count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (pair, interval, time) DO UPDATE SET
+             updated = excluded.updated, etime = excluded.etime, open = excluded.open,
+             high = excluded.high, low = excluded.low, close = excluded.close,
+             vwap = excluded.vwap, volume = excluded.volume, count = excluded.count
+             WHERE excluded.updated >= candles.updated",
+        )?;
+        statement.execute(params![
+            ohlc.pair,
+            ohlc.interval.minutes(),
+            ohlc.start(),
+            ohlc.time,
+            ohlc.etime,
+            ohlc.open,
+            ohlc.high,
+            ohlc.low,
+            ohlc.close,
+            ohlc.vwap,
+            ohlc.volume,
+            ohlc.count,
+        ])?;
+        Ok(())
+    }
+
+    fn write_own_trades(&mut self, trades: &OwnTrades) -> Result<()> {
+        let mut statement = self.conn.prepare_cached(
+            "INSERT OR IGNORE INTO own_trades
+             (trade_id, order_id, position_id, pair, time, side, order_type, price, cost, fee,
+             volume, margin) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        )?;
+        for trade in &trades.trades {
+            statement.execute(params![
+                trade.trade_id,
+                trade.order_id,
+                trade.position_id,
+                trade.pair,
+                trade.time,
+                trade.side,
+                trade.order_type,
+                trade.price,
+                trade.cost,
+                trade.fee,
+                trade.volume,
+                trade.margin,
+            ])?;
+        }
+        Ok(())
+    }
+
+    fn write_order_updates(&mut self, orders: &OpenOrders, received: Timestamp) -> Result<()> {
+        let mut statement = self.conn.prepare_cached(
+            "INSERT INTO order_updates
+             (received, order_id, status, user_ref, open_time, volume, volume_exec, cost, fee,
+             avg_price, cancel_reason, descr_order, descr_order_type, descr_pair, descr_price,
+             descr_price2, descr_side, descr_leverage, descr_close) VALUES
+             (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+        )?;
+        for order in &orders.orders {
+            let descr = order.description.as_ref();
+            statement.execute(params![
+                received,
+                order.order_id,
+                order.status,
+                order.user_ref,
+                order.open_time,
+                order.volume,
+                order.volume_exec,
+                order.cost,
+                order.fee,
+                order.avg_price,
+                order.cancel_reason,
+                descr.map(|d| &d.order),
+                descr.map(|d| &d.order_type),
+                descr.map(|d| &d.pair),
+                descr.map(|d| &d.price),
+                descr.and_then(|d| d.price2.as_ref()),
+                descr.map(|d| d.side),
+                descr.and_then(|d| d.leverage.as_ref()),
+                descr.and_then(|d| d.close.as_ref()),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write(&mut self, resp: &Resp, received: Timestamp) -> Result<()> {
+        match resp {
+            Resp::Ohlc(ohlc) => {
+                self.begin()?;
+                self.write_candle(ohlc)?;
+                self.written(1)
+            }
+            Resp::OwnTrades(trades) => {
+                self.begin()?;
+                self.write_own_trades(trades)?;
+                self.written(trades.trades.len())
+            }
+            Resp::OpenOrders(orders) => {
+                self.begin()?;
+                self.write_order_updates(orders, received)?;
+                self.written(orders.orders.len())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Commit the writes since the last commit.
+    fn flush(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            self.conn.execute_batch("COMMIT")?;
+            self.transaction = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// Columns 11 to 18 of the order update query.
+fn description(row: &Row) -> rusqlite::Result<Option<OrderDescription>> {
+    let order: Option<String> = row.get(11)?;
+    Ok(match order {
+        Some(order) => Some(OrderDescription {
+            order,
+            order_type: row.get(12)?,
+            pair: row.get(13)?,
+            price: row.get(14)?,
+            price2: row.get(15)?,
+            side: row.get(16)?,
+            leverage: row.get(17)?,
+            close: row.get(18)?,
+        }),
+        None => None,
+    })
+}
+
+impl ToSql for Timestamp {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let micros = i64::try_from(self.as_micros())
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(ToSqlOutput::from(micros))
+    }
+}
+
+impl FromSql for Timestamp {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let micros = u64::try_from(value.as_i64()?).map_err(|_| FromSqlError::InvalidType)?;
+        Ok(Timestamp::from_micros(micros))
+    }
+}
+
+impl ToSql for OrderSide {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            OrderSide::Buy => "buy",
+            OrderSide::Sell => "sell",
+        }))
+    }
+}
+
+impl FromSql for OrderSide {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "buy" => Ok(OrderSide::Buy),
+            "sell" => Ok(OrderSide::Sell),
+            side => Err(FromSqlError::Other(
+                anyhow!("Invalid order side {}", side).into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for OrderStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Open => "open",
+            OrderStatus::Closed => "closed",
+            OrderStatus::Canceled => "canceled",
+            OrderStatus::Expired => "expired",
+        }))
+    }
+}
+
+impl FromSql for OrderStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "pending" => Ok(OrderStatus::Pending),
+            "open" => Ok(OrderStatus::Open),
+            "closed" => Ok(OrderStatus::Closed),
+            "canceled" => Ok(OrderStatus::Canceled),
+            "expired" => Ok(OrderStatus::Expired),
+            status => Err(FromSqlError::Other(
+                anyhow!("Invalid order status {}", status).into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OWN_TRADES: &str = r#"[[{"TDLH43-DVQXD-2KHVYY":{"cost":"1000000.00000","fee":"1600.00000","margin":"0.00000","ordertxid":"TDLH43-DVQXD-2KHVYY","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070651","type":"sell","vol":"1000000000.00000000"}}],"ownTrades",{"sequence":2948}]"#;
+    const OPEN_ORDERS: &str = r#"[[{"OGTT3Y-C6I3P-XRI6HX":{"cost":"0.00000","descr":{"close":"","leverage":"0:1","order":"sell 10.00345345 XBT/EUR @ limit 34.50000","ordertype":"limit","pair":"XBT/EUR","price":"34.50000","price2":"0.00000","type":"sell"},"opentm":"1560516023.070651","status":"open","userref":0,"vol":"10.00345345","vol_exec":"0.00000000"}},{"OGTT3Y-C6I3P-XRI6HX":{"status":"canceled","cancel_reason":"User requested"}}],"openOrders",{"sequence":234}]"#;
+
+    fn ohlc(time: u64, etime: u64, close: &str) -> Ohlc {
+        Ohlc {
+            channel_id: 0,
+            interval: OhlcInterval::Mins1,
+            pair: "XBT/USD".to_string(),
+            time: Timestamp::from_secs(time),
+            etime: Timestamp::from_secs(etime),
+            open: "100.0".to_string(),
+            high: "110.0".to_string(),
+            low: "90.0".to_string(),
+            close: close.to_string(),
+            vwap: "100.0".to_string(),
+            volume: "1.0".to_string(),
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn candles_are_upserted() -> Result<()> {
+        let mut sink = SqliteSink::in_memory()?;
+        let received = Timestamp::from_secs(1542067300);
+        for candle in [
+            ohlc(1542057310, 1542057360, "101.0"),
+            ohlc(1542057320, 1542057360, "102.0"),
+            // Late and older than the update before it.
+            ohlc(1542057315, 1542057360, "99.0"),
+            ohlc(1542057370, 1542057420, "103.0"),
+            ohlc(1542057430, 1542057480, "104.0"),
+        ]
+        .iter()
+        {
+            sink.write(&Resp::Ohlc(candle.clone()), received)?;
+        }
+        sink.flush()?;
+
+        let candles = sink.candles(
+            "XBT/USD",
+            OhlcInterval::Mins1,
+            Timestamp::from_secs(1542057300),
+            Timestamp::from_secs(1542057420),
+        )?;
+        assert_eq!(
+            vec![
+                ohlc(1542057320, 1542057360, "102.0"),
+                ohlc(1542057370, 1542057420, "103.0")
+            ],
+            candles
+        );
+        assert!(sink
+            .candles(
+                "XBT/USD",
+                OhlcInterval::Mins5,
+                Timestamp::from_secs(0),
+                received
+            )?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn own_trades_stored_once() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kraken.db");
+        let trades: Resp = serde_json::from_str(OWN_TRADES)?;
+        let mut sink = SqliteSink::open(&path)?;
+        sink.write(&trades, Timestamp::now())?;
+        drop(sink);
+        // Resubscribing repeats the trades, reopened to check
+        // they were committed on drop.
+        let mut sink = SqliteSink::open(&path)?;
+        sink.write(&trades, Timestamp::now())?;
+
+        let stored = sink.own_trades(
+            Timestamp::from_secs(1560516023),
+            Timestamp::from_secs(1560516024),
+        )?;
+        match trades {
+            Resp::OwnTrades(trades) => assert_eq!(trades.trades, stored),
+            _ => unreachable!(),
+        }
+        assert!(sink
+            .own_trades(Timestamp::from_secs(0), Timestamp::from_secs(1560516023))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn commits_without_flush() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kraken.db");
+        let mut sink = SqliteSink::open(&path)?.commit_rows(2);
+        let reader = SqliteSink::open(&path)?;
+        let (from, to) = (Timestamp::from_secs(0), Timestamp::from_secs(1542057480));
+        let received = Timestamp::from_secs(1542067300);
+        sink.write(&Resp::Ohlc(ohlc(1542057310, 1542057360, "101.0")), received)?;
+        assert!(reader
+            .candles("XBT/USD", OhlcInterval::Mins1, from, to)?
+            .is_empty());
+        sink.write(&Resp::Ohlc(ohlc(1542057370, 1542057420, "103.0")), received)?;
+        assert_eq!(
+            2,
+            reader
+                .candles("XBT/USD", OhlcInterval::Mins1, from, to)?
+                .len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_out_of_range() {
+        let candle = Resp::Ohlc(ohlc(u64::MAX / 1_000_000, 1542057360, "101.0"));
+        let mut sink = SqliteSink::in_memory().unwrap();
+        assert!(sink.write(&candle, Timestamp::now()).is_err());
+    }
+
+    #[test]
+    fn order_updates_kept_in_order() -> Result<()> {
+        let mut sink = SqliteSink::in_memory()?;
+        let orders: Resp = serde_json::from_str(OPEN_ORDERS)?;
+        let received = Timestamp::from_secs(1560516024);
+        sink.write(&orders, received)?;
+
+        let stored = sink.order_updates(received, received + std::time::Duration::from_secs(1))?;
+        match orders {
+            Resp::OpenOrders(orders) => assert_eq!(
+                orders.orders,
+                stored.into_iter().map(|(_, o)| o).collect::<Vec<_>>()
+            ),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
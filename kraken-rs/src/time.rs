@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Error, Result};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MICROS_PER_SEC: u64 = 1_000_000;
+
+/// A point in time as sent by Kraken, seconds since the epoch
+/// with microsecond precision e.g. "1542057314.748456". This
+/// is displayed and serialized in the same decimal form.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Timestamp {
+    micros: u64,
+}
+
+impl Timestamp {
+    pub fn from_micros(micros: u64) -> Timestamp {
+        Timestamp { micros }
+    }
+
+    /// Saturates at the latest time a timestamp can hold.
+    pub fn from_secs(secs: u64) -> Timestamp {
+        Timestamp::from_micros(secs.saturating_mul(MICROS_PER_SEC))
+    }
+
+    pub fn now() -> Timestamp {
+        SystemTime::now().into()
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    /// Whole seconds since the epoch, truncating any fraction.
+    pub fn as_secs(&self) -> u64 {
+        self.micros / MICROS_PER_SEC
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(self.micros)
+    }
+
+    /// The time elapsed since the earlier timestamp, zero if
+    /// the earlier timestamp is actually later.
+    pub fn saturating_duration_since(&self, earlier: Timestamp) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+
+    /// The UTC date e.g. "2018-11-12".
+    pub fn utc_date(&self) -> String {
+        // Howard Hinnant's days to civil date algorithm.
+        let days = (self.as_secs() / 86400) as i64 + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    /// Round down to a whole multiple of the given duration
+    /// since the epoch, e.g. the start of the enclosing minute.
+    pub fn truncate(&self, to: Duration) -> Timestamp {
+        let step = to.as_micros() as u64;
+        match step {
+            0 => *self,
+            _ => Timestamp::from_micros(self.micros - self.micros % step),
+        }
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(t: SystemTime) -> Timestamp {
+        Timestamp::from_micros(
+            t.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0),
+        )
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    /// Saturates at the latest time a timestamp can hold.
+    fn add(self, rhs: Duration) -> Timestamp {
+        let micros = u64::try_from(rhs.as_micros()).unwrap_or(u64::MAX);
+        Timestamp::from_micros(self.micros.saturating_add(micros))
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: Duration) -> Timestamp {
+        Timestamp::from_micros(self.micros.saturating_sub(rhs.as_micros() as u64))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid timestamp {}", s);
+        let (secs, fraction) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        if secs.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let secs = secs.parse::<u64>().map_err(|_| invalid())?;
+        // Anything past microseconds is dropped, shorter
+        // fractions are padded out with zeros.
+        let micros = fraction
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(6)
+            .fold(0, |acc, c| acc * 10 + c.to_digit(10).unwrap() as u64);
+        secs.checked_mul(MICROS_PER_SEC)
+            .and_then(|secs| secs.checked_add(micros))
+            .map(Timestamp::from_micros)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:06}",
+            self.micros / MICROS_PER_SEC,
+            self.micros % MICROS_PER_SEC
+        )
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Timestamp {
+    fn schema_name() -> String {
+        "Timestamp".to_owned()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, SchemaObject, StringValidation};
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(r"^[0-9]+\.[0-9]{6}$".to_owned()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a decimal string of seconds since the epoch")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Timestamp, E>
+            where
+                E: de::Error,
+            {
+                v.parse().map_err(de::Error::custom)
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Timestamp, E>
+            where
+                E: de::Error,
+            {
+                Ok(Timestamp::from_secs(v))
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() -> Result<()> {
+        let t = "1542057314.748456".parse::<Timestamp>()?;
+        assert_eq!(1542057314748456, t.as_micros());
+        assert_eq!(1542057314, t.as_secs());
+        assert_eq!("1542057314.748456", t.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pads_and_truncates_fraction() -> Result<()> {
+        assert_eq!(
+            Timestamp::from_micros(1542057314500000),
+            "1542057314.5".parse()?
+        );
+        assert_eq!(
+            Timestamp::from_micros(1542057314748456),
+            "1542057314.7484569".parse()?
+        );
+        assert_eq!(Timestamp::from_secs(1542057314), "1542057314".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        assert!("".parse::<Timestamp>().is_err());
+        assert!(".5".parse::<Timestamp>().is_err());
+        assert!("15420a7314.748456".parse::<Timestamp>().is_err());
+        assert!("1542057314.74x456".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn out_of_range() {
+        for s in ["18446744073710.5", "18446744073709.551616"] {
+            let error = s.parse::<Timestamp>().unwrap_err();
+            assert_eq!(format!("Invalid timestamp {}", s), error.to_string());
+        }
+        assert_eq!(
+            Timestamp::from_micros(18446744073709551615),
+            "18446744073709.551615".parse().unwrap()
+        );
+        let latest = Timestamp::from_micros(u64::MAX);
+        assert_eq!(latest, Timestamp::from_secs(u64::MAX));
+        assert_eq!(latest, latest + Duration::from_secs(1));
+        assert_eq!(latest, Timestamp::from_secs(1) + Duration::MAX);
+    }
+
+    #[test]
+    fn truncate() {
+        let t = Timestamp::from_micros(1542057314748456);
+        assert_eq!(
+            Timestamp::from_secs(1542057300),
+            t.truncate(Duration::from_secs(60))
+        );
+        assert_eq!(
+            Timestamp::from_secs(1542056400),
+            t.truncate(Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn utc_date() {
+        assert_eq!("1970-01-01", Timestamp::from_secs(0).utc_date());
+        assert_eq!(
+            "2018-11-12",
+            Timestamp::from_micros(1542057314748456).utc_date()
+        );
+        assert_eq!(
+            "2020-02-29",
+            Timestamp::from_secs(1582934400 + 86399).utc_date()
+        );
+        assert_eq!("2020-03-01", Timestamp::from_secs(1583020800).utc_date());
+    }
+
+    #[test]
+    fn serde_round_trip() -> Result<()> {
+        let t = Timestamp::from_micros(1542057314748456);
+        assert_eq!(r#""1542057314.748456""#, serde_json::to_string(&t)?);
+        assert_eq!(t, serde_json::from_str(r#""1542057314.748456""#)?);
+        Ok(())
+    }
+}